@@ -0,0 +1,392 @@
+//! Compares the message schemas of a FDP system between two revisions of its manifest
+
+use crate::info::SystemDefinitionInfo;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// The schema keywords that only document a schema, and can change without breaking anything.
+/// A default value, e.g. of a `#[serde(default)]` field, is reported by the field becoming optional.
+const ANNOTATIONS: [&str; 5] = ["description", "title", "examples", "default", "$schema"];
+
+/// A snapshot of the declared messages of a FDP system, stored in a lock file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SchemaSnapshot {
+    /// The declared messages, keyed by `<app>::<identifier>`
+    pub messages: BTreeMap<String, MessageSnapshot>,
+}
+
+/// The snapshot of a declared message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageSnapshot {
+    pub topic: String,
+    pub version: u32,
+    /// The JSON schema of the message
+    pub schema: Value,
+}
+
+/// Whether a change keeps the existing publishers and listeners of a message working
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compatibility {
+    /// e.g. an optional field was added
+    Compatible,
+    /// e.g. a field was removed, its type changed or the topic was renamed
+    Breaking,
+}
+
+/// A change of a message between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaChange {
+    /// The changed message, as `<app>::<identifier>`
+    pub message: String,
+    pub compatibility: Compatibility,
+    pub description: String,
+    /// Whether the version of the message was bumped, which allows breaking changes
+    pub version_bumped: bool,
+}
+
+impl SchemaChange {
+    /// Whether the change can be released: it is compatible, or the message version was bumped
+    pub fn is_allowed(&self) -> bool {
+        self.compatibility == Compatibility::Compatible || self.version_bumped
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let compatibility = match (self.compatibility, self.version_bumped) {
+            (Compatibility::Compatible, _) => "compatible",
+            (Compatibility::Breaking, true) => "breaking, version bumped",
+            (Compatibility::Breaking, false) => "breaking",
+        };
+        write!(
+            f,
+            "{}: {} ({})",
+            self.message, self.description, compatibility
+        )
+    }
+}
+
+impl SchemaSnapshot {
+    /// Snapshots the declared messages of a FDP system
+    pub fn from(system: &SystemDefinitionInfo) -> Self {
+        let mut messages = BTreeMap::new();
+        for (app_name, app_info) in &system.apps {
+            let declarations = app_info
+                .broadcasted_events
+                .iter()
+                .chain(&app_info.incoming_requests)
+                .chain(&app_info.outgoing_responses);
+            for info in declarations {
+                messages.insert(
+                    format!("{}::{}", app_name, info.identifier),
+                    MessageSnapshot {
+                        topic: info.topic.clone(),
+                        version: info.version,
+                        schema: serde_json::to_value(&info.schema).unwrap(),
+                    },
+                );
+            }
+        }
+        SchemaSnapshot { messages }
+    }
+
+    /// Lists the changes of the messages from this snapshot to the current one
+    pub fn diff(&self, current: &SchemaSnapshot) -> Vec<SchemaChange> {
+        let mut changes = Vec::new();
+        for (name, previous) in &self.messages {
+            let Some(message) = current.messages.get(name) else {
+                changes.push(SchemaChange {
+                    message: name.clone(),
+                    compatibility: Compatibility::Breaking,
+                    description: "message removed".to_string(),
+                    version_bumped: false,
+                });
+                continue;
+            };
+
+            let mut message_changes = Vec::new();
+            if message.topic != previous.topic {
+                message_changes.push((
+                    Compatibility::Breaking,
+                    format!(
+                        "topic renamed from '{}' to '{}'",
+                        previous.topic, message.topic
+                    ),
+                ));
+            }
+            compare_root_schemas(&previous.schema, &message.schema, &mut message_changes);
+
+            let version_bumped = message.version > previous.version;
+            changes.extend(
+                message_changes
+                    .into_iter()
+                    .map(|(compatibility, description)| SchemaChange {
+                        message: name.clone(),
+                        compatibility,
+                        description,
+                        version_bumped,
+                    }),
+            );
+        }
+
+        for name in current.messages.keys() {
+            if !self.messages.contains_key(name) {
+                changes.push(SchemaChange {
+                    message: name.clone(),
+                    compatibility: Compatibility::Compatible,
+                    description: "message added".to_string(),
+                    version_bumped: false,
+                });
+            }
+        }
+        changes
+    }
+}
+
+pub(crate) type Changes = Vec<(Compatibility, String)>;
+
+/// Compares two root schemas, and the definitions they share
+pub(crate) fn compare_root_schemas(previous: &Value, current: &Value, changes: &mut Changes) {
+    compare_schemas("", previous, current, changes);
+
+    let definitions = |schema: &Value| schema.get("definitions").cloned().unwrap_or_default();
+    let (previous, current) = (definitions(previous), definitions(current));
+    if let (Some(previous), Some(current)) = (previous.as_object(), current.as_object()) {
+        for (name, previous) in previous {
+            // A removed definition is no longer referenced, which is reported where it was
+            if let Some(current) = current.get(name) {
+                compare_schemas(name, previous, current, changes);
+            }
+        }
+    }
+}
+
+/// Compares the schema of a value, at a path such as `position.x`
+fn compare_schemas(path: &str, previous: &Value, current: &Value, changes: &mut Changes) {
+    let (previous, current) = (without_annotations(previous), without_annotations(current));
+    if previous == current {
+        return;
+    }
+    let properties = |schema: &Value| schema.get("properties").and_then(Value::as_object).cloned();
+    let (Some(previous_properties), Some(current_properties)) =
+        (properties(&previous), properties(&current))
+    else {
+        let path = if path.is_empty() { "the message" } else { path };
+        changes.push((
+            Compatibility::Breaking,
+            format!("type of '{}' changed", path),
+        ));
+        return;
+    };
+
+    let (previous_required, current_required) = (required(&previous), required(&current));
+    let field = |name: &str| match path {
+        "" => name.to_string(),
+        path => format!("{}.{}", path, name),
+    };
+    for (name, previous_property) in &previous_properties {
+        match current_properties.get(name) {
+            None => changes.push((
+                Compatibility::Breaking,
+                format!("field '{}' removed", field(name)),
+            )),
+            Some(current_property) => {
+                // Publishers may omit an optional field, which breaks the listeners expecting it
+                match (
+                    previous_required.contains(name),
+                    current_required.contains(name),
+                ) {
+                    (false, true) => changes.push((
+                        Compatibility::Breaking,
+                        format!("field '{}' became required", field(name)),
+                    )),
+                    (true, false) => changes.push((
+                        Compatibility::Breaking,
+                        format!("field '{}' became optional", field(name)),
+                    )),
+                    _ => {}
+                }
+                compare_schemas(&field(name), previous_property, current_property, changes);
+            }
+        }
+    }
+    for name in current_properties.keys() {
+        if !previous_properties.contains_key(name) {
+            let change = if current_required.contains(name) {
+                (Compatibility::Breaking, "required field")
+            } else {
+                (Compatibility::Compatible, "optional field")
+            };
+            changes.push((change.0, format!("{} '{}' added", change.1, field(name))));
+        }
+    }
+
+    // Any other keyword, e.g. additionalProperties, restricts the values differently
+    let rest = |schema: &Value| {
+        let mut schema = schema.clone();
+        if let Some(schema) = schema.as_object_mut() {
+            for keyword in ["properties", "required", "definitions"] {
+                schema.remove(keyword);
+            }
+        }
+        schema
+    };
+    if rest(&previous) != rest(&current) {
+        let path = if path.is_empty() { "the message" } else { path };
+        changes.push((
+            Compatibility::Breaking,
+            format!("constraints of '{}' changed", path),
+        ));
+    }
+}
+
+/// The names of the required properties of an object schema
+fn required(schema: &Value) -> BTreeSet<String> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|name| name.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A schema without the keywords documenting it, nor the definitions compared separately
+fn without_annotations(schema: &Value) -> Value {
+    let mut schema = schema.clone();
+    if let Some(object) = schema.as_object_mut() {
+        for keyword in ANNOTATIONS.iter().chain(&["definitions"]) {
+            object.remove(*keyword);
+        }
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::JsonSchema;
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Robot {
+        name: String,
+        position: Position,
+    }
+
+    mod v2 {
+        #[allow(dead_code)]
+        #[derive(schemars::JsonSchema)]
+        pub struct Position {
+            x: f64,
+            y: f32,
+        }
+
+        #[allow(dead_code)]
+        #[derive(schemars::JsonSchema)]
+        pub struct Robot {
+            position: Position,
+            battery: Option<u8>,
+            speed: f32,
+        }
+    }
+
+    fn snapshot(topic: &str, version: u32, schema: schemars::schema::RootSchema) -> SchemaSnapshot {
+        let message = MessageSnapshot {
+            topic: topic.to_string(),
+            version,
+            schema: serde_json::to_value(schema).unwrap(),
+        };
+        SchemaSnapshot {
+            messages: BTreeMap::from([("app_1::Robot".to_string(), message)]),
+        }
+    }
+
+    #[test]
+    fn classifies_the_changes_of_a_message() {
+        let previous = snapshot("app_1/robot", 1, schemars::schema_for!(Robot));
+        let current = snapshot("app_1/robots", 1, schemars::schema_for!(v2::Robot));
+        let changes: Vec<(Compatibility, String)> = previous
+            .diff(&current)
+            .into_iter()
+            .map(|change| (change.compatibility, change.description))
+            .collect();
+
+        use Compatibility::*;
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    Breaking,
+                    "topic renamed from 'app_1/robot' to 'app_1/robots'".to_string()
+                ),
+                (Breaking, "field 'name' removed".to_string()),
+                (Compatible, "optional field 'battery' added".to_string()),
+                (Breaking, "required field 'speed' added".to_string()),
+                (Breaking, "type of 'Position.x' changed".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_fields_becoming_optional() {
+        #[allow(dead_code)]
+        #[derive(JsonSchema)]
+        struct DefaultName {
+            #[serde(default)]
+            name: String,
+            position: Position,
+        }
+
+        let previous = snapshot("app_1/robot", 1, schemars::schema_for!(Robot));
+        let current = snapshot("app_1/robot", 1, schemars::schema_for!(DefaultName));
+        let changes: Vec<(Compatibility, String)> = previous
+            .diff(&current)
+            .into_iter()
+            .map(|change| (change.compatibility, change.description))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![(
+                Compatibility::Breaking,
+                "field 'name' became optional".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn allows_breaking_changes_with_a_version_bump() {
+        let previous = snapshot("app_1/robot", 1, schemars::schema_for!(Robot));
+        let current = snapshot("app_1/robot", 2, schemars::schema_for!(v2::Robot));
+        assert!(previous.diff(&current).iter().all(SchemaChange::is_allowed));
+
+        let current = snapshot("app_1/robot", 1, schemars::schema_for!(v2::Robot));
+        assert!(!previous.diff(&current).iter().all(SchemaChange::is_allowed));
+        assert!(previous.diff(&previous).is_empty());
+    }
+
+    #[test]
+    fn reports_added_and_removed_messages() {
+        let previous = snapshot("app_1/robot", 1, schemars::schema_for!(Robot));
+        let changes = previous.diff(&SchemaSnapshot::default());
+        assert_eq!(changes[0].description, "message removed");
+        assert!(!changes[0].is_allowed());
+
+        let changes = SchemaSnapshot::default().diff(&previous);
+        assert_eq!(changes[0].description, "message added");
+        assert!(changes[0].is_allowed());
+    }
+}
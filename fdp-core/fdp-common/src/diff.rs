@@ -0,0 +1,331 @@
+//! Lists the differences between two snapshots of a FDP system, for the review of its changes
+
+use crate::compat::{compare_root_schemas, Compatibility};
+use crate::graph::{FdpEdge, FdpSystem};
+use crate::info::{MessageDeclarationInfo, SystemDefinitionInfo};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The differences between two snapshots of a FDP system
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SystemDiff {
+    pub added_apps: Vec<String>,
+    pub removed_apps: Vec<String>,
+    /// The declared messages, as `<app>::<identifier>`
+    pub added_messages: Vec<String>,
+    pub removed_messages: Vec<String>,
+    pub added_topics: Vec<String>,
+    pub removed_topics: Vec<String>,
+    pub added_edges: Vec<FdpEdge>,
+    pub removed_edges: Vec<FdpEdge>,
+    /// The changes of the schemas of the messages declared in both snapshots
+    pub schema_changes: Vec<SchemaFieldChange>,
+    /// What couldn't be compared, e.g. the edges when the graph of a snapshot can't be built
+    pub warnings: Vec<String>,
+}
+
+/// A change of the schema of a message, e.g. a removed field
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaFieldChange {
+    /// The changed message, as `<app>::<identifier>`
+    pub message: String,
+    pub description: String,
+    pub compatibility: Compatibility,
+}
+
+impl SystemDiff {
+    /// Compares two snapshots of a FDP system.
+    /// The apps, messages, topics and schemas are compared from the definitions. The edges are compared from the graphs,
+    /// and skipped with a warning if the graph of one of them can't be built, e.g. a new event isn't listened to yet.
+    pub fn between(previous: &SystemDefinitionInfo, current: &SystemDefinitionInfo) -> Self {
+        let (apps, previous_apps) = (app_names(current), app_names(previous));
+        let (messages, previous_messages) = (declarations(current), declarations(previous));
+        let topics = |messages: &BTreeMap<String, &MessageDeclarationInfo>| -> BTreeSet<String> {
+            messages.values().map(|info| info.topic.clone()).collect()
+        };
+        let (topics, previous_topics) = (topics(&messages), topics(&previous_messages));
+        let mut warnings = Vec::new();
+        let (edges, previous_edges) = match (
+            FdpSystem::from(current.clone()),
+            FdpSystem::from(previous.clone()),
+        ) {
+            (Ok(system), Ok(previous_system)) => (system.edges(), previous_system.edges()),
+            (system, previous_system) => {
+                let errors = [
+                    ("current", system.err()),
+                    ("previous", previous_system.err()),
+                ];
+                for (snapshot, error) in errors {
+                    if let Some(error) = error {
+                        warnings.push(format!(
+                            "The edges are not compared, the graph of the {} system can't be built: {}",
+                            snapshot, error
+                        ));
+                    }
+                }
+                (BTreeSet::new(), BTreeSet::new())
+            }
+        };
+
+        let mut schema_changes = Vec::new();
+        for (name, previous_info) in &previous_messages {
+            if let Some(info) = messages.get(name) {
+                let (previous_schema, schema) = (
+                    serde_json::to_value(&previous_info.schema).unwrap(),
+                    serde_json::to_value(&info.schema).unwrap(),
+                );
+                let mut changes = Vec::new();
+                compare_root_schemas(&previous_schema, &schema, &mut changes);
+                schema_changes.extend(changes.into_iter().map(|(compatibility, description)| {
+                    SchemaFieldChange {
+                        message: name.clone(),
+                        description,
+                        compatibility,
+                    }
+                }));
+            }
+        }
+
+        SystemDiff {
+            added_apps: difference(&apps, &previous_apps),
+            removed_apps: difference(&previous_apps, &apps),
+            added_messages: difference(
+                &messages.keys().collect(),
+                &previous_messages.keys().collect(),
+            ),
+            removed_messages: difference(
+                &previous_messages.keys().collect(),
+                &messages.keys().collect(),
+            ),
+            added_topics: difference(&topics, &previous_topics),
+            removed_topics: difference(&previous_topics, &topics),
+            added_edges: edges.difference(&previous_edges).cloned().collect(),
+            removed_edges: previous_edges.difference(&edges).cloned().collect(),
+            schema_changes,
+            warnings,
+        }
+    }
+
+    /// Whether both snapshots describe the same system, as far as they could be compared
+    pub fn is_empty(&self) -> bool {
+        *self
+            == SystemDiff {
+                warnings: self.warnings.clone(),
+                ..SystemDiff::default()
+            }
+    }
+
+    /// Renders the differences as a Markdown report
+    pub fn to_markdown(&self) -> String {
+        let mut report = String::from("# FDP system changes\n");
+        let warnings = self
+            .warnings
+            .iter()
+            .map(|warning| format!("- ⚠️ {}\n", warning));
+        if self.is_empty() {
+            report.push_str("\nNo changes.\n");
+            push_section(&mut report, "Warnings", warnings);
+            return report;
+        }
+
+        let sections = [
+            ("Apps", &self.added_apps, &self.removed_apps),
+            ("Messages", &self.added_messages, &self.removed_messages),
+            ("Topics", &self.added_topics, &self.removed_topics),
+        ];
+        for (title, added, removed) in sections {
+            let lines = added
+                .iter()
+                .map(|name| format!("- ➕ `{}`\n", name))
+                .chain(removed.iter().map(|name| format!("- ➖ `{}`\n", name)));
+            push_section(&mut report, title, lines);
+        }
+
+        let edge = |sign: &str, edge: &FdpEdge| {
+            format!("- {} {} → {}: {}\n", sign, edge.from, edge.to, edge.label)
+        };
+        let lines = self
+            .added_edges
+            .iter()
+            .map(|added| edge("➕", added))
+            .chain(self.removed_edges.iter().map(|removed| edge("➖", removed)));
+        push_section(&mut report, "Edges", lines);
+
+        if !self.schema_changes.is_empty() {
+            report.push_str(
+                "\n## Schema changes\n\n| Message | Change | Compatibility |\n|---|---|---|\n",
+            );
+            for change in &self.schema_changes {
+                let compatibility = match change.compatibility {
+                    Compatibility::Compatible => "compatible",
+                    Compatibility::Breaking => "breaking",
+                };
+                report.push_str(&format!(
+                    "| `{}` | {} | {} |\n",
+                    change.message, change.description, compatibility
+                ));
+            }
+        }
+        push_section(&mut report, "Warnings", warnings);
+        report
+    }
+}
+
+/// Appends a section listing changes, unless there are none
+fn push_section(report: &mut String, title: &str, lines: impl Iterator<Item = String>) {
+    let lines: String = lines.collect();
+    if !lines.is_empty() {
+        report.push_str(&format!("\n## {}\n\n{}", title, lines));
+    }
+}
+
+/// The sorted names in a set that are missing from another
+fn difference<T: ToString + Ord>(names: &BTreeSet<T>, other: &BTreeSet<T>) -> Vec<String> {
+    names.difference(other).map(ToString::to_string).collect()
+}
+
+fn app_names(system: &SystemDefinitionInfo) -> BTreeSet<String> {
+    system.apps.keys().cloned().collect()
+}
+
+/// The declared messages of a system, keyed by `<app>::<identifier>`
+fn declarations(system: &SystemDefinitionInfo) -> BTreeMap<String, &MessageDeclarationInfo> {
+    let mut messages = BTreeMap::new();
+    for (app_name, app_info) in &system.apps {
+        let declarations = app_info
+            .broadcasted_events
+            .iter()
+            .chain(&app_info.incoming_requests)
+            .chain(&app_info.outgoing_responses);
+        for info in declarations {
+            messages.insert(format!("{}::{}", app_name, info.identifier), info);
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::info::{AppDefinitionInfo, MessageReferenceInfo};
+    use schemars::JsonSchema;
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Reading {
+        value: f32,
+    }
+
+    mod v2 {
+        #[allow(dead_code)]
+        #[derive(schemars::JsonSchema)]
+        pub struct Reading {
+            value: f32,
+            unit: Option<String>,
+        }
+    }
+
+    fn reading(topic: &str, schema: schemars::schema::RootSchema) -> MessageDeclarationInfo {
+        MessageDeclarationInfo::for_test("Reading", topic, schema)
+    }
+
+    fn listens_to_reading() -> Vec<MessageReferenceInfo> {
+        vec![MessageReferenceInfo {
+            identifier: "Reading".to_string(),
+            app_name: "sensor".to_string(),
+            module: "broadcasted_events".to_string(),
+        }]
+    }
+
+    #[test]
+    fn lists_the_changes_between_two_systems() {
+        let previous = SystemDefinitionInfo::from(vec![
+            (
+                "sensor".to_string(),
+                AppDefinitionInfo::for_test(
+                    vec![reading("sensor/reading", schemars::schema_for!(Reading))],
+                    vec![],
+                ),
+            ),
+            (
+                "logger".to_string(),
+                AppDefinitionInfo::for_test(vec![], listens_to_reading()),
+            ),
+        ]);
+        let current = SystemDefinitionInfo::from(vec![
+            (
+                "sensor".to_string(),
+                AppDefinitionInfo::for_test(
+                    vec![reading(
+                        "sensor/readings",
+                        schemars::schema_for!(v2::Reading),
+                    )],
+                    vec![],
+                ),
+            ),
+            (
+                "dashboard".to_string(),
+                AppDefinitionInfo::for_test(vec![], listens_to_reading()),
+            ),
+        ]);
+
+        let diff = SystemDiff::between(&previous, &current);
+        assert_eq!(diff.added_apps, vec!["dashboard"]);
+        assert_eq!(diff.removed_apps, vec!["logger"]);
+        assert!(diff.added_messages.is_empty());
+        assert_eq!(diff.added_topics, vec!["sensor/readings"]);
+        assert_eq!(diff.removed_topics, vec!["sensor/reading"]);
+        assert_eq!(diff.added_edges[0].to, "dashboard");
+        assert_eq!(diff.removed_edges[0].to, "logger");
+        assert_eq!(
+            diff.schema_changes,
+            vec![SchemaFieldChange {
+                message: "sensor::Reading".to_string(),
+                description: "optional field 'unit' added".to_string(),
+                compatibility: Compatibility::Compatible,
+            }]
+        );
+
+        let report = diff.to_markdown();
+        assert!(report.contains("- ➕ `dashboard`"), "{}", report);
+        assert!(
+            report.contains("- ➖ sensor → logger: Broadcasts: Reading"),
+            "{}",
+            report
+        );
+        assert!(diff.warnings.is_empty());
+        assert!(SystemDiff::between(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn compares_systems_whose_graph_cannot_be_built() {
+        let previous = SystemDefinitionInfo::from(vec![(
+            "sensor".to_string(),
+            AppDefinitionInfo::for_test(vec![], vec![]),
+        )]);
+        // The new event isn't listened to yet
+        let current = SystemDefinitionInfo::from(vec![(
+            "sensor".to_string(),
+            AppDefinitionInfo::for_test(
+                vec![reading("sensor/reading", schemars::schema_for!(Reading))],
+                vec![],
+            ),
+        )]);
+
+        let diff = SystemDiff::between(&previous, &current);
+        assert_eq!(diff.added_messages, vec!["sensor::Reading"]);
+        assert_eq!(diff.added_topics, vec!["sensor/reading"]);
+        assert!(diff.added_edges.is_empty());
+        assert_eq!(diff.warnings.len(), 1, "{:?}", diff.warnings);
+        assert!(diff.warnings[0].contains("current system"));
+
+        let report = diff.to_markdown();
+        assert!(report.contains("- ➕ `sensor::Reading`"), "{}", report);
+        assert!(report.contains("## Warnings"), "{}", report);
+
+        let unchanged = SystemDiff::between(&current, &current);
+        assert!(unchanged.is_empty());
+        assert_eq!(unchanged.warnings.len(), 2);
+        assert!(unchanged.to_markdown().contains("No changes."));
+    }
+}
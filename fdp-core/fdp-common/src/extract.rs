@@ -0,0 +1,106 @@
+//! The information extracted from a module by the fdp::extract macro: its submodules and their items
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The information extracted from a module, returned by its generated `get_extracted_information` function
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractedInformation {
+    /// The name of the module
+    pub module: String,
+    /// The submodules, in declaration order
+    pub submodules: Vec<ExtractedModule>,
+}
+
+impl ExtractedInformation {
+    /// Returns the submodule with the given name, if the module contains it
+    pub fn submodule(&self, name: &str) -> Option<&ExtractedModule> {
+        self.submodules
+            .iter()
+            .find(|submodule| submodule.name == name)
+    }
+}
+
+/// A submodule and the items it contains
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractedModule {
+    pub name: String,
+    /// The items, in declaration order
+    pub items: Vec<ExtractedItem>,
+}
+
+/// An item of a submodule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractedItem {
+    pub kind: ItemKind,
+    /// The name of the item, e.g. the imported name of a `use` item, or the type of an `impl` block
+    pub ident: String,
+    /// Whether the item is public
+    pub public: bool,
+    /// The imported path of a `use` item, e.g. `crate::apps::app_1::broadcasted_events::Heartbeat`
+    pub path: Option<String>,
+}
+
+/// The kinds of items a submodule may contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Const,
+    Enum,
+    Fn,
+    Impl,
+    Macro,
+    Mod,
+    Static,
+    Struct,
+    Trait,
+    Type,
+    Use,
+}
+
+impl ItemKind {
+    /// Every kind of item, in the order of their keywords
+    pub const ALL: [ItemKind; 11] = [
+        ItemKind::Const,
+        ItemKind::Enum,
+        ItemKind::Fn,
+        ItemKind::Impl,
+        ItemKind::Macro,
+        ItemKind::Mod,
+        ItemKind::Static,
+        ItemKind::Struct,
+        ItemKind::Trait,
+        ItemKind::Type,
+        ItemKind::Use,
+    ];
+
+    /// The keyword declaring the kind of item, e.g. `struct`
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ItemKind::Const => "const",
+            ItemKind::Enum => "enum",
+            ItemKind::Fn => "fn",
+            ItemKind::Impl => "impl",
+            ItemKind::Macro => "macro",
+            ItemKind::Mod => "mod",
+            ItemKind::Static => "static",
+            ItemKind::Struct => "struct",
+            ItemKind::Trait => "trait",
+            ItemKind::Type => "type",
+            ItemKind::Use => "use",
+        }
+    }
+
+    /// Returns the kind of item declared with a keyword
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        ItemKind::ALL
+            .into_iter()
+            .find(|kind| kind.keyword() == keyword)
+    }
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
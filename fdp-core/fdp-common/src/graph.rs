@@ -4,15 +4,19 @@ use crate::info::{AppDefinitionInfo, MessageDeclarationInfo, SystemDefinitionInf
 use petgraph::{
     dot::{Config, Dot},
     graph::{DiGraph, NodeIndex},
-    visit::NodeRef,
+    visit::{EdgeRef, NodeRef},
 };
 use schemars::schema::RootSchema;
-use std::{collections::HashMap, fmt::Debug};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
 
 /// The FdpSystem holds a graph representation of the FDP system and it's messages producer/consumer relationship
 pub struct FdpSystem {
     pub graph: DiGraph<FdpApp, FdpMessage>,
-    pub index_map: HashMap<String, NodeIndex>,
+    pub index_map: BTreeMap<String, NodeIndex>,
 }
 
 /// A FdpApp represents an application within the FDP system
@@ -28,6 +32,16 @@ pub struct FdpMessage {
     pub message_type: MessageType,
     pub topic: String,
     pub schema: RootSchema,
+    /// The version of the message schema
+    pub version: u32,
+}
+
+/// An edge of the graph, identified by the apps it connects and the label of its message
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct FdpEdge {
+    pub from: String,
+    pub to: String,
+    pub label: String,
 }
 
 /// A message going through the MQTT broker can be only one of these types
@@ -41,10 +55,10 @@ pub enum MessageType {
 impl FdpSystem {
     pub fn from(definition_info: SystemDefinitionInfo) -> Result<Self, String> {
         let mut graph = DiGraph::new();
-        let mut index_map = HashMap::new();
+        let mut index_map = BTreeMap::new();
 
         // Create nodes for each app
-        for (app_name, _) in &definition_info.apps {
+        for app_name in definition_info.apps.keys() {
             let index = graph.add_node(FdpApp {
                 name: app_name.clone(),
             });
@@ -82,12 +96,14 @@ impl FdpSystem {
                                 message_type: MessageType::Event,
                                 topic: broadcast_message_declaration.topic.clone(),
                                 schema: broadcast_message_declaration.schema.clone(),
+                                version: broadcast_message_declaration.version,
                             },
                         );
                         listened = true;
                     }
                 }
-                if !listened {
+                // A previous version of an event may no longer be listened to, once a newer one is declared
+                if !listened && !info.declares_newer_version_of(broadcast_message_declaration) {
                     return Err(format!(
                         "Broadcast message '{}' on topic '{}' is never listened to by any other app.",
                         broadcast_message_declaration.identifier, broadcast_message_declaration.topic
@@ -114,6 +130,7 @@ impl FdpSystem {
                                 message_type: MessageType::Request,
                                 topic: incoming_requests_declaration.topic.clone(),
                                 schema: incoming_requests_declaration.schema.clone(),
+                                version: incoming_requests_declaration.version,
                             },
                         );
 
@@ -132,7 +149,7 @@ impl FdpSystem {
                         // );
                     }
                 }
-                if !handled {
+                if !handled && !info.declares_newer_version_of(incoming_requests_declaration) {
                     return Err(format!(
                         "Request '{}' on topic '{}' is never handled by any other app.",
                         incoming_requests_declaration.identifier,
@@ -149,21 +166,110 @@ impl FdpSystem {
         let dot = Dot::with_attr_getters(
             &self.graph,
             &[Config::NodeNoLabel, Config::EdgeNoLabel],
-            &|_, er| {
-                let fdp_message = er.weight();
-                let text = match fdp_message.message_type {
-                    MessageType::Event => format!("Broadcasts: {}", fdp_message.name),
-                    MessageType::Request => format!("Handles: {}", fdp_message.name),
-                    MessageType::Response => format!("Replies with {}", fdp_message.name),
-                };
-
-                format!("label = \"{}\"", text)
-            },
+            &|_, er| format!("label = \"{}\"", er.weight().label()),
             &|_, nr| format!("label = \"{}\"", nr.weight().name),
         );
 
         format!("{:?}", dot)
     }
+
+    /// Returns the edges of the graph, ordered by the apps they connect
+    pub fn edges(&self) -> BTreeSet<FdpEdge> {
+        self.graph
+            .edge_references()
+            .map(|edge| FdpEdge {
+                from: self.graph[edge.source()].name.clone(),
+                to: self.graph[edge.target()].name.clone(),
+                label: edge.weight().label(),
+            })
+            .collect()
+    }
+
+    /// Renders the graphviz graph of both systems, with the edges added since the previous system in green
+    /// and the removed ones in red. The apps only in one of the systems are colored the same way.
+    pub fn to_graphviz_diff(&self, previous: &FdpSystem) -> String {
+        let (edges, previous_edges) = (self.edges(), previous.edges());
+        let color = |current: bool, previous: bool| match (current, previous) {
+            (true, false) => "green",
+            (false, true) => "red",
+            _ => "black",
+        };
+
+        let mut graph: DiGraph<(String, &str), (String, &str)> = DiGraph::new();
+        let mut index_map: BTreeMap<&str, NodeIndex> = BTreeMap::new();
+        let apps: BTreeSet<&String> = self
+            .index_map
+            .keys()
+            .chain(previous.index_map.keys())
+            .collect();
+        for app in apps {
+            let color = color(
+                self.index_map.contains_key(app),
+                previous.index_map.contains_key(app),
+            );
+            index_map.insert(app, graph.add_node((app.clone(), color)));
+        }
+        for edge in edges.union(&previous_edges) {
+            let color = color(edges.contains(edge), previous_edges.contains(edge));
+            graph.add_edge(
+                index_map[edge.from.as_str()],
+                index_map[edge.to.as_str()],
+                (edge.label.clone(), color),
+            );
+        }
+
+        fn attributes((label, color): &(String, &str)) -> String {
+            format!(
+                "label = \"{}\" color = \"{}\" fontcolor = \"{}\"",
+                label, color, color
+            )
+        }
+        let dot = Dot::with_attr_getters(
+            &graph,
+            &[Config::NodeNoLabel, Config::EdgeNoLabel],
+            &|_, er| attributes(er.weight()),
+            &|_, nr| attributes(nr.weight()),
+        );
+        format!("{:?}", dot)
+    }
+}
+
+impl FdpMessage {
+    /// The label of the message on its edge
+    pub fn label(&self) -> String {
+        let mut text = match self.message_type {
+            MessageType::Event => format!("Broadcasts: {}", self.name),
+            MessageType::Request => format!("Handles: {}", self.name),
+            MessageType::Response => format!("Replies with {}", self.name),
+        };
+        // The later versions of a message are shown, as the previous ones may still be in use
+        if self.version > 1 {
+            text.push_str(&format!(" (v{})", self.version));
+        }
+        text
+    }
+}
+
+impl SystemDefinitionInfo {
+    /// Returns the names of the other apps listening to an event, or emitting a request, declared by an app
+    pub fn users_of(
+        &self,
+        declaring_app_name: &str,
+        message_declaration: &MessageDeclarationInfo,
+    ) -> Vec<&str> {
+        let mut users: Vec<&str> = self
+            .apps
+            .iter()
+            .filter(|(app_name, info)| {
+                *app_name != declaring_app_name
+                    && (info.references_message_as(declaring_app_name, message_declaration)
+                        || info.references_request_as(declaring_app_name, message_declaration))
+            })
+            .map(|(app_name, _)| app_name.as_str())
+            .collect();
+        users.sort();
+        users
+    }
 }
 
 impl AppDefinitionInfo {
@@ -188,6 +294,17 @@ impl AppDefinitionInfo {
         false
     }
 
+    /// Returns true if the app declares a newer version of a message, on the same declared topic
+    pub fn declares_newer_version_of(&self, message_declaration: &MessageDeclarationInfo) -> bool {
+        self.broadcasted_events
+            .iter()
+            .chain(&self.incoming_requests)
+            .any(|declaration| {
+                declaration.base_topic() == message_declaration.base_topic()
+                    && declaration.version > message_declaration.version
+            })
+    }
+
     pub fn references_request_as(
         &self,
         self_app_name: &str,
@@ -200,6 +317,6 @@ impl AppDefinitionInfo {
                 return true;
             }
         }
-        return false;
+        false
     }
 }
@@ -1,23 +1,62 @@
 //! The raw information extracted from the declarative Rust app definitions, that are created by the fdp-definition-macros
 
 use schemars::schema::RootSchema;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 
-/// Represents the information extracted about the whole FDP system
-#[derive(Debug)]
+/// Represents the information extracted about the whole FDP system.
+/// The apps are ordered by name, and their messages by identifier, so that the generated outputs are reproducible.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemDefinitionInfo {
-    pub apps: HashMap<String, AppDefinitionInfo>,
+    pub apps: BTreeMap<String, AppDefinitionInfo>,
 }
 
 impl SystemDefinitionInfo {
     pub fn from(app_definitions: Vec<(String, AppDefinitionInfo)>) -> Self {
-        let apps = app_definitions.into_iter().collect::<HashMap<_, _>>();
+        let apps = app_definitions
+            .into_iter()
+            .map(|(name, mut info)| {
+                info.sort();
+                (name, info)
+            })
+            .collect::<BTreeMap<_, _>>();
         SystemDefinitionInfo { apps }
     }
+
+    /// Serializes the system to its JSON manifest, whose objects have their keys sorted
+    /// so that the same system always gives the same manifest
+    pub fn to_manifest(&self) -> serde_json::Result<String> {
+        let manifest = canonical(serde_json::to_value(self)?);
+        serde_json::to_string_pretty(&manifest)
+    }
+
+    /// Reads a system from its JSON manifest
+    pub fn from_manifest(manifest: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(manifest)
+    }
+}
+
+/// Sorts the keys of the objects in a JSON value, recursively
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(String, Value)> = object.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonical(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonical).collect()),
+        value => value,
+    }
 }
 
 /// Represents the information extracted from a FDP app definition
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppDefinitionInfo {
     // Message declarations
     pub broadcasted_events: Vec<MessageDeclarationInfo>,
@@ -28,9 +67,41 @@ pub struct AppDefinitionInfo {
     pub emitted_requests: Vec<MessageReferenceInfo>,
 }
 
+impl AppDefinitionInfo {
+    /// Orders the messages of every submodule by identifier
+    pub fn sort(&mut self) {
+        for declarations in [
+            &mut self.broadcasted_events,
+            &mut self.incoming_requests,
+            &mut self.outgoing_responses,
+        ] {
+            declarations.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        }
+        for references in [&mut self.listened_events, &mut self.emitted_requests] {
+            references
+                .sort_by(|a, b| (&a.identifier, &a.app_name).cmp(&(&b.identifier, &b.app_name)));
+        }
+    }
+
+    /// An app broadcasting and listening to events, without requests, for the tests
+    #[cfg(test)]
+    pub(crate) fn for_test(
+        broadcasted_events: Vec<MessageDeclarationInfo>,
+        listened_events: Vec<MessageReferenceInfo>,
+    ) -> Self {
+        AppDefinitionInfo {
+            broadcasted_events,
+            incoming_requests: vec![],
+            outgoing_responses: vec![],
+            listened_events,
+            emitted_requests: vec![],
+        }
+    }
+}
+
 /// Represents the information available from the Rust code for a message declaration
-/// extracted from a 'pub struct Identifier { ... }' that implements the Message trait
-#[derive(Debug)]
+/// extracted from a 'pub struct Identifier { ... }' or 'pub enum Identifier { ... }' that implements the Message trait
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageDeclarationInfo {
     /// The identifier of the message
     pub identifier: String,
@@ -38,11 +109,164 @@ pub struct MessageDeclarationInfo {
     pub topic: String,
     /// The JSON schema of the message
     pub schema: RootSchema,
+    /// The QoS level the message is delivered with, if declared
+    pub qos: Option<u8>,
+    /// Whether the message is retained by the broker
+    pub retain: bool,
+    /// The name of the codec the message is encoded with
+    pub codec: String,
+    /// The version of the message schema
+    pub version: u32,
+}
+
+impl MessageDeclarationInfo {
+    /// The topic declared for the message, shared by all its versions
+    pub fn base_topic(&self) -> &str {
+        let suffix = format!("/v{}", self.version);
+        match self.version {
+            0 | 1 => &self.topic,
+            _ => self.topic.strip_suffix(&suffix).unwrap_or(&self.topic),
+        }
+    }
+
+    /// The JSON schema of the message, and apart the schemas of the types it uses, e.g. the enum of a field.
+    /// Their references are rewritten to the given path, e.g. `#/components/schemas/`,
+    /// so that they can be gathered in a document shared by several messages.
+    pub fn split_schema(&self, definitions_path: &str) -> (Value, BTreeMap<String, Value>) {
+        let mut schema = serde_json::to_value(&self.schema).unwrap();
+        let mut definitions = match schema.as_object_mut().and_then(|s| s.remove("definitions")) {
+            Some(Value::Object(definitions)) => definitions.into_iter().collect(),
+            _ => BTreeMap::new(),
+        };
+        rewrite_references(&mut schema, definitions_path);
+        for definition in definitions.values_mut() {
+            rewrite_references(definition, definitions_path);
+        }
+        (schema, definitions)
+    }
+
+    /// A message declared with the default QoS, retain flag, codec and version, for the tests
+    #[cfg(test)]
+    pub(crate) fn for_test(identifier: &str, topic: &str, schema: RootSchema) -> Self {
+        MessageDeclarationInfo {
+            identifier: identifier.to_string(),
+            topic: topic.to_string(),
+            schema,
+            qos: None,
+            retain: false,
+            codec: "json".to_string(),
+            version: 1,
+        }
+    }
+}
+
+/// Returns the property discriminating the variants of a tagged union schema, e.g. `kind` for an enum
+/// with the `#[serde(tag = "kind")]` attribute: every variant of its `oneOf` requires it, with a constant value
+pub fn union_discriminator(schema: &Value) -> Option<&str> {
+    let variants = schema.get("oneOf")?.as_array()?;
+    constant_properties(variants.first()?)
+        .into_iter()
+        .find(|tag| {
+            variants
+                .iter()
+                .all(|variant| constant_properties(variant).contains(tag))
+        })
+}
+
+/// A variant of a tagged union, e.g. the `Move` variant of a `Command` enum tagged with `kind`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnionVariant {
+    /// The value of the tag identifying the variant, e.g. `move_to`
+    pub tag: String,
+    /// The name of the variant's own type, the union's followed by its tag, e.g. `CommandMoveTo`
+    pub name: String,
+    /// The schema of the variant
+    pub schema: Value,
+}
+
+/// The variants of a tagged union schema, discriminated by the given property (see `union_discriminator`).
+/// It fails if a variant doesn't declare a single string as the value of its tag.
+pub fn union_variants(name: &str, schema: &Value, tag: &str) -> Result<Vec<UnionVariant>, String> {
+    let variants = schema
+        .get("oneOf")
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("The union '{}' has no 'oneOf' variants", name))?;
+    variants
+        .iter()
+        .map(|variant| {
+            let values = variant["properties"][tag]["enum"].as_array();
+            match values.map(Vec::as_slice) {
+                Some([Value::String(value)]) => Ok(UnionVariant {
+                    tag: value.clone(),
+                    name: format!("{}{}", name, to_pascal_case(value)),
+                    schema: variant.clone(),
+                }),
+                _ => Err(format!(
+                    "A variant of the union '{}' doesn't declare a single string as its '{}' tag: {}",
+                    name, tag, variant
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Converts a tag value to a type name suffix, e.g. `MoveTo` for `move_to`
+fn to_pascal_case(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+        })
+        .collect()
+}
+
+/// The required properties of an object schema that have a single possible value
+fn constant_properties(schema: &Value) -> Vec<&str> {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let required = schema.get("required").and_then(Value::as_array);
+    let (Some(properties), Some(required)) = (properties, required) else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter(|(name, property)| {
+            let values = property.get("enum").and_then(Value::as_array);
+            values.is_some_and(|values| values.len() == 1) && required.contains(&json!(name))
+        })
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Rewrites the references of a schema to its definitions, e.g. `#/definitions/Color`, to another path
+fn rewrite_references(schema: &mut Value, definitions_path: &str) {
+    match schema {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::String(reference) if key == "$ref" => {
+                        if let Some(name) = reference.strip_prefix("#/definitions/") {
+                            *reference = format!("{}{}", definitions_path, name);
+                        }
+                    }
+                    value => rewrite_references(value, definitions_path),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                rewrite_references(value, definitions_path);
+            }
+        }
+        _ => {}
+    }
 }
 
 /// Representes the information available from the Rust code for a message reference
 /// extracted from a `pub use crate::apps::<app_name>::<submodule>::<Identifier>;` statement.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageReferenceInfo {
     /// The non-renamed identifier of the message
     pub identifier: String,
@@ -51,3 +275,114 @@ pub struct MessageReferenceInfo {
     /// The submodule in which the message is defined
     pub module: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(identifier: &str) -> AppDefinitionInfo {
+        let status = MessageDeclarationInfo {
+            qos: Some(1),
+            retain: true,
+            ..MessageDeclarationInfo::for_test(
+                identifier,
+                &format!("{}/status", identifier.to_lowercase()),
+                schemars::schema_for!(bool),
+            )
+        };
+        AppDefinitionInfo::for_test(vec![status], vec![])
+    }
+
+    #[allow(dead_code)]
+    #[derive(schemars::JsonSchema)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    #[allow(dead_code)]
+    #[derive(schemars::JsonSchema)]
+    struct Light {
+        color: Color,
+        colors: Vec<Color>,
+    }
+
+    #[test]
+    fn splits_the_schemas_of_the_types_a_message_uses() {
+        let info =
+            MessageDeclarationInfo::for_test("Light", "light/status", schemars::schema_for!(Light));
+        let (schema, definitions) = info.split_schema("#/components/schemas/");
+        assert!(schema.get("definitions").is_none());
+        assert_eq!(
+            schema["properties"]["colors"]["items"]["$ref"],
+            "#/components/schemas/Color"
+        );
+        assert_eq!(definitions.keys().collect::<Vec<_>>(), vec!["Color"]);
+    }
+
+    #[allow(dead_code)]
+    #[derive(schemars::JsonSchema)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    enum Command {
+        Stop,
+        Move { speed: f32 },
+    }
+
+    #[test]
+    fn finds_the_discriminator_of_a_tagged_union() {
+        let schema = |schema| serde_json::to_value(schema).unwrap();
+        assert_eq!(
+            union_discriminator(&schema(schemars::schema_for!(Command))),
+            Some("kind")
+        );
+        assert_eq!(
+            union_discriminator(&schema(schemars::schema_for!(Color))),
+            None
+        );
+        assert_eq!(
+            union_discriminator(&schema(schemars::schema_for!(Light))),
+            None
+        );
+    }
+
+    #[test]
+    fn lists_the_variants_of_a_tagged_union() {
+        let schema = serde_json::to_value(schemars::schema_for!(Command)).unwrap();
+        let variants = union_variants("Command", &schema, "kind").unwrap();
+        let names: Vec<(&str, &str)> = variants
+            .iter()
+            .map(|variant| (variant.tag.as_str(), variant.name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("stop", "CommandStop"), ("move", "CommandMove")]
+        );
+        assert_eq!(variants[1].schema["properties"]["speed"]["type"], "number");
+
+        let mut numbered = schema.clone();
+        numbered["oneOf"][0]["properties"]["kind"]["enum"] = json!([0]);
+        let error = union_variants("Command", &numbered, "kind").unwrap_err();
+        assert!(error.contains("'kind' tag"), "{}", error);
+    }
+
+    #[test]
+    fn writes_a_canonical_manifest() {
+        let apps = || {
+            ["app_1", "app_2", "app_3"]
+                .map(|name| (name.to_string(), app(&name.to_uppercase())))
+                .to_vec()
+        };
+        let manifest = SystemDefinitionInfo::from(apps()).to_manifest().unwrap();
+        let mut reversed = apps();
+        reversed.reverse();
+        assert_eq!(
+            SystemDefinitionInfo::from(reversed).to_manifest().unwrap(),
+            manifest
+        );
+
+        let system = SystemDefinitionInfo::from_manifest(&manifest).unwrap();
+        assert_eq!(system.apps["app_2"].broadcasted_events[0].qos, Some(1));
+        assert_eq!(system.to_manifest().unwrap(), manifest);
+        assert!(manifest.find("\"app_1\"") < manifest.find("\"app_2\""));
+    }
+}
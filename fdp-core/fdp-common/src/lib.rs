@@ -3,4 +3,8 @@
 pub mod info;
 pub mod parsing;
 pub mod graph;
+pub mod compat;
+pub mod diff;
+pub mod typescript;
+pub mod extract;
 pub mod mqtt;
\ No newline at end of file
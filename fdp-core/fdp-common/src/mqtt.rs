@@ -3,12 +3,59 @@
 use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Serialize};
 
-/// A Message within the FDP system can be serialized/deserialized to JSON and has a topic.
+/// The names of the codecs a message can be encoded with
+pub const CODECS: [&str; 4] = ["json", "msgpack", "cbor", "postcard"];
+
+/// The MQTT v5 content type of the payloads encoded with a codec
+pub fn content_type(codec: &str) -> &'static str {
+    match codec {
+        "msgpack" => "application/msgpack",
+        "cbor" => "application/cbor",
+        "postcard" => "application/x-postcard",
+        _ => "application/json",
+    }
+}
+
+/// The topic of a version of a message: the first version is sent on the declared topic,
+/// and the later ones side by side on a `/v<version>` subtopic, e.g. `app_1/sensor/temperature/v2`
+pub fn versioned_topic(topic: &str, version: u32) -> String {
+    match version {
+        0 | 1 => topic.to_string(),
+        version => format!("{}/v{}", topic, version),
+    }
+}
+
+/// A Message within the FDP system can be serialized/deserialized with its codec (JSON by default) and has a topic.
 /// It can represent an event, a request or a response
 pub trait Message: Serialize + DeserializeOwned + JsonSchema + Send + Sync + 'static {
     /// The static topic the message is sent on
     fn topic() -> &'static str;
 
+    /// The QoS level (0, 1 or 2) the message is delivered with.
+    /// When `None`, the client's default QoS is used.
+    fn qos() -> Option<u8> {
+        None
+    }
+
+    /// Whether the broker retains the last message published on the topic
+    fn retain() -> bool {
+        false
+    }
+
+    /// The name of the codec the message is encoded with, one of `CODECS`.
+    /// It is a constant so that clients can reject the codecs they weren't built with at compile time.
+    const CODEC: &'static str = "json";
+
+    /// The name of the codec the message is encoded with, see `CODEC`
+    fn codec() -> &'static str {
+        Self::CODEC
+    }
+
+    /// The version of the message schema, carried in the envelope of the message
+    fn version() -> u32 {
+        1
+    }
+
     /// The JSON schema of the message
     fn schema() -> schemars::schema::SchemaObject {
         schemars::schema_for!(Self).schema
@@ -23,3 +70,21 @@ pub trait Request: Message {
     /// The associated response type required for the request
     type Response: Message;
 }
+
+/// A FDP app, as named in the manifest
+pub trait App {
+    /// The name of the app, e.g. `app_1` for `crate::apps::app_1`
+    fn name() -> &'static str;
+}
+
+/// An event declared in the `broadcasted_events` of the app `A`, which only this app may broadcast
+pub trait BroadcastedEvent<A>: Event {}
+
+/// An event referenced in the `listened_events` of the app `A`
+pub trait ListenedEvent<A>: Event {}
+
+/// A request declared in the `incoming_requests` of the app `A`, which only this app may respond to
+pub trait IncomingRequest<A>: Request {}
+
+/// A request referenced in the `emitted_requests` of the app `A`
+pub trait EmittedRequest<A>: Request {}
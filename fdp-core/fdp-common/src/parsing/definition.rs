@@ -1,8 +1,9 @@
 //! Parsing logic for a FDP app definition module
 
+use crate::parsing::extract::{Extraction, ModuleSpec};
 use crate::parsing::modules::*;
-use quote::{quote, ToTokens};
-use syn::{parse::Parse, ItemMod};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse::Parse, parse_quote, Ident, ItemMod};
 
 /// Handles the fdp::definition macro
 pub struct AppDefinitionModule {
@@ -16,32 +17,29 @@ pub struct AppDefinitionModule {
 }
 
 /// When parsing a FDP app definition module, we need to ensure it matches a predefined structure.
-/// It can only contain 5 public sub-modules named according to the AppDefinitionModule struct.
+/// It can only contain the 5 public sub-modules named according to the AppDefinitionModule struct,
+/// a missing one being treated as empty.
 impl Parse for AppDefinitionModule {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let module: ItemMod = input.parse()?;
         enforce_module_name(&module, "definition")?;
-        let Submodules {
-            broadcasted_events,
-            incoming_requests,
-            outgoing_responses,
-            listened_events,
-            emitted_requests,
-        } = enforce_submodules(&module)?;
+        enforce_client_placement(&module)?;
+        let extraction = definition_spec().extract(&module)?;
 
         Ok(AppDefinitionModule {
-            broadcasted_events: syn::parse2(broadcasted_events.to_token_stream())?,
-            incoming_requests: syn::parse2(incoming_requests.to_token_stream())?,
-            outgoing_responses: syn::parse2(outgoing_responses.to_token_stream())?,
-            listened_events: syn::parse2(listened_events.to_token_stream())?,
-            emitted_requests: syn::parse2(emitted_requests.to_token_stream())?,
+            broadcasted_events: submodule(&extraction)?,
+            incoming_requests: submodule(&extraction)?,
+            outgoing_responses: submodule(&extraction)?,
+            listened_events: submodule(&extraction)?,
+            emitted_requests: submodule(&extraction)?,
         })
     }
 }
 
 /// For the definition module, we want to expose the submodules as public items
 /// at the root of the module for the documentation, and then add a get_definition function
-/// that will return the constructed FDP definition
+/// that will return the constructed FDP definition.
+/// The typed client of the app is generated separately by `client_tokens`, for the fdp::client macro.
 impl ToTokens for AppDefinitionModule {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let (b_evn, b_evn_g) = (&self.broadcasted_events, &self.broadcasted_events.gen);
@@ -50,6 +48,11 @@ impl ToTokens for AppDefinitionModule {
         let (l_evn, l_evn_g) = (&self.listened_events, &self.listened_events.gen);
         let (e_req, e_req_g) = (&self.emitted_requests, &self.emitted_requests.gen);
 
+        let b_evn_m = get_message_idents(&self.broadcasted_events.module);
+        let i_req_m = get_message_idents(&self.incoming_requests.module);
+        let l_evn_m = get_message_idents(&self.listened_events.module);
+        let e_req_m = get_message_idents(&self.emitted_requests.module);
+
         tokens.extend(quote! {
             #[doc(inline)]
             pub use definition::*;
@@ -86,145 +89,175 @@ impl ToTokens for AppDefinitionModule {
                     emitted_requests: #e_req_g,
                 }
             }
+
+            /// Marks the messages this app declares or references, to restrict its typed client to them
+            #[derive(Debug, Clone, Copy)]
+            pub struct App;
+
+            impl fdp_common::mqtt::App for App {
+                fn name() -> &'static str {
+                    // The definition is expanded in the module of the app
+                    let path = module_path!();
+                    path.rsplit("::").next().unwrap_or(path)
+                }
+            }
+
+            #(impl fdp_common::mqtt::BroadcastedEvent<App> for broadcasted_events::#b_evn_m {})*
+            #(impl fdp_common::mqtt::IncomingRequest<App> for incoming_requests::#i_req_m {})*
+            #(impl fdp_common::mqtt::ListenedEvent<App> for listened_events::#l_evn_m {})*
+            #(impl fdp_common::mqtt::EmittedRequest<App> for emitted_requests::#e_req_m {})*
         });
     }
 }
 
-/// Enforces that a module had a specific name
-fn enforce_module_name(module: &ItemMod, name: &str) -> syn::Result<()> {
-    if module.ident != name {
-        return Err(syn::Error::new_spanned(
-            &module.ident,
-            "The module must be named 'definition'",
-        ));
-    }
-    Ok(())
-}
+impl AppDefinitionModule {
+    /// The typed `Client` of the app, whose methods only accept the messages the app may use,
+    /// and its `Handlers` trait registered with `serve`, with one method per listened event and incoming request.
+    /// They depend on fdp-mqtt-client, so they are only generated for the definitions marked with fdp::client,
+    /// next to the items generated by fdp::definition.
+    pub fn client_tokens(&self) -> proc_macro2::TokenStream {
+        let l_evn_m = get_message_idents(&self.listened_events.module);
+        let i_req_m = get_message_idents(&self.incoming_requests.module);
+        let handlers = get_handlers(&l_evn_m, &i_req_m);
+        quote! {
+            /// The typed client of this app, which only accepts the messages of its definition
+            pub type Client<T = fdp_mqtt_client::MqttTransport> = fdp_mqtt_client::AppClient<App, T>;
 
-struct Submodules {
-    // Message declarations
-    broadcasted_events: ItemMod,
-    incoming_requests: ItemMod,
-    outgoing_responses: ItemMod,
-    // Message references
-    listened_events: ItemMod,
-    emitted_requests: ItemMod,
+            #handlers
+        }
+    }
 }
 
-/// Enforces that a module contains only the required public submodules
-fn enforce_submodules(module: &ItemMod) -> syn::Result<Submodules> {
-    let required_submodules: Vec<String> = vec![
-        "broadcasted_events",
-        "incoming_requests",
-        "outgoing_responses",
-        "listened_events",
-        "emitted_requests",
-    ]
-    .into_iter()
-    .map(String::from)
-    .collect();
-
-    // Error message closure for missing submodules
-    let missing_modules = |submodules: &Vec<String>| {
+/// Generates the Handlers trait of an app, with one method per listened event and incoming request,
+/// and the serve function registering them on the app's client
+fn get_handlers(events: &[Ident], requests: &[Ident]) -> proc_macro2::TokenStream {
+    let on_event: Vec<Ident> = events
+        .iter()
+        .map(|event| format_ident!("on_{}", to_snake_case(event)))
+        .collect();
+    let on_request: Vec<Ident> = requests
+        .iter()
+        .map(|request| format_ident!("handle_{}", to_snake_case(request)))
+        .collect();
+    let event_docs = events
+        .iter()
+        .map(|event| format!("Handles the listened `{}` events", event));
+    let request_docs = requests.iter().map(|request| {
         format!(
-            "The fdp::definition module is missing the following public submodules: {}",
-            submodules.join(", ")
+            "Handles the incoming `{}` requests, returning their response",
+            request
         )
-    };
+    });
 
-    // Error message closure for unexpected items
-    let unexpected_item = |ident: &str| {
-        format!(
-            "The fdp::definition module contains an unexpected item {}. It should only contain public submodules named: {:?}.",
-            ident,required_submodules
-        )
-    };
-
-    // Will hold the extracted submodules, which haven't been checked individually yet
-    struct OptionalSubmodules {
-        broadcasted_events: Option<ItemMod>,
-        incoming_requests: Option<ItemMod>,
-        outgoing_responses: Option<ItemMod>,
-        listened_events: Option<ItemMod>,
-        emitted_requests: Option<ItemMod>,
-    }
-
-    let mut submodules: OptionalSubmodules = OptionalSubmodules {
-        broadcasted_events: None,
-        incoming_requests: None,
-        outgoing_responses: None,
-        listened_events: None,
-        emitted_requests: None,
-    };
+    // Every handler is registered with its own reference to the shared handlers
+    let registrations = events
+        .iter()
+        .zip(&on_event)
+        .map(|(event, on_event)| {
+            quote! {{
+                let handlers = handlers.clone();
+                client.listen(move |event: listened_events::#event| {
+                    let handlers = handlers.clone();
+                    Box::pin(async move { handlers.#on_event(event).await })
+                }).await
+            }}
+        })
+        .chain(
+            requests
+                .iter()
+                .zip(&on_request)
+                .map(|(request, on_request)| {
+                    quote! {{
+                        let handlers = handlers.clone();
+                        client.respond(move |request: incoming_requests::#request| {
+                            let handlers = handlers.clone();
+                            Box::pin(async move { handlers.#on_request(request).await })
+                        }).await
+                    }}
+                }),
+        );
 
-    let items = match &module.content {
-        Some((_, items)) => items,
-        None => {
-            return Err(syn::Error::new_spanned(
-                &module,
-                missing_modules(&required_submodules),
-            ))
+    quote! {
+        /// Handles every event this app listens to and every request it responds to.
+        /// Listening to a new event or responding to a new request requires a new handler.
+        pub trait Handlers: Send + Sync + 'static {
+            #(
+                #[doc = #event_docs]
+                fn #on_event(&self, event: listened_events::#events)
+                    -> impl ::std::future::Future<Output = ()> + Send;
+            )*
+            #(
+                #[doc = #request_docs]
+                fn #on_request(&self, request: incoming_requests::#requests)
+                    -> impl ::std::future::Future<
+                        Output = <incoming_requests::#requests as fdp_common::mqtt::Request>::Response
+                    > + Send;
+            )*
         }
-    };
-
-    let mut found_submodules: Vec<String> = Vec::new();
-    for item in items {
-        if let syn::Item::Mod(item_mod) = item {
-            // Ensure the module is public
-            if !matches!(item_mod.vis, syn::Visibility::Public(_)) {
-                return Err(syn::Error::new_spanned(
-                    item_mod,
-                    "The submodule must be public",
-                ));
-            }
-
-            // Ensure the module has a valid name
-            let submodule_name = item_mod.ident.to_string();
-            if !required_submodules.contains(&submodule_name) {
-                return Err(syn::Error::new_spanned(item_mod, unexpected_item(&quote! { #item }.to_string())));
-            }
 
-            found_submodules.push(submodule_name.clone());
-
-            match submodule_name.as_str() {
-                "broadcasted_events" => submodules.broadcasted_events = Some(item_mod.clone()),
-                "incoming_requests" => submodules.incoming_requests = Some(item_mod.clone()),
-                "outgoing_responses" => submodules.outgoing_responses = Some(item_mod.clone()),
-                "listened_events" => submodules.listened_events = Some(item_mod.clone()),
-                "emitted_requests" => submodules.emitted_requests = Some(item_mod.clone()),
-                _ => {}
-            }
-        } else {
-            return Err(syn::Error::new_spanned(
-                item,
-                unexpected_item(&quote! { #item }.to_string()),
-            ));
+        /// Registers all the handlers on the client of this app.
+        /// They stay registered until the returned subscriptions are cancelled or dropped.
+        #[allow(unused_variables)]
+        pub async fn serve<T: fdp_mqtt_client::Transport, H: Handlers>(
+            client: &mut Client<T>,
+            handlers: H,
+        ) -> Vec<T::Subscription> {
+            let handlers = ::std::sync::Arc::new(handlers);
+            vec![#(#registrations),*]
         }
     }
+}
 
-    let missing_submodules: Vec<String> = required_submodules
-        .iter()
-        .filter(|&submodule| !found_submodules.contains(submodule))
-        .cloned()
-        .collect();
-    if !missing_submodules.is_empty() {
+/// Enforces that a module had a specific name
+fn enforce_module_name(module: &ItemMod, name: &str) -> syn::Result<()> {
+    if module.ident != name {
         return Err(syn::Error::new_spanned(
-            &module,
-            format!(
-                "The definition module is missing the following public submodules: {:?}",
-                missing_submodules
-            ),
+            &module.ident,
+            "The module must be named 'definition'",
         ));
     }
+    Ok(())
+}
 
-    // Unwrap all submodules and return them as a tuple
-    Ok(Submodules {
-        broadcasted_events: submodules.broadcasted_events.unwrap(),
-        incoming_requests: submodules.incoming_requests.unwrap(),
-        outgoing_responses: submodules.outgoing_responses.unwrap(),
-        listened_events: submodules.listened_events.unwrap(),
-        emitted_requests: submodules.emitted_requests.unwrap(),
-    })
+/// Rejects a fdp::client attribute left below fdp::definition, which would expand without it
+fn enforce_client_placement(module: &ItemMod) -> syn::Result<()> {
+    match module
+        .attrs
+        .iter()
+        .find(|attr| is_fdp_attribute(attr, "client"))
+    {
+        Some(attr) => Err(syn::Error::new_spanned(
+            attr,
+            "The fdp::client macro must be placed above the fdp::definition macro",
+        )),
+        None => Ok(()),
+    }
 }
 
+/// The expected shape of the definition module, with the items each of its submodules accepts
+fn definition_spec() -> ModuleSpec {
+    ModuleSpec {
+        name: "fdp::definition".to_string(),
+        submodules: vec![
+            BroadcastedEvents::spec().optional(),
+            IncomingRequests::spec().optional(),
+            OutgoingResponses::spec().optional(),
+            ListenedEvents::spec().optional(),
+            EmittedRequests::spec().optional(),
+        ],
+        open: false,
+        public_submodules: true,
+    }
+}
 
+/// Creates a submodule of the definition module from its checked items,
+/// or an empty one if the definition module doesn't contain it
+fn submodule<K: SubmoduleKind>(extraction: &Extraction) -> syn::Result<DefinitionSubmodule<K>> {
+    match extraction.submodule(K::NAME) {
+        Some(submodule) => DefinitionSubmodule::from_items(submodule.module, &submodule.items),
+        None => {
+            let name = format_ident!("{}", K::NAME);
+            DefinitionSubmodule::from_items(&parse_quote! { pub mod #name {} }, &[])
+        }
+    }
+}
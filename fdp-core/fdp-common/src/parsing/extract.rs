@@ -0,0 +1,548 @@
+//! Parsing logic for the fdp::extract macro, extracting the submodules of a module according to a declarative spec
+
+use crate::extract::{ExtractedInformation, ExtractedItem, ExtractedModule, ItemKind};
+use crate::parsing::modules::get_use_tree_name;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    ext::IdentExt, parenthesized, parse::Parse, punctuated::Punctuated, visit::Visit, Ident, Item,
+    ItemMod, Token, Visibility,
+};
+
+/// Validates an item accepted in a submodule, e.g. that a message declaration is public
+pub type ItemValidator = Box<dyn Fn(&Item) -> syn::Result<()>>;
+
+/// The expected shape of a module: the submodules it may contain, and the items they accept
+pub struct ModuleSpec {
+    /// The name of the module in the errors, e.g. `fdp::definition`
+    pub name: String,
+    /// The submodules the module may contain
+    pub submodules: Vec<SubmoduleSpec>,
+    /// Whether the module accepts submodules that are not in the spec, with any items
+    pub open: bool,
+    /// Whether the submodules must be public
+    pub public_submodules: bool,
+}
+
+/// The expected shape of a submodule
+pub struct SubmoduleSpec {
+    pub name: String,
+    /// Whether the module must contain the submodule
+    pub required: bool,
+    /// The kinds of items the submodule accepts, the others are rejected
+    pub accepts: Vec<ItemKind>,
+    /// Validates every accepted item
+    pub validators: Vec<ItemValidator>,
+    /// The error reported for a rejected item, listing the accepted kinds by default
+    pub rejection: Option<String>,
+}
+
+impl SubmoduleSpec {
+    /// A required submodule accepting the given kinds of items
+    pub fn new(name: &str, accepts: &[ItemKind]) -> Self {
+        SubmoduleSpec {
+            name: name.to_string(),
+            required: true,
+            accepts: accepts.to_vec(),
+            validators: Vec::new(),
+            rejection: None,
+        }
+    }
+
+    /// Makes the submodule optional, a missing submodule being treated as empty
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    /// Adds a validator of the accepted items
+    pub fn validate(mut self, validator: impl Fn(&Item) -> syn::Result<()> + 'static) -> Self {
+        self.validators.push(Box::new(validator));
+        self
+    }
+
+    /// Reports the rejected items with the given error
+    pub fn reject_with(mut self, rejection: &str) -> Self {
+        self.rejection = Some(rejection.to_string());
+        self
+    }
+
+    /// Checks the items of the submodule, returning them if they are all accepted and valid.
+    /// The errors of every item are reported together.
+    pub fn check<'a>(&self, module: &'a ItemMod) -> syn::Result<Vec<&'a Item>> {
+        let items = module_items(module);
+        let mut errors = Errors::default();
+        for item in &items {
+            let accepted = item_kind(item).is_some_and(|kind| self.accepts.contains(&kind));
+            if !accepted {
+                errors.push(syn::Error::new_spanned(item, self.rejection()));
+                continue;
+            }
+            for validator in &self.validators {
+                if let Err(error) = validator(item) {
+                    errors.push(error);
+                }
+            }
+        }
+        errors.into_result(items)
+    }
+
+    fn rejection(&self) -> String {
+        self.rejection.clone().unwrap_or_else(|| {
+            let kinds: Vec<&str> = self.accepts.iter().map(ItemKind::keyword).collect();
+            format!(
+                "Only {} items are allowed in the '{}' submodule.",
+                kinds.join(", "),
+                self.name
+            )
+        })
+    }
+}
+
+/// The submodules extracted from a module
+pub struct Extraction<'a> {
+    pub module: &'a ItemMod,
+    /// The submodules, in declaration order
+    pub submodules: Vec<ExtractedSubmodule<'a>>,
+}
+
+/// A submodule extracted from a module, with its checked items
+pub struct ExtractedSubmodule<'a> {
+    pub module: &'a ItemMod,
+    pub items: Vec<&'a Item>,
+}
+
+impl<'a> Extraction<'a> {
+    /// Returns the submodule with the given name, if the module contains it
+    pub fn submodule(&self, name: &str) -> Option<&ExtractedSubmodule<'a>> {
+        self.submodules
+            .iter()
+            .find(|submodule| submodule.module.ident == name)
+    }
+
+    /// The information about the extracted submodules and their items
+    pub fn information(&self) -> ExtractedInformation {
+        ExtractedInformation {
+            module: self.module.ident.to_string(),
+            submodules: self
+                .submodules
+                .iter()
+                .map(|submodule| ExtractedModule {
+                    name: submodule.module.ident.to_string(),
+                    items: submodule
+                        .items
+                        .iter()
+                        .filter_map(|item| extracted_item(item))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl ModuleSpec {
+    /// A module accepting any submodule with any items
+    pub fn open(name: &str) -> Self {
+        ModuleSpec {
+            name: name.to_string(),
+            submodules: Vec::new(),
+            open: true,
+            public_submodules: false,
+        }
+    }
+
+    /// Extracts the submodules of a module, checking them against the spec.
+    /// The errors of every submodule are reported together.
+    pub fn extract<'a>(&self, module: &'a ItemMod) -> syn::Result<Extraction<'a>> {
+        let mut extractor = Extractor {
+            spec: self,
+            depth: 0,
+            submodules: Vec::new(),
+            errors: Errors::default(),
+        };
+        extractor.visit_item_mod(module);
+
+        let Extractor {
+            submodules,
+            mut errors,
+            ..
+        } = extractor;
+        // A submodule with errors is reported on its own, not as missing
+        let declared = |name: &str| {
+            module_items(module)
+                .into_iter()
+                .any(|item| matches!(item, Item::Mod(submodule) if submodule.ident == name))
+        };
+        let missing: Vec<&str> = self
+            .submodules
+            .iter()
+            .filter(|spec| spec.required && !declared(&spec.name))
+            .map(|spec| spec.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            errors.push(syn::Error::new_spanned(
+                module,
+                format!(
+                    "The {} module is missing the following public submodules: {}",
+                    self.name,
+                    missing.join(", ")
+                ),
+            ));
+        }
+        errors.into_result(Extraction { module, submodules })
+    }
+
+    fn submodule_spec(&self, name: &Ident) -> Option<&SubmoduleSpec> {
+        self.submodules.iter().find(|spec| name == &spec.name)
+    }
+
+    fn unexpected_item(&self, item: &Item) -> String {
+        let names: Vec<&str> = self
+            .submodules
+            .iter()
+            .map(|spec| spec.name.as_str())
+            .collect();
+        let public = if self.public_submodules {
+            "public "
+        } else {
+            ""
+        };
+        // An unexpected submodule is named, rather than quoted with all its items
+        let (item, suggestion) = match item {
+            Item::Mod(module) => (
+                format!("submodule '{}'", module.ident),
+                closest_name(&module.ident.to_string(), &names)
+                    .map(|name| format!(" Did you mean '{}'?", name)),
+            ),
+            item => (format!("item {}", quote! { #item }), None),
+        };
+        let mut error = format!(
+            "The {} module contains an unexpected {}. It should only contain {}submodules named: {:?}.",
+            self.name, item, public, names
+        );
+        error.push_str(&suggestion.unwrap_or_default());
+        error
+    }
+}
+
+/// Visits a module and its direct submodules, checking them against a spec
+struct Extractor<'s, 'a> {
+    spec: &'s ModuleSpec,
+    /// The depth of the visited module, the extracted module being at depth 0
+    depth: usize,
+    submodules: Vec<ExtractedSubmodule<'a>>,
+    errors: Errors,
+}
+
+impl<'a> Visit<'a> for Extractor<'_, 'a> {
+    fn visit_item_mod(&mut self, module: &'a ItemMod) {
+        if self.depth > 0 {
+            self.extract_submodule(module);
+            return;
+        }
+        self.depth += 1;
+        for item in module_items(module) {
+            self.visit_item(item);
+        }
+        self.depth -= 1;
+    }
+
+    fn visit_item(&mut self, item: &'a Item) {
+        match item {
+            Item::Mod(module) => self.visit_item_mod(module),
+            // Other items are only kept in open modules
+            _ if self.spec.open => {}
+            _ => self.errors.push(syn::Error::new_spanned(
+                item,
+                self.spec.unexpected_item(item),
+            )),
+        }
+    }
+}
+
+impl<'a> Extractor<'_, 'a> {
+    fn extract_submodule(&mut self, module: &'a ItemMod) {
+        if self.spec.public_submodules && !matches!(module.vis, Visibility::Public(_)) {
+            self.errors.push(syn::Error::new_spanned(
+                module,
+                "The submodule must be public",
+            ));
+            return;
+        }
+        let items = match self.spec.submodule_spec(&module.ident) {
+            Some(spec) => spec.check(module),
+            None if self.spec.open => Ok(module_items(module)),
+            None => Err(syn::Error::new_spanned(
+                module,
+                self.spec.unexpected_item(&Item::Mod(module.clone())),
+            )),
+        };
+        match items {
+            Ok(items) => self.submodules.push(ExtractedSubmodule { module, items }),
+            Err(error) => self.errors.push(error),
+        }
+    }
+}
+
+/// The errors found while extracting a module, reported together
+#[derive(Default)]
+struct Errors(Option<syn::Error>);
+
+impl Errors {
+    fn push(&mut self, error: syn::Error) {
+        match &mut self.0 {
+            Some(errors) => errors.combine(error),
+            None => self.0 = Some(error),
+        }
+    }
+
+    fn into_result<T>(self, value: T) -> syn::Result<T> {
+        match self.0 {
+            Some(errors) => Err(errors),
+            None => Ok(value),
+        }
+    }
+}
+
+/// Returns the name closest to a misspelled one, if it is close enough to be a typo
+fn closest_name<'n>(name: &str, names: &[&'n str]) -> Option<&'n str> {
+    names
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two names: the number of inserted, removed or replaced characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let replaced = previous[j] + usize::from(a != *b);
+            current.push(replaced.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the direct items of a module
+fn module_items(module: &ItemMod) -> Vec<&Item> {
+    match &module.content {
+        Some((_, items)) => items.iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the kind of an item, if it is one of the extracted kinds
+pub fn item_kind(item: &Item) -> Option<ItemKind> {
+    Some(match item {
+        Item::Const(_) => ItemKind::Const,
+        Item::Enum(_) => ItemKind::Enum,
+        Item::Fn(_) => ItemKind::Fn,
+        Item::Impl(_) => ItemKind::Impl,
+        Item::Macro(_) => ItemKind::Macro,
+        Item::Mod(_) => ItemKind::Mod,
+        Item::Static(_) => ItemKind::Static,
+        Item::Struct(_) => ItemKind::Struct,
+        Item::Trait(_) => ItemKind::Trait,
+        Item::Type(_) => ItemKind::Type,
+        Item::Use(_) => ItemKind::Use,
+        _ => return None,
+    })
+}
+
+/// The information about an item
+fn extracted_item(item: &Item) -> Option<ExtractedItem> {
+    let name = |tokens: &dyn ToTokens| tokens.to_token_stream().to_string().replace(' ', "");
+    let (ident, vis) = match item {
+        Item::Const(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Enum(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Fn(item) => (item.sig.ident.to_string(), Some(&item.vis)),
+        Item::Impl(item) => (name(&item.self_ty), None),
+        Item::Macro(item) => (name(&item.mac.path), None),
+        Item::Mod(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Static(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Struct(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Trait(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Type(item) => (item.ident.to_string(), Some(&item.vis)),
+        Item::Use(item) => (
+            get_use_tree_name(&item.tree)
+                .map(|ident| ident.to_string())
+                .unwrap_or_else(|| name(&item.tree)),
+            Some(&item.vis),
+        ),
+        _ => return None,
+    };
+    Some(ExtractedItem {
+        kind: item_kind(item)?,
+        ident,
+        public: matches!(vis, Some(Visibility::Public(_))),
+        path: match item {
+            Item::Use(item) => Some(name(&item.tree)),
+            _ => None,
+        },
+    })
+}
+
+/// The spec of one submodule in the arguments of the fdp::extract macro, e.g. `events(struct, impl)`
+struct SubmoduleArg {
+    name: Ident,
+    kinds: Vec<ItemKind>,
+}
+
+impl Parse for SubmoduleArg {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        let content;
+        parenthesized!(content in input);
+        let keywords =
+            Punctuated::<Ident, Token![,]>::parse_terminated_with(&content, Ident::parse_any)?;
+        let kinds = keywords
+            .iter()
+            .map(|keyword| {
+                ItemKind::from_keyword(&keyword.to_string()).ok_or_else(|| {
+                    let keywords: Vec<&str> = ItemKind::ALL.iter().map(ItemKind::keyword).collect();
+                    syn::Error::new_spanned(
+                        keyword,
+                        format!(
+                            "Unknown item kind, expected one of: {}",
+                            keywords.join(", ")
+                        ),
+                    )
+                })
+            })
+            .collect::<syn::Result<_>>()?;
+        Ok(SubmoduleArg { name, kinds })
+    }
+}
+
+/// The arguments of `#[fdp::extract(events(struct, impl), references(use))]`, listing the required
+/// submodules and the kinds of items they accept. Without arguments, any submodule is accepted.
+/// The per-item validators of a `SubmoduleSpec` can't be declared here, only set programmatically.
+pub struct ExtractArgs {
+    submodules: Vec<SubmoduleArg>,
+}
+
+impl Parse for ExtractArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let submodules = Punctuated::<SubmoduleArg, Token![,]>::parse_terminated(input)?;
+        Ok(ExtractArgs {
+            submodules: submodules.into_iter().collect(),
+        })
+    }
+}
+
+impl ExtractArgs {
+    /// The spec of the module declared by the arguments
+    pub fn spec(&self) -> ModuleSpec {
+        let mut spec = ModuleSpec::open("fdp::extract");
+        if !self.submodules.is_empty() {
+            spec.open = false;
+            spec.submodules = self
+                .submodules
+                .iter()
+                .map(|arg| SubmoduleSpec::new(&arg.name.to_string(), &arg.kinds))
+                .collect();
+        }
+        spec
+    }
+}
+
+/// Handles the fdp::extract macro: the module is kept as is, re-exported at its parent level,
+/// with a `get_extracted_information` function returning its extracted information
+pub struct ExtractModule {
+    pub module: ItemMod,
+    pub information: ExtractedInformation,
+}
+
+impl ExtractModule {
+    /// Extracts a module according to a spec
+    pub fn new(module: ItemMod, spec: &ModuleSpec) -> syn::Result<Self> {
+        let information = spec.extract(&module)?.information();
+        Ok(ExtractModule {
+            module,
+            information,
+        })
+    }
+}
+
+impl ToTokens for ExtractModule {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let ItemMod {
+            attrs,
+            vis,
+            ident,
+            content,
+            ..
+        } = &self.module;
+        let items = content.iter().flat_map(|(_, items)| items);
+        let information = &self.information;
+        tokens.extend(quote! {
+            #[doc(inline)]
+            #[allow(unused_imports)]
+            #vis use #ident::*;
+
+            #(#attrs)*
+            #[doc(hidden)]
+            #vis mod #ident {
+                #(#items)*
+
+                /// Returns the information extracted from this module by the fdp::extract macro
+                #[doc(hidden)]
+                pub fn get_extracted_information() -> fdp_common::extract::ExtractedInformation {
+                    #information
+                }
+            }
+        });
+    }
+}
+
+impl ToTokens for ExtractedInformation {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let module = &self.module;
+        let submodules = &self.submodules;
+        tokens.extend(quote! {
+            fdp_common::extract::ExtractedInformation {
+                module: #module.to_string(),
+                submodules: vec![#(#submodules),*],
+            }
+        });
+    }
+}
+
+impl ToTokens for ExtractedModule {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let name = &self.name;
+        let items = &self.items;
+        tokens.extend(quote! {
+            fdp_common::extract::ExtractedModule {
+                name: #name.to_string(),
+                items: vec![#(#items),*],
+            }
+        });
+    }
+}
+
+impl ToTokens for ExtractedItem {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let kind = format_ident!("{}", format!("{:?}", self.kind));
+        let ident = &self.ident;
+        let public = self.public;
+        let path = match &self.path {
+            Some(path) => quote! { Some(#path.to_string()) },
+            None => quote! { None },
+        };
+        tokens.extend(quote! {
+            fdp_common::extract::ExtractedItem {
+                kind: fdp_common::extract::ItemKind::#kind,
+                ident: #ident.to_string(),
+                public: #public,
+                path: #path,
+            }
+        });
+    }
+}
@@ -9,19 +9,16 @@ macro_rules! p {
 }
 
 pub fn parse_file(contents: &str) {
-    let file = syn::parse_file(&contents).unwrap();
+    let file = syn::parse_file(contents).unwrap();
     for item in file.items {
-        match item {
-            syn::Item::Mod(item_mod) => {
-                if item_mod.ident == "definition" {
-                    // We have the pub mod definition {} module
-
-                    let app_definition: AppDefinitionModule =
-                        syn::parse2(quote! { #item_mod }).unwrap();
-                    p!("{}", quote! { #app_definition });
-                }
+        if let syn::Item::Mod(item_mod) = item {
+            if item_mod.ident == "definition" {
+                // We have the pub mod definition {} module
+
+                let app_definition: AppDefinitionModule =
+                    syn::parse2(quote! { #item_mod }).unwrap();
+                p!("{}", quote! { #app_definition });
             }
-            _ => {}
         }
     }
 }
@@ -1,8 +1,10 @@
 //! Contains parsing logic for FDP definition modules, used by the fdp-definition-macros
 
 pub mod definition;
+pub mod extract;
 pub mod modules;
 pub mod file;
+pub mod topic;
 
 #[cfg(test)]
 mod tests;
\ No newline at end of file
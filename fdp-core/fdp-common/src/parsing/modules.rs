@@ -1,221 +1,269 @@
 //! Parsing logic for the submodules in a FDP app definition module
 
+use crate::extract::ItemKind;
 use crate::info::MessageReferenceInfo;
+use crate::parsing::extract::SubmoduleSpec;
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{parse::Parse, Item, ItemMod};
+use std::marker::PhantomData;
+use syn::{parse::Parse, Attribute, Ident, Item, ItemMod, UseTree, Visibility};
 
-/// The broadcast_messages module contains message declarations
-pub struct BroadcastedEventsModule {
-    pub module: ItemMod,
-    pub gen: TokenStream,
-}
+/// A kind of submodule in a FDP app definition module.
+/// A new kind only declares its name and content, the parsing and validation being derived from them.
+pub trait SubmoduleKind {
+    /// The name of the submodule
+    const NAME: &'static str;
+    /// What the submodule contains
+    const CONTENT: SubmoduleContent;
 
-impl Parse for BroadcastedEventsModule {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let module: ItemMod = input.parse()?;
-        let module_items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_declaration_module(&module_items)?;
-        let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
+    /// The expected shape of the submodule
+    fn spec() -> SubmoduleSpec {
+        match Self::CONTENT {
+            SubmoduleContent::Declarations => {
+                SubmoduleSpec::new(Self::NAME, &SUPPORTED_DECLARATION_ITEMS)
+                    .validate(validate_declaration)
+                    .reject_with(
+                        "Only public struct and #[fdp::topic] enum messages, and the enum, \
+                        #[fdp::shared] struct, type, const and impl items supporting them, \
+                        are allowed in this module.",
+                    )
+            }
+            SubmoduleContent::References(module) => {
+                SubmoduleSpec::new(Self::NAME, &[ItemKind::Use])
+                    .validate(move |item| match item {
+                        Item::Use(item_use) => parse_item_use_tree(item_use, module).map(|_| ()),
+                        _ => Ok(()),
+                    })
+                    .reject_with("Only public use references are allowed in this module.")
+            }
+        }
     }
 }
 
-impl ToTokens for BroadcastedEventsModule {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        self.module.to_tokens(tokens);
-    }
+/// The items a declaration submodule accepts: the messages, and the items supporting them
+const SUPPORTED_DECLARATION_ITEMS: [ItemKind; 5] = [
+    ItemKind::Struct,
+    ItemKind::Enum,
+    ItemKind::Type,
+    ItemKind::Const,
+    ItemKind::Impl,
+];
+
+/// What a submodule of a FDP app definition module contains
+pub enum SubmoduleContent {
+    /// Message declarations, as public structs or tagged unions, with the items supporting them
+    Declarations,
+    /// Message references, as `use` items of messages declared in the given submodule of other apps
+    References(&'static str),
 }
 
-/// The listened_messages module contains message references
-pub struct ListenedEventsModule {
+/// A submodule of a FDP app definition module, with the generated information of its messages
+pub struct DefinitionSubmodule<K> {
     pub module: ItemMod,
     pub gen: TokenStream,
+    kind: PhantomData<K>,
 }
 
-impl Parse for ListenedEventsModule {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let module: ItemMod = input.parse()?;
-        let items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_reference_module(&items, "broadcasted_events")?;
+impl<K: SubmoduleKind> DefinitionSubmodule<K> {
+    /// Creates the submodule from its checked items
+    pub fn from_items(module: &ItemMod, items: &[&Item]) -> syn::Result<Self> {
+        let mut gen_items = Vec::new();
+        for item in items {
+            match (item, K::CONTENT) {
+                (_, SubmoduleContent::Declarations) => {
+                    if let Some((ident, _)) = get_message_declaration(item) {
+                        gen_items.push(get_gen_for_declaration(ident))
+                    }
+                }
+                (Item::Use(item_use), SubmoduleContent::References(module)) => gen_items.push(
+                    get_gen_for_reference(&parse_item_use_tree(item_use, module)?),
+                ),
+                _ => {}
+            }
+        }
         let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
+        Ok(DefinitionSubmodule {
+            module: module.clone(),
+            gen,
+            kind: PhantomData,
+        })
     }
 }
 
-impl ToTokens for ListenedEventsModule {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        self.module.to_tokens(tokens);
-    }
-}
-
-/// The outgoing_requests module contains message declarations
-pub struct EmittedRequestsModule {
-    pub module: ItemMod,
-    pub gen: TokenStream,
-}
-
-impl Parse for EmittedRequestsModule {
+impl<K: SubmoduleKind> Parse for DefinitionSubmodule<K> {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
         let module: ItemMod = input.parse()?;
-        let module_items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_reference_module(&module_items, "incoming_requests")?;
-        let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
+        Self::from_items(&module, &K::spec().check(&module)?)
     }
 }
 
-impl ToTokens for EmittedRequestsModule {
+impl<K> ToTokens for DefinitionSubmodule<K> {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         self.module.to_tokens(tokens);
     }
 }
 
-/// The incoming_responses module contains message declarations
-pub struct IncomingResponsesModule {
-    pub module: ItemMod,
-    pub gen: TokenStream,
-}
+macro_rules! submodule_kind {
+    ($(#[$doc:meta])* $kind:ident, $module:ident, $name:literal, $content:expr) => {
+        $(#[$doc])*
+        pub struct $kind;
 
-impl Parse for IncomingResponsesModule {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let module: ItemMod = input.parse()?;
-        let module_items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_reference_module(&module_items, "outgoing_responses")?;
-        let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
-    }
+        impl SubmoduleKind for $kind {
+            const NAME: &'static str = $name;
+            const CONTENT: SubmoduleContent = $content;
+        }
+
+        pub type $module = DefinitionSubmodule<$kind>;
+    };
 }
 
-impl ToTokens for IncomingResponsesModule {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        self.module.to_tokens(tokens);
+submodule_kind!(
+    /// The broadcasted_events module contains message declarations
+    BroadcastedEvents,
+    BroadcastedEventsModule,
+    "broadcasted_events",
+    SubmoduleContent::Declarations
+);
+submodule_kind!(
+    /// The listened_events module contains message references
+    ListenedEvents,
+    ListenedEventsModule,
+    "listened_events",
+    SubmoduleContent::References("broadcasted_events")
+);
+submodule_kind!(
+    /// The emitted_requests module contains message references
+    EmittedRequests,
+    EmittedRequestsModule,
+    "emitted_requests",
+    SubmoduleContent::References("incoming_requests")
+);
+submodule_kind!(
+    /// The incoming_responses module contains message references
+    IncomingResponses,
+    IncomingResponsesModule,
+    "incoming_responses",
+    SubmoduleContent::References("outgoing_responses")
+);
+submodule_kind!(
+    /// The incoming_requests module contains message declarations
+    IncomingRequests,
+    IncomingRequestsModule,
+    "incoming_requests",
+    SubmoduleContent::Declarations
+);
+submodule_kind!(
+    /// The outgoing_responses module contains message declarations
+    OutgoingResponses,
+    OutgoingResponsesModule,
+    "outgoing_responses",
+    SubmoduleContent::Declarations
+);
+
+/// Returns a list of the direct items in a module.
+fn get_direct_module_items(item: &ItemMod) -> Vec<Item> {
+    let input = item.clone();
+    if let Some((_, content)) = input.content {
+        content
+    } else {
+        Vec::new()
     }
 }
 
-/// The incoming_requests module contains message references
-pub struct IncomingRequestsModule {
-    pub module: ItemMod,
-    pub gen: TokenStream,
+/// Returns the identifiers of the messages declared or referenced in a module.
+pub fn get_message_idents(module: &ItemMod) -> Vec<Ident> {
+    get_direct_module_items(module)
+        .iter()
+        .filter_map(|item| match item {
+            Item::Use(item_use) => get_use_tree_name(&item_use.tree),
+            item => get_message_declaration(item).map(|(ident, _)| ident.clone()),
+        })
+        .collect()
 }
 
-impl Parse for IncomingRequestsModule {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let module: ItemMod = input.parse()?;
-        let items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_declaration_module(&items)?;
-        let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
+/// Returns the name a use tree imports, e.g. `Message` for `crate::apps::app_1::broadcasted_events::Message`.
+pub(crate) fn get_use_tree_name(tree: &UseTree) -> Option<Ident> {
+    match tree {
+        UseTree::Path(path) => get_use_tree_name(&path.tree),
+        UseTree::Name(name) => Some(name.ident.clone()),
+        _ => None,
     }
 }
 
-impl ToTokens for IncomingRequestsModule {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        self.module.to_tokens(tokens);
+/// Converts a message identifier to snake case, e.g. `square_request` for `SquareRequest`.
+pub fn to_snake_case(ident: &Ident) -> String {
+    let chars: Vec<char> = ident.to_string().chars().collect();
+    let mut name = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let after_lower = !chars[i - 1].is_uppercase() && chars[i - 1] != '_';
+            let before_lower =
+                chars[i - 1].is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if after_lower || before_lower {
+                name.push('_');
+            }
+        }
+        name.extend(c.to_lowercase());
     }
+    name
 }
 
-/// The outgoing_responses module contains message references
-pub struct OutgoingResponsesModule {
-    pub module: ItemMod,
-    pub gen: TokenStream,
-}
-
-impl Parse for OutgoingResponsesModule {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let module: ItemMod = input.parse()?;
-        let items = get_direct_module_items(&module);
-        let gen_items = get_gen_for_declaration_module(&items)?;
-        let gen = quote! { vec![#( #gen_items ),*] };
-        Ok(Self { module, gen })
+/// Returns the identifier and visibility of the message an item of a declaration submodule declares.
+/// A message is a struct, or an enum with the `#[fdp::topic]` attribute, e.g. a union tagged with serde.
+/// The other items support the messages, e.g. an enum or a `#[fdp::shared]` struct used as the type of a field,
+/// and their schemas are included in the schemas of the messages using them.
+fn get_message_declaration(item: &Item) -> Option<(&Ident, &Visibility)> {
+    match item {
+        Item::Struct(item_struct) if !has_fdp_attribute(&item_struct.attrs, "shared") => {
+            Some((&item_struct.ident, &item_struct.vis))
+        }
+        Item::Enum(item_enum) if has_fdp_attribute(&item_enum.attrs, "topic") => {
+            Some((&item_enum.ident, &item_enum.vis))
+        }
+        _ => None,
     }
 }
 
-impl ToTokens for OutgoingResponsesModule {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        self.module.to_tokens(tokens);
-    }
+/// Whether the attributes of an item include a FDP macro, e.g. `#[fdp::shared]`
+fn has_fdp_attribute(attrs: &[Attribute], name: &str) -> bool {
+    attrs.iter().any(|attr| is_fdp_attribute(attr, name))
 }
 
-/// Returns a list of the direct items in a module.
-fn get_direct_module_items(item: &ItemMod) -> Vec<Item> {
-    let input = item.clone();
-    let content = if let Some((_, content)) = input.content {
-        content
-    } else {
-        Vec::new()
-    };
-    content
+/// Whether an attribute is a FDP macro, named by its full path, e.g. `#[fdp::client]` for `client`
+pub fn is_fdp_attribute(attr: &Attribute, name: &str) -> bool {
+    let segments: Vec<String> = attr
+        .path()
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+    segments == ["fdp", name]
 }
 
-/// Returns a list of the generated items for the broadcast_messages module.
-fn get_gen_for_declaration_module(module_items: &Vec<Item>) -> syn::Result<Vec<TokenStream>> {
-    let mut gen_items = Vec::new();
-    for item in module_items {
-        match item {
-            Item::Struct(item_struct) => {
-                let ident = &item_struct.ident;
-                if let syn::Visibility::Public(_) = item_struct.vis {
-                    gen_items.push(quote! {
-                        MessageDeclarationInfo {
-                            identifier: stringify!(#ident).to_string(),
-                            topic: <#ident as fdp_common::mqtt::Message>::topic().to_string(),
-                            schema: schemars::schema_for!(#ident),
-                        }
-                    });
-                } else {
-                    return Err(syn::Error::new_spanned(
-                        &item_struct,
-                        "A message declaration must be public.",
-                    ));
-                }
-            }
-            Item::Impl(_) => {}
-            _ => {
-                return Err(syn::Error::new_spanned(
-                    item,
-                    "Only public struct items are allowed in this module.",
-                ));
-            }
-        }
+/// Validates a message declaration, which must be public
+fn validate_declaration(item: &Item) -> syn::Result<()> {
+    match get_message_declaration(item) {
+        Some((_, vis)) if !matches!(vis, Visibility::Public(_)) => Err(syn::Error::new_spanned(
+            item,
+            "A message declaration must be public.",
+        )),
+        _ => Ok(()),
     }
-    Ok(gen_items)
 }
 
-fn _get_gen_for_request_declaration_module(module_items: &Vec<Item>) -> syn::Result<Vec<TokenStream>> {
-    let mut gen_items = Vec::new();
-    for item in module_items {
-        match item {
-            Item::Struct(item_struct) => {
-                let ident = &item_struct.ident;
-                if let syn::Visibility::Public(_) = item_struct.vis {
-                    gen_items.push(quote! {
-                        RequestDeclarationInfo {
-                            identifier: stringify!(#ident).to_string(),
-                            topic: <#ident as fdp_common::mqtt::Message>::topic().to_string(),
-                            schema: schemars::schema_for!(#ident),
-                            response: ResponseReferenceInfo {
-                                identifier: stringify!(<#ident as fdp_common::mqtt::Request>::Response),
-                            }
-                        }
-                    });
-                } else {
-                    return Err(syn::Error::new_spanned(
-                        &item_struct,
-                        "A message declaration must be public.",
-                    ));
-                }
-            }
-            Item::Impl(_) => {}
-            _ => {
-                return Err(syn::Error::new_spanned(
-                    item,
-                    "Only public struct items are allowed in this module.",
-                ));
-            }
+/// Returns the generated information of a message declaration
+fn get_gen_for_declaration(ident: &Ident) -> TokenStream {
+    quote! {
+        MessageDeclarationInfo {
+            identifier: stringify!(#ident).to_string(),
+            topic: <#ident as fdp_common::mqtt::Message>::topic().to_string(),
+            schema: schemars::schema_for!(#ident),
+            qos: <#ident as fdp_common::mqtt::Message>::qos(),
+            retain: <#ident as fdp_common::mqtt::Message>::retain(),
+            codec: <#ident as fdp_common::mqtt::Message>::codec().to_string(),
+            version: <#ident as fdp_common::mqtt::Message>::version(),
         }
     }
-    Ok(gen_items)
 }
 
 fn parse_item_use_tree(
@@ -289,35 +337,18 @@ fn parse_item_use_tree(
     })
 }
 
-fn get_gen_for_reference_module(
-    module_items: &Vec<Item>,
-    submodule_name: &str,
-) -> syn::Result<Vec<TokenStream>> {
-    let mut gen_items = Vec::new();
-    for item in module_items {
-        match item {
-            Item::Use(use_item) => {
-                let MessageReferenceInfo {
-                    identifier,
-                    app_name,
-                    module,
-                } = parse_item_use_tree(&use_item, submodule_name)?;
-
-                gen_items.push(quote! {
-                    MessageReferenceInfo {
-                        identifier: #identifier.to_string(),
-                        app_name: #app_name.to_string(),
-                        module: #module.to_string(),
-                    }
-                });
-            }
-            _ => {
-                return Err(syn::Error::new_spanned(
-                    item,
-                    "Only public use references are allowed in this module.",
-                ));
-            }
+/// Returns the generated information of a message reference
+fn get_gen_for_reference(reference: &MessageReferenceInfo) -> TokenStream {
+    let MessageReferenceInfo {
+        identifier,
+        app_name,
+        module,
+    } = reference;
+    quote! {
+        MessageReferenceInfo {
+            identifier: #identifier.to_string(),
+            app_name: #app_name.to_string(),
+            module: #module.to_string(),
         }
     }
-    Ok(gen_items)
 }
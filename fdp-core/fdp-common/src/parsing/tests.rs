@@ -7,6 +7,7 @@ use crate::parsing::modules::{
     OutgoingResponsesModule,
     EmittedRequestsModule,
 };
+use crate::parsing::topic::TopicArgs;
 
 #[test]
 fn test_parse_app_definition_module() {
@@ -52,6 +53,72 @@ fn test_parse_broadcasted_events_module() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_parse_supporting_items() {
+    use crate::parsing::modules::get_message_idents;
+
+    let input = parse_quote! {
+        pub mod broadcasted_events {
+            const MAX: u8 = 3;
+            pub type Celsius = f32;
+            pub enum Mode {
+                Off,
+                #[cfg(feature = "boost")]
+                Boost,
+            }
+            #[fdp::shared]
+            struct Room {
+                target: Celsius,
+            }
+            pub struct Event1 {
+                mode: Mode,
+                rooms: Vec<Room>,
+            }
+            impl Event1 {}
+        }
+    };
+
+    // Only the messages are declared
+    let result: BroadcastedEventsModule = syn::parse2(input).unwrap();
+    assert_eq!(get_message_idents(&result.module), vec!["Event1"]);
+    assert_eq!(result.gen.to_string().matches("MessageDeclarationInfo").count(), 1);
+}
+
+#[test]
+fn test_parse_enum_messages() {
+    use crate::parsing::modules::get_message_idents;
+
+    let input = parse_quote! {
+        pub mod incoming_requests {
+            pub enum Unit {
+                Meters,
+                Feet,
+            }
+            #[fdp::topic("app_1/distance")]
+            #[serde(tag = "kind")]
+            pub enum Distance {
+                Between { from: String, to: String },
+                Along { path: Vec<String>, unit: Unit },
+            }
+        }
+    };
+
+    // Only the enums with a topic are messages
+    let result: IncomingRequestsModule = syn::parse2(input).unwrap();
+    assert_eq!(get_message_idents(&result.module), vec!["Distance"]);
+
+    let input = parse_quote! {
+        pub mod incoming_requests {
+            #[fdp::topic("app_1/distance")]
+            enum Distance {
+                Between { from: String, to: String },
+            }
+        }
+    };
+    let result: syn::Result<IncomingRequestsModule> = syn::parse2(input);
+    assert!(result.is_err());
+}
+
 #[test]
 fn test_parse_listened_events_module() {
     let input = parse_quote! {
@@ -122,6 +189,52 @@ fn test_invalid_app_definition_module() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_parse_partial_app_definition_module() {
+    let input = parse_quote! {
+        pub mod definition {
+            pub mod broadcasted_events {
+                pub struct Event1;
+            }
+        }
+    };
+
+    // The missing submodules are empty
+    let result: AppDefinitionModule = syn::parse2(input).unwrap();
+    assert_eq!(result.listened_events.module.ident, "listened_events");
+    assert_eq!(result.listened_events.gen.to_string(), "vec ! []");
+}
+
+#[test]
+fn test_misspelled_app_definition_submodule() {
+    let input = parse_quote! {
+        pub mod definition {
+            pub mod broadcast_events {}
+        }
+    };
+
+    let error = syn::parse2::<AppDefinitionModule>(input).err().unwrap();
+    assert!(error.to_string().ends_with("Did you mean 'broadcasted_events'?"), "{}", error);
+}
+
+#[test]
+fn test_client_placement() {
+    // fdp::client is expanded before fdp::definition only when placed above it
+    let input = parse_quote! {
+        #[fdp::client]
+        pub mod definition {}
+    };
+    let error = syn::parse2::<AppDefinitionModule>(input).err().unwrap();
+    assert!(error.to_string().contains("must be placed above"), "{}", error);
+
+    // Other macros named client are not concerned
+    let input = parse_quote! {
+        #[my_crate::client]
+        pub mod definition {}
+    };
+    assert!(syn::parse2::<AppDefinitionModule>(input).is_ok());
+}
+
 #[test]
 fn test_invalid_broadcasted_events_module() {
     let input = parse_quote! {
@@ -144,4 +257,107 @@ fn test_invalid_listened_events_module() {
 
     let result: syn::Result<ListenedEventsModule> = syn::parse2(input);
     assert!(result.is_err());
-}
\ No newline at end of file
+}
+#[test]
+fn test_parse_topic_args() {
+    let args: TopicArgs = syn::parse2(quote::quote! { "app_1/status" }).unwrap();
+    assert_eq!(args.topic.value(), "app_1/status");
+    assert_eq!(args.qos, None);
+    assert!(!args.retain);
+
+    let args: TopicArgs =
+        syn::parse2(quote::quote! { "app_1/status", qos = 2, retain = true }).unwrap();
+    assert_eq!(args.qos, Some(2));
+    assert!(args.retain);
+    assert_eq!(args.codec, None);
+
+    let args: TopicArgs = syn::parse2(quote::quote! { "app_1/status", codec = "cbor" }).unwrap();
+    assert_eq!(args.codec.as_deref(), Some("cbor"));
+    assert_eq!(args.version, None);
+
+    let args: TopicArgs = syn::parse2(quote::quote! { "app_1/status", version = 2 }).unwrap();
+    assert_eq!(args.version, Some(2));
+}
+
+#[test]
+fn test_invalid_topic_args() {
+    let result: syn::Result<TopicArgs> = syn::parse2(quote::quote! { "app_1/status", qos = 3 });
+    assert!(result.is_err());
+
+    let result: syn::Result<TopicArgs> = syn::parse2(quote::quote! { "app_1/status", retain = 1 });
+    assert!(result.is_err());
+
+    let result: syn::Result<TopicArgs> = syn::parse2(quote::quote! { "app_1/status", priority = 1 });
+    assert!(result.is_err());
+
+    let result: syn::Result<TopicArgs> =
+        syn::parse2(quote::quote! { "app_1/status", codec = "xml" });
+    assert!(result.is_err());
+
+    let result: syn::Result<TopicArgs> =
+        syn::parse2(quote::quote! { "app_1/status", codec = cbor });
+    assert!(result.is_err());
+
+    let result: syn::Result<TopicArgs> = syn::parse2(quote::quote! { "app_1/status", version = 0 });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_to_snake_case() {
+    use crate::parsing::modules::to_snake_case;
+    use proc_macro2::{Ident, Span};
+
+    let snake = |name: &str| to_snake_case(&Ident::new(name, Span::call_site()));
+    assert_eq!(snake("Heartbeat"), "heartbeat");
+    assert_eq!(snake("SquareRequest"), "square_request");
+    assert_eq!(snake("HTTPPing"), "http_ping");
+    assert_eq!(snake("Sensor2Reading"), "sensor2_reading");
+}
+
+#[test]
+fn test_extract_module() {
+    use crate::extract::ItemKind;
+    use crate::parsing::extract::ExtractArgs;
+
+    let args: ExtractArgs = syn::parse2(quote::quote! { events(struct, impl), references(use) }).unwrap();
+    let module: syn::ItemMod = parse_quote! {
+        mod app {
+            pub mod events {
+                pub struct Started;
+                impl Started {}
+            }
+            pub mod references {
+                pub use crate::Shared;
+            }
+        }
+    };
+    let spec = args.spec();
+    let information = spec.extract(&module).unwrap().information();
+    assert_eq!(information.module, "app");
+    let events = information.submodule("events").unwrap();
+    assert_eq!(events.items[0].kind, ItemKind::Struct);
+    assert_eq!(events.items[1].ident, "Started");
+    let references = information.submodule("references").unwrap();
+    assert_eq!(references.items[0].path.as_deref(), Some("crate::Shared"));
+}
+
+#[test]
+fn test_invalid_extract_module() {
+    use crate::parsing::extract::ExtractArgs;
+
+    let result: syn::Result<ExtractArgs> = syn::parse2(quote::quote! { events(structs) });
+    assert!(result.is_err());
+
+    let args: ExtractArgs = syn::parse2(quote::quote! { events(struct), references(use) }).unwrap();
+    let module: syn::ItemMod = parse_quote! {
+        mod app {
+            pub mod events {
+                pub fn start() {}
+            }
+            pub mod handlers {}
+        }
+    };
+    let error = args.spec().extract(&module).err().unwrap();
+    // Every error is reported, not only the first one
+    assert_eq!(error.into_iter().count(), 3);
+}
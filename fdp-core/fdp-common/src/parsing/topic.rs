@@ -0,0 +1,129 @@
+//! Parsing logic for the arguments of the fdp::topic macro
+
+use crate::mqtt::CODECS;
+use syn::{parse::Parse, punctuated::Punctuated, Expr, ExprLit, Lit, LitStr, MetaNameValue, Token};
+
+/// The arguments of `#[fdp::topic("topic", qos = 1, retain = true, codec = "cbor", version = 2)]`
+pub struct TopicArgs {
+    /// The static topic the message is sent on
+    pub topic: LitStr,
+    /// The declared QoS level, if any
+    pub qos: Option<u8>,
+    /// Whether the message is retained by the broker
+    pub retain: bool,
+    /// The declared codec, if any
+    pub codec: Option<String>,
+    /// The declared version of the message schema, if any
+    pub version: Option<u32>,
+}
+
+impl Parse for TopicArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let topic: LitStr = input.parse()?;
+        let mut args = TopicArgs {
+            topic,
+            qos: None,
+            retain: false,
+            codec: None,
+            version: None,
+        };
+
+        if input.is_empty() {
+            return Ok(args);
+        }
+        input.parse::<Token![,]>()?;
+
+        let options = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)?;
+        for option in options {
+            let name = option
+                .path
+                .get_ident()
+                .map(|ident| ident.to_string())
+                .unwrap_or_default();
+            match name.as_str() {
+                "qos" => {
+                    let qos = match &option.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Int(lit), ..
+                        }) => lit.base10_parse::<u8>()?,
+                        value => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "The qos must be an integer literal",
+                            ))
+                        }
+                    };
+                    if qos > 2 {
+                        return Err(syn::Error::new_spanned(
+                            &option.value,
+                            "The qos must be 0, 1 or 2",
+                        ));
+                    }
+                    args.qos = Some(qos);
+                }
+                "retain" => {
+                    args.retain = match &option.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Bool(lit),
+                            ..
+                        }) => lit.value,
+                        value => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "The retain flag must be a boolean literal",
+                            ))
+                        }
+                    };
+                }
+                "codec" => {
+                    let codec = match &option.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(lit), ..
+                        }) => lit.value(),
+                        value => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "The codec must be a string literal",
+                            ))
+                        }
+                    };
+                    if !CODECS.contains(&codec.as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            &option.value,
+                            format!("Unknown codec, expected one of: {}", CODECS.join(", ")),
+                        ));
+                    }
+                    args.codec = Some(codec);
+                }
+                "version" => {
+                    let version = match &option.value {
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Int(lit), ..
+                        }) => lit.base10_parse::<u32>()?,
+                        value => {
+                            return Err(syn::Error::new_spanned(
+                                value,
+                                "The version must be an integer literal",
+                            ))
+                        }
+                    };
+                    if version == 0 {
+                        return Err(syn::Error::new_spanned(
+                            &option.value,
+                            "The versions start at 1",
+                        ));
+                    }
+                    args.version = Some(version);
+                }
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &option.path,
+                        "Unknown fdp::topic option, expected 'qos', 'retain', 'codec' or 'version'",
+                    ))
+                }
+            }
+        }
+
+        Ok(args)
+    }
+}
@@ -0,0 +1,309 @@
+//! Renders the JSON schemas of the messages as TypeScript declarations
+
+use crate::info::{union_discriminator, union_variants};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Renders named JSON schemas as exported TypeScript declarations, ordered by name.
+/// Objects become interfaces, and tagged unions, e.g. enums with the `#[serde(tag = "kind")]` attribute,
+/// become discriminated unions of one interface per variant, named after the union and the tag of the variant,
+/// e.g. `type Command = CommandStop | CommandMove`.
+/// The references to other schemas, e.g. `#/definitions/Color`, are rendered as the names of their declarations.
+pub fn declarations(definitions: &BTreeMap<String, Value>) -> Result<String, String> {
+    let mut declarations = Vec::new();
+    for (name, schema) in definitions {
+        match union_discriminator(schema) {
+            Some(tag) => {
+                let variants = union_variants(name, schema, tag)?;
+                let names: Vec<&str> = variants.iter().map(|v| v.name.as_str()).collect();
+                declarations.push(format!(
+                    "{}export type {} = {};\n",
+                    doc_comment(schema, ""),
+                    name,
+                    names.join(" | ")
+                ));
+                for variant in &variants {
+                    declarations.push(declaration(&variant.name, &variant.schema));
+                }
+            }
+            None => declarations.push(declaration(name, schema)),
+        }
+    }
+    Ok(declarations.join("\n"))
+}
+
+/// Renders a schema as an interface if it is a plain object, or as a type alias otherwise
+fn declaration(name: &str, schema: &Value) -> String {
+    let combined = ["allOf", "oneOf", "anyOf"]
+        .iter()
+        .any(|key| schema.get(*key).is_some());
+    match schema.get("properties").and_then(Value::as_object) {
+        Some(_) if !combined => format!(
+            "{}export interface {} {}\n",
+            doc_comment(schema, ""),
+            name,
+            object_type(schema, "")
+        ),
+        _ => format!(
+            "{}export type {} = {};\n",
+            doc_comment(schema, ""),
+            name,
+            type_of(schema)
+        ),
+    }
+}
+
+/// The TypeScript type of a schema
+fn type_of(schema: &Value) -> String {
+    let mut parts = Vec::new();
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        parts.push(
+            reference
+                .rsplit('/')
+                .next()
+                .unwrap_or(reference)
+                .to_string(),
+        );
+    } else if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        // JSON literals are TypeScript literals
+        parts.push(union(values.iter().map(Value::to_string).collect()));
+    } else if let Some(value) = schema.get("const") {
+        parts.push(value.to_string());
+    } else {
+        match schema.get("type") {
+            Some(Value::String(kind)) => parts.push(primitive(kind, schema)),
+            Some(Value::Array(kinds)) => parts.push(union(
+                kinds
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|kind| primitive(kind, schema))
+                    .collect(),
+            )),
+            _ if schema.get("properties").is_some() => parts.push(object_type(schema, "")),
+            _ => {}
+        }
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(variants) = schema.get(key).and_then(Value::as_array) {
+            parts.push(union(variants.iter().map(type_of).collect()));
+        }
+    }
+    if let Some(members) = schema.get("allOf").and_then(Value::as_array) {
+        parts.extend(members.iter().map(type_of));
+    }
+
+    match parts.len() {
+        0 => "unknown".to_string(),
+        1 => parts.remove(0),
+        _ => parts
+            .iter()
+            .map(|part| {
+                if part.contains(" | ") {
+                    format!("({})", part)
+                } else {
+                    part.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" & "),
+    }
+}
+
+/// The TypeScript type of a JSON schema type, e.g. `number` for `integer`
+fn primitive(kind: &str, schema: &Value) -> String {
+    match kind {
+        "string" => "string".to_string(),
+        "integer" | "number" => "number".to_string(),
+        "boolean" => "boolean".to_string(),
+        "null" => "null".to_string(),
+        "array" => match schema.get("items") {
+            // Tuples, e.g. `(f32, f32)`
+            Some(Value::Array(items)) => format!(
+                "[{}]",
+                items.iter().map(type_of).collect::<Vec<_>>().join(", ")
+            ),
+            Some(items) => format!("Array<{}>", type_of(items)),
+            None => "Array<unknown>".to_string(),
+        },
+        "object" => object_type(schema, ""),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// The body of an object type, with one line per property, or a record type when its properties aren't declared,
+/// e.g. for a `HashMap<String, f32>`
+fn object_type(schema: &Value, indent: &str) -> String {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return match schema.get("additionalProperties") {
+            Some(values @ Value::Object(_)) => format!("Record<string, {}>", type_of(values)),
+            _ => "Record<string, unknown>".to_string(),
+        };
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let inner = format!("{}  ", indent);
+    let mut body = String::from("{\n");
+    for (name, property) in properties {
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        let property_type = match property.get("properties") {
+            Some(_) => object_type(property, &inner),
+            None => type_of(property),
+        };
+        body.push_str(&format!(
+            "{}{}{}{}: {};\n",
+            doc_comment(property, &inner),
+            inner,
+            property_name(name),
+            optional,
+            property_type
+        ));
+    }
+    body.push_str(indent);
+    body.push('}');
+    body
+}
+
+/// A property name, quoted unless it is an identifier
+fn property_name(name: &str) -> String {
+    let identifier = name.chars().enumerate().all(|(i, c)| {
+        c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+    });
+    if identifier && !name.is_empty() {
+        name.to_string()
+    } else {
+        Value::from(name).to_string()
+    }
+}
+
+/// A union of types, without duplicates
+fn union(types: Vec<String>) -> String {
+    let mut unique: Vec<String> = Vec::new();
+    for kind in types {
+        if !unique.contains(&kind) {
+            unique.push(kind);
+        }
+    }
+    unique.join(" | ")
+}
+
+/// The documentation comment of a schema, from its description
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    match schema.get("description").and_then(Value::as_str) {
+        Some(description) => {
+            let lines: Vec<String> = description
+                .lines()
+                .map(|line| format!("{} * {}", indent, line).trim_end().to_string())
+                .collect();
+            format!("{}/**\n{}\n{} */\n", indent, lines.join("\n"), indent)
+        }
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::info::MessageDeclarationInfo;
+    use schemars::JsonSchema;
+    use std::collections::HashMap;
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    /// A command sent to a robot
+    enum Command {
+        Stop,
+        MoveTo { x: f32, y: f32, speed: Option<u8> },
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Light {
+        /// The color shown
+        color: Color,
+        colors: Vec<Color>,
+        levels: HashMap<String, f32>,
+        position: (f32, f32),
+        #[serde(rename = "last-seen")]
+        last_seen: Option<String>,
+    }
+
+    fn render(schemas: Vec<(&str, schemars::schema::RootSchema)>) -> String {
+        let mut definitions = BTreeMap::new();
+        for (name, schema) in schemas {
+            let info = MessageDeclarationInfo::for_test(name, "topic", schema);
+            let (schema, shared) = info.split_schema("#/definitions/");
+            definitions.extend(shared);
+            definitions.insert(name.to_string(), schema);
+        }
+        declarations(&definitions).unwrap()
+    }
+
+    #[test]
+    fn renders_objects_as_interfaces() {
+        let rendered = render(vec![("Light", schemars::schema_for!(Light))]);
+        assert_eq!(
+            rendered,
+            r#"export type Color = "Red" | "Green";
+
+export interface Light {
+  /**
+   * The color shown
+   */
+  color: Color;
+  colors: Array<Color>;
+  "last-seen"?: string | null;
+  levels: Record<string, number>;
+  position: [number, number];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn renders_tagged_unions_as_discriminated_unions() {
+        let rendered = render(vec![("Command", schemars::schema_for!(Command))]);
+        assert_eq!(
+            rendered,
+            r#"/**
+ * A command sent to a robot
+ */
+export type Command = CommandStop | CommandMoveTo;
+
+export interface CommandStop {
+  kind: "stop";
+}
+
+export interface CommandMoveTo {
+  kind: "move_to";
+  speed?: number | null;
+  x: number;
+  y: number;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn rejects_unions_whose_tags_are_not_strings() {
+        let mut schema = serde_json::to_value(schemars::schema_for!(Command)).unwrap();
+        schema["oneOf"][1]["properties"]["kind"]["enum"] = serde_json::json!([1]);
+        let definitions = BTreeMap::from([("Command".to_string(), schema)]);
+        let error = declarations(&definitions).unwrap_err();
+        assert!(error.contains("'kind' tag"), "{}", error);
+    }
+}
@@ -1,8 +1,8 @@
 #![doc = include_str!("../doc/app_1.md")]
 
+#[cfg_attr(feature = "client", fdp::client)]
 #[fdp::definition]
 pub mod definition {
-
     pub mod broadcasted_events {
 
         #[fdp::topic("app_1/sensor/temperature")]
@@ -128,10 +128,4 @@ pub mod definition {
         pub use crate::apps::app_2_30::broadcasted_events::UserActivity;
         pub use crate::apps::app_2_30::broadcasted_events::WebhookDelivery;
     }
-
-    pub mod incoming_requests {}
-
-    pub mod outgoing_responses {}
-
-    pub mod emitted_requests {}
 }
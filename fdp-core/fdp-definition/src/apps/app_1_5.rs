@@ -1,8 +1,8 @@
 #![doc = include_str!("../doc/app_1.md")]
 
+#[cfg_attr(feature = "client", fdp::client)]
 #[fdp::definition]
 pub mod definition {
-
     pub mod broadcasted_events {
 
         #[fdp::topic("app_1/random_number")]
@@ -42,12 +42,4 @@ pub mod definition {
             pub disk_space: i64,
         }
     }
-
-    pub mod listened_events {}
-
-    pub mod incoming_requests {}
-
-    pub mod outgoing_responses {}
-
-    pub mod emitted_requests {}
 }
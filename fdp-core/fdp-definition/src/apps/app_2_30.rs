@@ -1,5 +1,6 @@
 #![doc = include_str!("../doc/app_2.md")]
 
+#[cfg_attr(feature = "client", fdp::client)]
 #[fdp::definition]
 pub mod definition {
     pub mod broadcasted_events {
@@ -129,8 +130,4 @@ pub mod definition {
         pub use crate::apps::app_1_30::broadcasted_events::UserLogout;
         pub use crate::apps::app_1_30::broadcasted_events::WarningAlert;
     }
-
-    pub mod incoming_requests {}
-    pub mod outgoing_responses {}
-    pub mod emitted_requests {}
 }
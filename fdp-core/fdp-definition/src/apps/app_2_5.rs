@@ -1,9 +1,8 @@
 #![doc = include_str!("../doc/app_2.md")]
 
+#[cfg_attr(feature = "client", fdp::client)]
 #[fdp::definition]
 pub mod definition {
-    pub mod broadcasted_events {}
-
     pub mod listened_events {
         pub use crate::apps::app_1_5::broadcasted_events::RandomNumber;
         pub use crate::apps::app_1_5::broadcasted_events::StatusUpdate;
@@ -11,8 +10,4 @@ pub mod definition {
         pub use crate::apps::app_1_5::broadcasted_events::UserEvent;
         pub use crate::apps::app_1_5::broadcasted_events::SystemMetrics;
     }
-
-    pub mod incoming_requests {}
-    pub mod outgoing_responses {}
-    pub mod emitted_requests {}
 }
@@ -0,0 +1,107 @@
+use clap::Parser;
+use fdp_common::info::{union_discriminator, MessageDeclarationInfo, SystemDefinitionInfo};
+use fdp_common::mqtt::content_type;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Output file path
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let start_time = std::time::Instant::now();
+
+    let definition = fdp_definition::apps::get_definition();
+    let document = generate_asyncapi(&definition);
+
+    if let Some(parent) = args.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&args.output, serde_json::to_string_pretty(&document)?)?;
+
+    let duration = start_time.elapsed();
+    println!(
+        "📡 Generated AsyncAPI document {} in {:.2?}",
+        args.output.display(),
+        duration
+    );
+    Ok(())
+}
+
+/// Generates an AsyncAPI document describing every message declared in the FDP system
+fn generate_asyncapi(system_info: &SystemDefinitionInfo) -> Value {
+    let mut channels = Map::new();
+    let mut messages = Map::new();
+    let mut schemas = Map::new();
+
+    for (app_name, app_info) in &system_info.apps {
+        let declarations = [
+            ("broadcasted_events", &app_info.broadcasted_events),
+            ("incoming_requests", &app_info.incoming_requests),
+            ("outgoing_responses", &app_info.outgoing_responses),
+        ];
+
+        for (module_name, items) in declarations {
+            for info in items {
+                channels.insert(info.topic.clone(), channel(app_name, module_name, info));
+                messages.insert(
+                    info.identifier.clone(),
+                    json!({
+                        "name": info.identifier,
+                        "contentType": content_type(&info.codec),
+                        "payload": { "$ref": format!("#/components/schemas/{}", info.identifier) },
+                    }),
+                );
+                // The types the messages use, e.g. the enum of a field, are shared components
+                let (schema, definitions) = info.split_schema("#/components/schemas/");
+                schemas.insert(info.identifier.clone(), schema);
+                schemas.extend(definitions);
+            }
+        }
+    }
+
+    // The tagged unions, e.g. enums with the `#[serde(tag = "kind")]` attribute, name their discriminator
+    for schema in schemas.values_mut() {
+        if let Some(tag) = union_discriminator(schema).map(String::from) {
+            schema["discriminator"] = json!(tag);
+        }
+    }
+
+    json!({
+        "asyncapi": "2.6.0",
+        "info": {
+            "title": "FDP System",
+            "version": "0.1.0",
+        },
+        "defaultContentType": "application/json",
+        "channels": channels,
+        "components": {
+            "messages": messages,
+            "schemas": schemas,
+        },
+    })
+}
+
+/// The channel of a declared message, with its MQTT operation bindings
+fn channel(app_name: &str, module_name: &str, info: &MessageDeclarationInfo) -> Value {
+    let mut binding = Map::new();
+    if let Some(qos) = info.qos {
+        binding.insert("qos".to_string(), json!(qos));
+    }
+    binding.insert("retain".to_string(), json!(info.retain));
+    binding.insert("bindingVersion".to_string(), json!("0.1.0"));
+
+    json!({
+        "description": format!("{}::{}::{}", app_name, module_name, info.identifier),
+        "publish": {
+            "operationId": format!("{}_{}", app_name, info.identifier),
+            "bindings": { "mqtt": binding },
+            "message": { "$ref": format!("#/components/messages/{}", info.identifier) },
+        },
+    })
+}
@@ -0,0 +1,57 @@
+use clap::Parser;
+use fdp_common::compat::{Compatibility, SchemaSnapshot};
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// The lock file holding the snapshot of the message schemas
+    #[arg(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/fdp.lock.json"))]
+    lock: PathBuf,
+    /// Updates the lock file with the current schemas, when they are compatible with it
+    #[arg(short, long)]
+    update: bool,
+}
+
+fn main() -> std::io::Result<ExitCode> {
+    let args = Args::parse();
+    let definition = fdp_definition::apps::get_definition();
+    let current = SchemaSnapshot::from(&definition);
+
+    if !args.lock.exists() {
+        fs::write(&args.lock, serde_json::to_string_pretty(&current)?)?;
+        println!("🔒 Created the schema lock file {}", args.lock.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let locked: SchemaSnapshot = serde_json::from_str(&fs::read_to_string(&args.lock)?)?;
+    let changes = locked.diff(&current);
+    for change in &changes {
+        let icon = match (change.compatibility, change.is_allowed()) {
+            (Compatibility::Compatible, _) => "✅",
+            (Compatibility::Breaking, true) => "⚠️ ",
+            (Compatibility::Breaking, false) => "❌",
+        };
+        println!("{} {}", icon, change);
+    }
+
+    let breaking = changes.iter().filter(|change| !change.is_allowed()).count();
+    if breaking > 0 {
+        println!(
+            "💥 {} breaking change(s) without a version bump of their message",
+            breaking
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    if changes.is_empty() {
+        println!("🔒 The message schemas match the lock file");
+    } else if args.update {
+        fs::write(&args.lock, serde_json::to_string_pretty(&current)?)?;
+        println!("🔒 Updated the schema lock file {}", args.lock.display());
+    } else {
+        println!("🔓 The changes are compatible, run with --update to lock them");
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,63 @@
+use clap::{Parser, ValueEnum};
+use fdp_common::diff::SystemDiff;
+use fdp_common::graph::FdpSystem;
+use fdp_common::info::SystemDefinitionInfo;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// The manifest of the previous revision of the system, written by the `manifest` binary
+    previous: PathBuf,
+    /// The manifest of the current revision, the definitions of this crate by default
+    current: Option<PathBuf>,
+    /// The format of the report, printed on the standard output
+    #[arg(short, long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+    /// Writes the graphviz graph of both revisions, with the added edges in green and the removed ones in red
+    #[arg(short, long)]
+    graphviz: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Markdown,
+    Json,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let previous = read_manifest(&args.previous)?;
+    let current = match &args.current {
+        Some(path) => read_manifest(path)?,
+        None => fdp_definition::apps::get_definition(),
+    };
+
+    let diff = SystemDiff::between(&previous, &current);
+    for warning in &diff.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    match args.format {
+        Format::Markdown => print!("{}", diff.to_markdown()),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+    }
+
+    if let Some(path) = &args.graphviz {
+        match (FdpSystem::from(current), FdpSystem::from(previous)) {
+            (Ok(system), Ok(previous_system)) => {
+                fs::write(path, system.to_graphviz_diff(&previous_system))?;
+                eprintln!("🎨 Generated the graph of the changes {}", path.display());
+            }
+            _ => eprintln!(
+                "Warning: The graph of the changes is not generated, the graph of a system can't be built."
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Reads the manifest of a system
+fn read_manifest(path: &Path) -> std::io::Result<SystemDefinitionInfo> {
+    let manifest = fs::read_to_string(path)?;
+    Ok(SystemDefinitionInfo::from_manifest(&manifest)?)
+}
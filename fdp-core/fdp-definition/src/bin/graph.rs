@@ -1,4 +1,6 @@
 use fdp_common::graph::FdpSystem;
+use fdp_common::info::{AppDefinitionInfo, MessageDeclarationInfo, SystemDefinitionInfo};
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -9,15 +11,15 @@ fn main() {
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
     print!("🔍 Analysing Rust Manifest in : {}", manifest_dir);
     let system = FdpSystem::from(definition).unwrap();
-    print!(" ✅ \n");
+    println!(" ✅ ");
 
-    let apps = fdp_definition::apps::get_definition().apps;
+    let system_info = fdp_definition::apps::get_definition();
 
     let images_dir = Path::new(&manifest_dir).join("target/doc/images");
     fs::create_dir_all(&images_dir).expect("Failed to create images directory");
 
     let dot_content = system.to_graphviz();
-    for (app_name, _) in apps {
+    for (app_name, app_info) in &system_info.apps {
         let dot_file_path = images_dir.join(format!("{}.dot", app_name));
         let png_file_path = images_dir.join(format!("{}.png", app_name));
         let md_file_path = Path::new(&manifest_dir)
@@ -28,7 +30,7 @@ fn main() {
         fs::write(&dot_file_path, &dot_content).expect("Failed to write dot file");
 
         let output = Command::new("dot")
-            .args(&[
+            .args([
                 "-Tpng",
                 dot_file_path.to_str().unwrap(),
                 "-o",
@@ -49,8 +51,11 @@ fn main() {
             app_name
         );
         let markdown_content = format!(
-            "# {} Description\n![Graph Image]({})\n",
-            app_name, relative_image_path
+            "# {} Description\n![Graph Image]({})\n{}{}",
+            app_name,
+            relative_image_path,
+            messages_table(app_info),
+            versions_table(app_name, app_info, &system_info)
         );
 
         fs::create_dir_all(md_file_path.parent().unwrap()).expect("Failed to create doc directory");
@@ -61,3 +66,83 @@ fn main() {
 
     println!("📄 Successfully updated documentation")
 }
+
+/// Renders the declared messages of an app, with their delivery semantics, as a markdown table
+fn messages_table(app_info: &AppDefinitionInfo) -> String {
+    let declarations: Vec<(&str, &MessageDeclarationInfo)> = [
+        ("Event", &app_info.broadcasted_events),
+        ("Request", &app_info.incoming_requests),
+        ("Response", &app_info.outgoing_responses),
+    ]
+    .into_iter()
+    .flat_map(|(kind, messages)| messages.iter().map(move |message| (kind, message)))
+    .collect();
+
+    if declarations.is_empty() {
+        return String::new();
+    }
+
+    let mut table = String::from(
+        "\n## Declared messages\n\n| Message | Type | Topic | Version | QoS | Retain | Codec |\n|---|---|---|---|---|---|---|\n",
+    );
+    for (kind, message) in declarations {
+        let qos = message
+            .qos
+            .map(|qos| qos.to_string())
+            .unwrap_or_else(|| "default".to_string());
+        table.push_str(&format!(
+            "| {} | {} | `{}` | {} | {} | {} | {} |\n",
+            message.identifier,
+            kind,
+            message.topic,
+            message.version,
+            qos,
+            message.retain,
+            message.codec
+        ));
+    }
+    table
+}
+
+/// Renders the versions of the messages the app declares side by side, with the apps still using each of them
+fn versions_table(
+    app_name: &str,
+    app_info: &AppDefinitionInfo,
+    system_info: &SystemDefinitionInfo,
+) -> String {
+    let mut versions: BTreeMap<&str, Vec<&MessageDeclarationInfo>> = BTreeMap::new();
+    for message in app_info
+        .broadcasted_events
+        .iter()
+        .chain(&app_info.incoming_requests)
+    {
+        versions
+            .entry(message.base_topic())
+            .or_default()
+            .push(message);
+    }
+    versions.retain(|_, messages| messages.len() > 1);
+    if versions.is_empty() {
+        return String::new();
+    }
+
+    let mut table = String::from(
+        "\n## Message versions\n\n| Topic | Version | Message | Used by |\n|---|---|---|---|\n",
+    );
+    for (topic, mut messages) in versions {
+        messages.sort_by_key(|message| message.version);
+        for message in messages {
+            let users = system_info.users_of(app_name, message);
+            let users = if users.is_empty() {
+                "no longer used".to_string()
+            } else {
+                users.join(", ")
+            };
+            table.push_str(&format!(
+                "| `{}` | {} | {} | {} |\n",
+                topic, message.version, message.identifier, users
+            ));
+        }
+    }
+    table
+}
@@ -0,0 +1,31 @@
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Output file path
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let start_time = std::time::Instant::now();
+
+    let definition = fdp_definition::apps::get_definition();
+    let manifest = definition.to_manifest()?;
+
+    if let Some(parent) = args.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&args.output, manifest)?;
+
+    let duration = start_time.elapsed();
+    println!(
+        "📦 Generated manifest {} in {:.2?}",
+        args.output.display(),
+        duration
+    );
+    Ok(())
+}
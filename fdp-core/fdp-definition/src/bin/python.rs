@@ -1,10 +1,12 @@
 use clap::Parser;
-use fdp_common::info::{MessageDeclarationInfo, SystemDefinitionInfo};
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use fdp_common::info::{
+    union_discriminator, union_variants, MessageDeclarationInfo, SystemDefinitionInfo,
+};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
 use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 #[derive(Parser, Debug)]
@@ -12,6 +14,10 @@ struct Args {
     /// Output folder path
     #[arg(short, long)]
     output: PathBuf,
+    /// Only writes the merged JSON schemas and the package modules, without generating the Pydantic classes
+    /// with datamodel-codegen
+    #[arg(long)]
+    skip_codegen: bool,
 }
 
 fn main() -> std::io::Result<()> {
@@ -29,7 +35,7 @@ fn main() -> std::io::Result<()> {
 
     let definition = fdp_definition::apps::get_definition();
 
-    generate_python_modules(&definition, &args.output)?;
+    generate_python_modules(&definition, &args.output, !args.skip_codegen)?;
 
     let duration = start_time.elapsed();
     println!(
@@ -40,7 +46,7 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
-fn generate_pyproject_toml(output_dir: &PathBuf, project_name: &str) -> std::io::Result<()> {
+fn generate_pyproject_toml(output_dir: &Path, project_name: &str) -> std::io::Result<()> {
     let content = format!(
         r#"[build-system]
 requires = ["setuptools>=61.0"]
@@ -71,7 +77,8 @@ where = ["src"]
 
 fn generate_python_modules(
     system_info: &SystemDefinitionInfo,
-    output_dir: &PathBuf,
+    output_dir: &Path,
+    codegen: bool,
 ) -> std::io::Result<()> {
     // Create the main package directory
     fs::create_dir_all(output_dir)?;
@@ -96,7 +103,7 @@ fn generate_python_modules(
         ];
 
         for (module_name, items) in &modules {
-            generate_pydantic_class(items, &app_dir, module_name)?;
+            generate_pydantic_class(items, &app_dir, module_name, codegen)?;
         }
 
         generate_import_module(&app_info.listened_events, &app_dir, "listened_events")?;
@@ -120,47 +127,80 @@ fn generate_python_modules(
     Ok(())
 }
 
-fn merge_json_schemas(schemas: &[(&str, Value)]) -> Value {
-    let mut definitions = HashMap::new();
+fn merge_json_schemas(
+    schemas: &[(&str, Value)],
+    shared: BTreeMap<String, Value>,
+) -> Result<Value, String> {
+    // The types the messages use, e.g. the enum of a field, are defined next to them
+    let mut definitions = shared;
     for (name, schema) in schemas {
         definitions.insert(name.to_string(), schema.clone());
     }
+    discriminate_unions(&mut definitions)?;
 
-    json!({
+    Ok(json!({
         "$schema": "http://json-schema.org/draft-07/schema#",
         "definitions": definitions,
-    })
+    }))
+}
+
+/// Turns the tagged unions, e.g. enums with the `#[serde(tag = "kind")]` attribute, into discriminated unions
+/// of one class per variant, named after the union and the tag of the variant, e.g. `CommandMove`
+fn discriminate_unions(definitions: &mut BTreeMap<String, Value>) -> Result<(), String> {
+    let unions: Vec<(String, String)> = definitions
+        .iter()
+        .filter_map(|(name, schema)| Some((name.clone(), union_discriminator(schema)?.to_string())))
+        .collect();
+    for (name, tag) in unions {
+        let variants = union_variants(&name, &definitions[&name], &tag)?;
+        let (mut references, mut mapping) = (Vec::new(), Map::new());
+        for variant in variants {
+            let reference = format!("#/definitions/{}", variant.name);
+            references.push(json!({ "$ref": reference }));
+            mapping.insert(variant.tag, json!(reference));
+            definitions.insert(variant.name, variant.schema);
+        }
+        let schema = definitions.get_mut(&name).unwrap();
+        schema["oneOf"] = Value::Array(references);
+        schema["discriminator"] = json!({ "propertyName": tag, "mapping": mapping });
+    }
+    Ok(())
 }
 
 fn generate_pydantic_class(
     message_infos: &[MessageDeclarationInfo],
-    output_dir: &PathBuf,
+    output_dir: &Path,
     module_name: &str,
+    codegen: bool,
 ) -> std::io::Result<()> {
     // Ensure the output directory exists
     fs::create_dir_all(output_dir)?;
 
     // Merge JSON schemas
+    let mut shared = BTreeMap::new();
     let schemas: Vec<(&str, Value)> = message_infos
         .iter()
         .map(|info| {
-            (
-                info.identifier.as_str(),
-                serde_json::to_value(&info.schema).unwrap(),
-            )
+            let (schema, definitions) = info.split_schema("#/definitions/");
+            shared.extend(definitions);
+            (info.identifier.as_str(), schema)
         })
         .collect();
-    let merged_schema = merge_json_schemas(&schemas);
+    let merged_schema =
+        merge_json_schemas(&schemas, shared).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
 
     // Save the merged schema to a file in the output directory
     let schema_file_path = output_dir.join(format!("{}.json", module_name));
     let mut file = File::create(&schema_file_path)?;
     file.write_all(serde_json::to_string_pretty(&merged_schema)?.as_bytes())?;
+    if !codegen {
+        return Ok(());
+    }
 
     // Generate Python Pydantic v2 classes
     let pydantic_file_path_temp = output_dir.join(format!("{}.py", module_name));
     let output = Command::new("datamodel-codegen")
-        .args(&[
+        .args([
             "--input",
             schema_file_path.to_str().unwrap(),
             "--input-file-type",
@@ -173,6 +213,9 @@ fn generate_pydantic_class(
             "3.12",
             "--use-union-operator",
             "--use-subclass-enum",
+            // The tags of the discriminated unions are typed as literals
+            "--enum-field-as-literal",
+            "one",
             // "--disable-timestamp",
             // "--disable-appending-item-suffix",
         ])
@@ -191,7 +234,7 @@ fn generate_pydantic_class(
 
 fn generate_import_module(
     items: &[fdp_common::info::MessageReferenceInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
     module_name: &str,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join(format!("{}.py", module_name)))?;
@@ -2,7 +2,7 @@ use clap::Parser;
 use fdp_common::info::SystemDefinitionInfo;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -41,7 +41,7 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
-fn generate_pyproject_toml(output_dir: &PathBuf, project_name: &str) -> std::io::Result<()> {
+fn generate_pyproject_toml(output_dir: &Path, project_name: &str) -> std::io::Result<()> {
     let content = format!(
         r#"[build-system]
 requires = ["setuptools>=61.0"]
@@ -69,7 +69,7 @@ where = ["src"]
 
 fn generate_python_modules(
     system_info: &SystemDefinitionInfo,
-    output_dir: &PathBuf,
+    output_dir: &Path,
 ) -> std::io::Result<()> {
     // Create the main package directory
     fs::create_dir_all(output_dir)?;
@@ -113,7 +113,7 @@ fn generate_python_modules(
 
 fn generate_broadcasted_events_module(
     events: &[fdp_common::info::MessageDeclarationInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join("broadcasted_events.py"))?;
     writeln!(file, "from dataclasses import dataclass")?;
@@ -129,7 +129,7 @@ fn generate_broadcasted_events_module(
 
 fn generate_incoming_requests_module(
     requests: &[fdp_common::info::MessageDeclarationInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join("incoming_requests.py"))?;
     writeln!(file, "from dataclasses import dataclass")?;
@@ -145,7 +145,7 @@ fn generate_incoming_requests_module(
 
 fn generate_outgoing_responses_module(
     responses: &[fdp_common::info::MessageDeclarationInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join("outgoing_responses.py"))?;
     writeln!(file, "from dataclasses import dataclass")?;
@@ -161,7 +161,7 @@ fn generate_outgoing_responses_module(
 
 fn generate_listened_events_module(
     events: &[fdp_common::info::MessageReferenceInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join("listened_events.py"))?;
     for event in events {
@@ -176,7 +176,7 @@ fn generate_listened_events_module(
 
 fn generate_emitted_requests_module(
     requests: &[fdp_common::info::MessageReferenceInfo],
-    app_dir: &PathBuf,
+    app_dir: &Path,
 ) -> std::io::Result<()> {
     let mut file = fs::File::create(app_dir.join("emitted_requests.py"))?;
     for request in requests {
@@ -0,0 +1,120 @@
+use clap::Parser;
+use fdp_common::info::{MessageDeclarationInfo, MessageReferenceInfo, SystemDefinitionInfo};
+use fdp_common::typescript::declarations;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Output folder path
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let start_time = std::time::Instant::now();
+
+    let definition = fdp_definition::apps::get_definition();
+    generate_typescript_modules(&definition, &args.output)?;
+
+    let duration = start_time.elapsed();
+    println!(
+        "🟦 Generated TypeScript definitions in {} in {:.2?}",
+        args.output.display(),
+        duration
+    );
+    Ok(())
+}
+
+/// Writes one directory per app, with one module per definition submodule,
+/// and an `index.ts` exporting the apps as namespaces, e.g. `app_1.broadcasted_events.Heartbeat`
+fn generate_typescript_modules(
+    system_info: &SystemDefinitionInfo,
+    output_dir: &Path,
+) -> std::io::Result<()> {
+    fs::create_dir_all(output_dir)?;
+
+    for (app_name, app_info) in &system_info.apps {
+        let app_dir = output_dir.join(app_name);
+        fs::create_dir_all(&app_dir)?;
+
+        let declarations = [
+            ("broadcasted_events", &app_info.broadcasted_events),
+            ("incoming_requests", &app_info.incoming_requests),
+            ("outgoing_responses", &app_info.outgoing_responses),
+        ];
+        for (module_name, items) in declarations {
+            write_module(&app_dir, module_name, declaration_module(items)?)?;
+        }
+        write_module(
+            &app_dir,
+            "listened_events",
+            reference_module(&app_info.listened_events),
+        )?;
+        write_module(
+            &app_dir,
+            "emitted_requests",
+            reference_module(&app_info.emitted_requests),
+        )?;
+
+        let submodules = [
+            "broadcasted_events",
+            "incoming_requests",
+            "outgoing_responses",
+            "listened_events",
+            "emitted_requests",
+        ];
+        write_module(&app_dir, "index", namespaces(submodules))?;
+    }
+
+    write_module(
+        output_dir,
+        "index",
+        namespaces(system_info.apps.keys().map(String::as_str)),
+    )
+}
+
+/// The declarations of the messages of a submodule, next to the types they use, e.g. the enum of a field
+fn declaration_module(message_infos: &[MessageDeclarationInfo]) -> std::io::Result<String> {
+    let mut definitions = BTreeMap::new();
+    for info in message_infos {
+        let (schema, shared) = info.split_schema("#/definitions/");
+        definitions.extend(shared);
+        definitions.insert(info.identifier.clone(), schema);
+    }
+    declarations(&definitions).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Re-exports the messages referenced by a submodule from the modules of the apps declaring them
+fn reference_module(items: &[MessageReferenceInfo]) -> String {
+    items
+        .iter()
+        .map(|item| {
+            format!(
+                "export type {{ {} }} from \"../{}/{}\";\n",
+                item.identifier, item.app_name, item.module
+            )
+        })
+        .collect()
+}
+
+/// Exports modules as namespaces of the same name
+fn namespaces<'a>(modules: impl IntoIterator<Item = &'a str>) -> String {
+    modules
+        .into_iter()
+        .map(|module| format!("export * as {} from \"./{}\";\n", module, module))
+        .collect()
+}
+
+/// Writes a module, which exports nothing when it's empty so that it can still be imported
+fn write_module(dir: &Path, name: &str, content: String) -> std::io::Result<()> {
+    let content = if content.is_empty() {
+        "export {};\n".to_string()
+    } else {
+        content
+    };
+    fs::write(dir.join(format!("{}.ts", name)), content)
+}
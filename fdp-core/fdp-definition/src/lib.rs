@@ -1,10 +1,19 @@
 //! # FDP Definition
 //!
 //! This crate contains the definitions of the apps within the FDP system, placed in the `apps` module.
+//! With the `client` feature, each app also gets its typed `Client`, `Handlers` trait and `serve` function,
+//! generated by the fdp::client macro, e.g. `apps::app_1::Client`.
 //!
 //! This crate also contains the following binaries:
 //! - `doc`: Generates the documentation for the FDP system, to be viewed using `cargo doc --open`.
 //! - `python`: Generates corresponding Python definitions for the FDP system.
+//! - `typescript`: Generates TypeScript declarations of the messages, with the tagged unions as discriminated unions.
+//! - `asyncapi`: Generates an AsyncAPI document, with the MQTT bindings of each message.
+//! - `compat`: Checks the message schemas against the `fdp.lock.json` snapshot, failing on breaking changes
+//!   of messages whose version wasn't bumped. `--update` locks the compatible changes.
+//! - `manifest`: Writes the JSON manifest of the FDP system, with a canonical ordering, for the tools in other languages.
+//! - `diff`: Reports the changes between two manifests in Markdown or JSON,
+//!   and renders them as a graphviz graph with `--graphviz`.
 
 pub mod apps;
 
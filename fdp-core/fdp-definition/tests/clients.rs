@@ -0,0 +1,36 @@
+//! The typed clients and handlers of the apps, generated with the `client` feature.
+//! Handling every listened event of the apps here makes a new listened event a compile error until it is handled.
+#![cfg(feature = "client")]
+
+use fdp_definition::apps::{app_1, app_2};
+use fdp_mqtt_client::BroadcastTransport;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+struct RandomNumbers {
+    values: mpsc::UnboundedSender<i32>,
+}
+
+impl app_2::Handlers for RandomNumbers {
+    async fn on_random_number(&self, event: app_1::broadcasted_events::RandomNumber) {
+        let _ = self.values.send(event.value);
+    }
+}
+
+#[tokio::test]
+async fn serves_the_listened_events() {
+    let bus = BroadcastTransport::new(16);
+    let app_1 = app_1::Client::with_transport(bus.connect());
+    let mut app_2 = app_2::Client::with_transport(bus.connect());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let _handlers = app_2::serve(&mut app_2, RandomNumbers { values: tx }).await;
+    tokio::spawn(app_1.clone().start());
+    tokio::spawn(app_2.clone().start());
+
+    app_1
+        .broadcast(app_1::broadcasted_events::RandomNumber { value: 4 })
+        .await;
+    let received = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+    assert_eq!(received.unwrap(), Some(4));
+}
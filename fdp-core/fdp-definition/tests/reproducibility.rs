@@ -0,0 +1,119 @@
+use fdp_common::graph::FdpSystem;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs a generator binary writing to a file, and returns the generated bytes
+fn generate(binary: &str, output: &Path) -> Vec<u8> {
+    let status = Command::new(binary)
+        .arg("--output")
+        .arg(output)
+        .status()
+        .expect("Failed to run the generator");
+    assert!(status.success(), "{} failed", binary);
+    std::fs::read(output).unwrap()
+}
+
+/// Reads the files of a generated directory, keyed by their path relative to it
+fn read_tree(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                directories.push(path);
+            } else {
+                let content = std::fs::read(&path).unwrap();
+                files.insert(path.strip_prefix(root).unwrap().to_path_buf(), content);
+            }
+        }
+    }
+    files
+}
+
+#[test]
+fn generates_the_same_outputs_on_every_run() {
+    let output_dir =
+        std::env::temp_dir().join(format!("fdp-reproducibility-{}", std::process::id()));
+    std::fs::create_dir_all(&output_dir).unwrap();
+
+    for (binary, file) in [
+        (env!("CARGO_BIN_EXE_manifest"), "manifest.json"),
+        (env!("CARGO_BIN_EXE_asyncapi"), "asyncapi.json"),
+    ] {
+        let output = output_dir.join(file);
+        let first = generate(binary, &output);
+        for _ in 0..3 {
+            assert_eq!(
+                generate(binary, &output),
+                first,
+                "{} changed between runs",
+                file
+            );
+        }
+    }
+
+    // The Pydantic classes are generated by datamodel-codegen, from the merged schemas compared here
+    for (binary, name, args, expected) in [
+        (
+            env!("CARGO_BIN_EXE_python"),
+            "python",
+            &["--skip-codegen"][..],
+            &["broadcasted_events.json", "__init__.py"][..],
+        ),
+        (
+            env!("CARGO_BIN_EXE_typescript"),
+            "typescript",
+            &[][..],
+            &["broadcasted_events.ts", "index.ts"][..],
+        ),
+    ] {
+        let generate_tree = |run: usize| {
+            let output = output_dir.join(format!("{}-{}", name, run));
+            let status = Command::new(binary)
+                .arg("--output")
+                .arg(&output)
+                .args(args)
+                .status()
+                .expect("Failed to run the generator");
+            assert!(status.success(), "{} failed", name);
+            read_tree(&output)
+        };
+        let first = generate_tree(0);
+        for file in expected {
+            assert!(
+                first.keys().any(|path| path.ends_with(file)),
+                "{} wrote no {}",
+                name,
+                file
+            );
+        }
+        for run in 1..3 {
+            let files = generate_tree(run);
+            assert_eq!(
+                files.keys().collect::<Vec<_>>(),
+                first.keys().collect::<Vec<_>>()
+            );
+            for (path, content) in &files {
+                assert!(
+                    first[path] == *content,
+                    "{} changed between runs",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    let graphviz = || {
+        FdpSystem::from(fdp_definition::apps::get_definition())
+            .unwrap()
+            .to_graphviz()
+    };
+    let first = graphviz();
+    for _ in 0..3 {
+        assert_eq!(graphviz(), first);
+    }
+
+    std::fs::remove_dir_all(&output_dir).unwrap();
+}
@@ -2,10 +2,15 @@
 //!
 //! This module provides macros to simplify the definition of message structures and their associated metadata within the FDP system.
 
-use fdp_common::parsing::definition::AppDefinitionModule;
+use fdp_common::parsing::{
+    definition::AppDefinitionModule,
+    extract::{ExtractArgs, ExtractModule},
+    modules::is_fdp_attribute,
+    topic::TopicArgs,
+};
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput, LitStr};
+use syn::{parse_macro_input, DeriveInput, ItemMod};
 
 /// The `fdp::replies_with` macro is used to define a reply for a request.
 /// It expects a path to the reply message as an argument.
@@ -26,7 +31,13 @@ pub fn replies_with(args: TokenStream, input: TokenStream) -> TokenStream {
 }
 
 /// The `fdp::topic` macro is used to define a message to be used within the FDP system.
-/// It expects a topic string as an argument.
+/// It expects a topic string as an argument, optionally followed by the delivery semantics:
+/// `#[fdp::topic("app_1/status", qos = 1, retain = true, codec = "cbor")]`.
+/// The codec is one of `json` (the default), `msgpack`, `cbor` or `postcard`.
+/// A later version of a message, e.g. `version = 2`, is sent on the `<topic>/v2` subtopic,
+/// so that it can be declared next to the previous versions still in use.
+/// A message is a struct, or an enum such as a union tagged with `#[serde(tag = "kind")]`,
+/// whose schema is the `oneOf` of its variants.
 #[proc_macro_attribute]
 pub fn topic(args: TokenStream, input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -35,9 +46,26 @@ pub fn topic(args: TokenStream, input: TokenStream) -> TokenStream {
             compile_error!("A topic string is required for the message macro");
         });
     }
-    let args = parse_macro_input!(args as LitStr);
+    let args = parse_macro_input!(args as TopicArgs);
     let struct_name = &input.ident;
-    let topic = args.value();
+    let topic = fdp_common::mqtt::versioned_topic(&args.topic.value(), args.version.unwrap_or(1));
+    let qos = match args.qos {
+        Some(qos) => quote! { Some(#qos) },
+        None => quote! { None },
+    };
+    let retain = args.retain;
+    let codec = args.codec.map(|codec| {
+        quote! {
+            const CODEC: &'static str = #codec;
+        }
+    });
+    let version = args.version.map(|version| {
+        quote! {
+            fn version() -> u32 {
+                #version
+            }
+        }
+    });
     quote! {
         #[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
         #input
@@ -45,11 +73,35 @@ pub fn topic(args: TokenStream, input: TokenStream) -> TokenStream {
             fn topic() -> &'static str {
                 #topic
             }
+
+            fn qos() -> Option<u8> {
+                #qos
+            }
+
+            fn retain() -> bool {
+                #retain
+            }
+
+            #codec
+            #version
         }
     }
     .into()
 }
 
+/// The `fdp::shared` macro is used to define a type supporting the messages of a definition submodule,
+/// e.g. the type of one of their fields, which is not a message itself.
+/// Its schema is included in the schemas of the messages using it.
+#[proc_macro_attribute]
+pub fn shared(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    quote! {
+        #[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+        #input
+    }
+    .into()
+}
+
 /// The `fdp::event` macro is used to define an event to be used within the FDP system.
 #[proc_macro_attribute]
 pub fn event(_args: TokenStream, input: TokenStream) -> TokenStream {
@@ -63,25 +115,66 @@ pub fn event(_args: TokenStream, input: TokenStream) -> TokenStream {
 }
 
 /// The fdp::definition macro is used to define the messages used and consumed by an application within the FDP system
+/// Its submodules are optional, a missing one declaring or referencing no message
 #[proc_macro_attribute]
 pub fn definition(_attr: TokenStream, input: TokenStream) -> TokenStream {
     let input_module = parse_macro_input!(input as AppDefinitionModule);
     quote!(#input_module).into()
 }
 
-/// The `fdp::extract` macro is used to extract submodule structure, struct declarations and references from a module.
-/// It flattens the top level module name (re-exporting it as `pub use module::*`) for easier access (with according `#[doc(hidden)]`).
-/// It provides errors if the structure of the submodules is not as expected.
-/// It can perform custom validation for any submodule's items.
-/// For any given submodule, it should be able to only allow certain items, reject others and validate propretires of them.
-/// It should also be able to add code to the module, such as impl blocks.
-/// For a sumbodule error, it reports the errors.
-/// It uses a visitor pattern to traverse the module tree.
-/// It creates a `#[doc(hidden)]` get_extracted_information function that returns the extracted information.
-/// The information is stored as a tree/list/ sublists of modules, where each module containts struct items corresponding
-/// to items in the submodule. It could be smart to implement the parsing/visitor implementation of that struct in the same struct maybe?
-/// These abstract structs for modules and items should be stored in the fdp_common crate.
+/// The `fdp::client` macro generates the typed `Client` of an app, its `Handlers` trait and the `serve` function
+/// registering them, which depend on fdp-mqtt-client. It is placed above the fdp::definition macro of the app:
+/// `#[fdp::client] #[fdp::definition] pub mod definition { ... }`.
+/// A crate shared by the apps and tools which don't use MQTT can generate them behind a feature instead,
+/// e.g. `#[cfg_attr(feature = "client", fdp::client)]`, as in fdp-definition.
 #[proc_macro_attribute]
-pub fn extract(_args: TokenStream, _input: TokenStream) -> TokenStream {
-    todo!()
-}
\ No newline at end of file
+pub fn client(_attr: TokenStream, input: TokenStream) -> TokenStream {
+    let mut module = parse_macro_input!(input as ItemMod);
+    let Some(position) = module
+        .attrs
+        .iter()
+        .position(|attr| is_fdp_attribute(attr, "definition"))
+    else {
+        return syn::Error::new_spanned(
+            &module.ident,
+            "The fdp::client macro must be placed above the fdp::definition macro",
+        )
+        .to_compile_error()
+        .into();
+    };
+    // The definition is parsed without its own attribute, which expands it separately
+    let definition_attribute = module.attrs.remove(position);
+    let definition = match syn::parse2::<AppDefinitionModule>(quote!(#module)) {
+        Ok(definition) => definition,
+        // The errors are reported by the fdp::definition macro
+        Err(_) => return quote!(#definition_attribute #module).into(),
+    };
+    let client = definition.client_tokens();
+    quote! {
+        #definition_attribute
+        #module
+
+        #client
+    }
+    .into()
+}
+
+/// The `fdp::extract` macro extracts the submodules of a module and their items, checking them against a declarative spec.
+/// The arguments list the required submodules and the kinds of items each accepts, e.g.
+/// `#[fdp::extract(events(struct, impl), references(use))]`. Without arguments, any submodule is accepted.
+/// The errors of every submodule are reported together.
+/// The module is re-exported at its parent level (`pub use module::*`), and gets a `#[doc(hidden)]`
+/// `get_extracted_information` function returning its `fdp_common::extract::ExtractedInformation`.
+/// The parsing and checks are built in `fdp_common::parsing::extract`, which the fdp::definition macro is built on.
+/// The arguments can't declare per-item validators: they run while the macro expands, so they can't be functions
+/// of the annotated crate. They are set on the `SubmoduleSpec` of the macros built on the extractor instead,
+/// e.g. fdp::definition requiring public message declarations.
+#[proc_macro_attribute]
+pub fn extract(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as ExtractArgs);
+    let module = parse_macro_input!(input as ItemMod);
+    match ExtractModule::new(module, &args.spec()) {
+        Ok(module) => quote!(#module).into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,61 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                #[fdp::topic("app_1/heartbeat")]
+                #[fdp::event]
+                pub struct Heartbeat {
+                    pub uptime: u64,
+                }
+            }
+
+            pub mod listened_events {}
+
+            pub mod incoming_requests {}
+
+            pub mod outgoing_responses {}
+
+            pub mod emitted_requests {}
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {}
+
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Heartbeat;
+            }
+
+            pub mod incoming_requests {}
+
+            pub mod outgoing_responses {}
+
+            pub mod emitted_requests {}
+        }
+    }
+}
+
+use apps::{app_1, app_2};
+use fdp_mqtt_client::BroadcastTransport;
+
+async fn misuse(
+    mut app_1: app_1::Client<BroadcastTransport>,
+    app_2: app_2::Client<BroadcastTransport>,
+) {
+    // app_2 only listens to the heartbeats of app_1
+    app_2
+        .broadcast(app_1::broadcasted_events::Heartbeat { uptime: 3 })
+        .await;
+
+    // app_1 doesn't listen to its own heartbeats
+    let _heartbeats = app_1
+        .listen(|_: app_1::broadcasted_events::Heartbeat| Box::pin(async {}))
+        .await;
+}
+
+fn main() {}
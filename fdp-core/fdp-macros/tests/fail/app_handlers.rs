@@ -0,0 +1,63 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                #[fdp::topic("app_1/heartbeat")]
+                #[fdp::event]
+                pub struct Heartbeat {
+                    pub uptime: u64,
+                }
+            }
+
+            pub mod listened_events {}
+
+            pub mod incoming_requests {
+                #[fdp::topic("app_1/square")]
+                #[fdp::replies_with(super::outgoing_responses::Squared)]
+                pub struct Square {
+                    pub value: i32,
+                }
+            }
+
+            pub mod outgoing_responses {
+                #[fdp::topic("app_1/square/response")]
+                pub struct Squared {
+                    pub value: i32,
+                }
+            }
+
+            pub mod emitted_requests {}
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {}
+
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Heartbeat;
+            }
+
+            pub mod incoming_requests {}
+
+            pub mod outgoing_responses {}
+
+            pub mod emitted_requests {
+                pub use crate::apps::app_1::incoming_requests::Square;
+            }
+        }
+    }
+}
+
+use apps::app_2;
+
+struct Monitor;
+
+// The heartbeats app_2 listens to are not handled
+impl app_2::Handlers for Monitor {}
+
+fn main() {}
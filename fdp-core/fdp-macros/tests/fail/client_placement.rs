@@ -0,0 +1,18 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::definition]
+        #[fdp::client]
+        pub mod definition {
+            pub mod broadcasted_events {}
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        pub mod definition {
+            pub mod broadcasted_events {}
+        }
+    }
+}
+
+fn main() {}
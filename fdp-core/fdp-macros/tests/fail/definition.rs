@@ -0,0 +1,16 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcast_events {
+                #[fdp::topic("app_1/heartbeat")]
+                #[fdp::event]
+                pub struct Heartbeat {
+                    pub uptime: u64,
+                }
+            }
+        }
+    }
+}
+
+fn main() {}
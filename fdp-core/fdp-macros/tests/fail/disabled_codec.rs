@@ -0,0 +1,13 @@
+use fdp_mqtt_client::MqttClient;
+
+#[fdp::topic("test/reading", codec = "msgpack")]
+#[fdp::event]
+struct CompactReading {
+    value: f32,
+}
+
+#[tokio::main]
+async fn main() {
+    let (client, _event_loop) = MqttClient::new("disabled_codec", "localhost", 1883);
+    client.broadcast(CompactReading { value: 1.5 }).await;
+}
@@ -0,0 +1,6 @@
+#[fdp::extract(events(struct), references(use))]
+mod missing_submodule {
+    pub mod events {}
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+#[fdp::extract(events(struct, impl), references(use))]
+mod rejected_items {
+    pub mod events {
+        pub struct Started;
+
+        pub fn start() {}
+    }
+
+    pub mod references {
+        pub struct Reference;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+#[fdp::extract(events(struct))]
+mod unexpected_submodule {
+    pub mod events {}
+
+    pub mod handlers {}
+
+    fn helper() {}
+}
+
+fn main() {}
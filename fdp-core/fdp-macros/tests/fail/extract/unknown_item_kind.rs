@@ -0,0 +1,6 @@
+#[fdp::extract(events(structs))]
+mod unknown_item_kind {
+    pub mod events {}
+}
+
+fn main() {}
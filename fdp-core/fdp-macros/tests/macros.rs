@@ -3,6 +3,17 @@ fn test_macros() {
     let t = trybuild::TestCases::new();
     t.pass("tests/pass/message_macro.rs");
     t.pass("tests/pass/reply_macro.rs");
-    // TODO: Make it pass
+    t.pass("tests/pass/delivery_macro.rs");
+    t.pass("tests/pass/versioned_macro.rs");
+    t.pass("tests/pass/supporting_items.rs");
+    t.pass("tests/pass/enum_messages.rs");
+    t.pass("tests/pass/app_client.rs");
+    t.compile_fail("tests/fail/app_client.rs");
+    t.pass("tests/pass/app_handlers.rs");
+    t.compile_fail("tests/fail/app_handlers.rs");
+    t.compile_fail("tests/fail/definition.rs");
+    t.compile_fail("tests/fail/disabled_codec.rs");
+    t.compile_fail("tests/fail/client_placement.rs");
+    t.pass("tests/pass/extract/*.rs");
     t.compile_fail("tests/fail/extract/*.rs");
 }
@@ -0,0 +1,110 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                #[fdp::topic("app_1/heartbeat")]
+                #[fdp::event]
+                pub struct Heartbeat {
+                    pub uptime: u64,
+                }
+            }
+
+            pub mod listened_events {}
+
+            pub mod incoming_requests {
+                #[fdp::topic("app_1/square")]
+                #[fdp::replies_with(super::outgoing_responses::Squared)]
+                pub struct Square {
+                    pub value: i32,
+                }
+            }
+
+            pub mod outgoing_responses {
+                #[fdp::topic("app_1/square/response")]
+                pub struct Squared {
+                    pub value: i32,
+                }
+            }
+
+            pub mod emitted_requests {}
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {}
+
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Heartbeat;
+            }
+
+            pub mod incoming_requests {}
+
+            pub mod outgoing_responses {}
+
+            pub mod emitted_requests {
+                pub use crate::apps::app_1::incoming_requests::Square;
+            }
+        }
+    }
+}
+
+use apps::{app_1, app_2};
+use fdp_mqtt_client::BroadcastTransport;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+fn main() {
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let bus = BroadcastTransport::new(16);
+        let mut app_1 = app_1::Client::with_transport(bus.connect());
+        let mut app_2 = app_2::Client::with_transport(bus.connect());
+
+        let _squares = app_1
+            .respond(|request: app_1::incoming_requests::Square| {
+                Box::pin(async move {
+                    app_1::outgoing_responses::Squared {
+                        value: request.value * request.value,
+                    }
+                })
+            })
+            .await;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let heartbeats = tx.clone();
+        let _heartbeats = app_2
+            .listen(move |heartbeat: app_1::broadcasted_events::Heartbeat| {
+                let _ = heartbeats.send(heartbeat.uptime as i32);
+                Box::pin(async {})
+            })
+            .await;
+        let _responses = app_2
+            .on_response::<app_2::emitted_requests::Square, _>(move |response| {
+                let _ = tx.send(response.value);
+                Box::pin(async {})
+            })
+            .await;
+
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        app_1
+            .broadcast(app_1::broadcasted_events::Heartbeat { uptime: 3 })
+            .await;
+        app_2
+            .request(app_2::emitted_requests::Square { value: 4 })
+            .await;
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let value = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+            received.push(value.unwrap().unwrap());
+        }
+        received.sort();
+        assert_eq!(received, vec![3, 16]);
+    });
+}
@@ -0,0 +1,114 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                #[fdp::topic("app_1/heartbeat")]
+                #[fdp::event]
+                pub struct Heartbeat {
+                    pub uptime: u64,
+                }
+            }
+
+            pub mod incoming_requests {
+                #[fdp::topic("app_1/square")]
+                #[fdp::replies_with(super::outgoing_responses::Squared)]
+                pub struct Square {
+                    pub value: i32,
+                }
+            }
+
+            pub mod outgoing_responses {
+                #[fdp::topic("app_1/square/response")]
+                pub struct Squared {
+                    pub value: i32,
+                }
+            }
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Heartbeat;
+            }
+
+            pub mod emitted_requests {
+                pub use crate::apps::app_1::incoming_requests::Square;
+            }
+        }
+    }
+}
+
+use apps::{app_1, app_2};
+use fdp_mqtt_client::BroadcastTransport;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+struct Squarer;
+
+impl app_1::Handlers for Squarer {
+    async fn handle_square(
+        &self,
+        request: app_1::incoming_requests::Square,
+    ) -> app_1::outgoing_responses::Squared {
+        app_1::outgoing_responses::Squared {
+            value: request.value * request.value,
+        }
+    }
+}
+
+struct Monitor {
+    uptimes: mpsc::UnboundedSender<u64>,
+}
+
+impl app_2::Handlers for Monitor {
+    async fn on_heartbeat(&self, event: app_1::broadcasted_events::Heartbeat) {
+        let _ = self.uptimes.send(event.uptime);
+    }
+}
+
+fn main() {
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let bus = BroadcastTransport::new(16);
+        let mut app_1 = app_1::Client::with_transport(bus.connect());
+        let mut app_2 = app_2::Client::with_transport(bus.connect());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _app_1_handlers = app_1::serve(&mut app_1, Squarer).await;
+        let _app_2_handlers = app_2::serve(&mut app_2, Monitor { uptimes: tx }).await;
+
+        let (responses_tx, mut responses) = mpsc::unbounded_channel();
+        let _responses = app_2
+            .on_response::<app_2::emitted_requests::Square, _>(move |response| {
+                let _ = responses_tx.send(response.value);
+                Box::pin(async {})
+            })
+            .await;
+
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        app_1
+            .broadcast(app_1::broadcasted_events::Heartbeat { uptime: 3 })
+            .await;
+        app_2
+            .request(app_2::emitted_requests::Square { value: 4 })
+            .await;
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            tokio::time::timeout(timeout, rx.recv()).await.unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            tokio::time::timeout(timeout, responses.recv())
+                .await
+                .unwrap(),
+            Some(16)
+        );
+    });
+}
@@ -0,0 +1,28 @@
+use fdp_common::mqtt::Message;
+
+#[fdp::topic("test/default")]
+struct DefaultMessage {
+    field: String,
+}
+
+#[fdp::topic("test/status", qos = 1, retain = true)]
+struct StatusMessage {
+    online: bool,
+}
+
+#[fdp::topic("test/reading", codec = "cbor")]
+struct CompactMessage {
+    value: f32,
+}
+
+fn main() {
+    assert_eq!(DefaultMessage::qos(), None);
+    assert!(!DefaultMessage::retain());
+    assert_eq!(DefaultMessage::codec(), "json");
+
+    assert_eq!(StatusMessage::topic(), "test/status");
+    assert_eq!(StatusMessage::qos(), Some(1));
+    assert!(StatusMessage::retain());
+
+    assert_eq!(CompactMessage::codec(), "cbor");
+}
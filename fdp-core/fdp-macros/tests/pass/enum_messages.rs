@@ -0,0 +1,148 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                /// The commands of a robot, tagged with their kind
+                #[fdp::topic("app_1/command")]
+                #[fdp::event]
+                #[serde(tag = "kind", rename_all = "snake_case")]
+                pub enum Command {
+                    Stop,
+                    Move { speed: f32 },
+                }
+            }
+
+            pub mod incoming_requests {
+                #[fdp::topic("app_1/shape")]
+                #[fdp::replies_with(super::outgoing_responses::Area)]
+                #[serde(tag = "shape")]
+                pub enum Shape {
+                    Square { side: f32 },
+                    Rectangle { width: f32, height: f32 },
+                }
+            }
+
+            pub mod outgoing_responses {
+                #[fdp::topic("app_1/shape/response")]
+                #[serde(tag = "result", content = "value")]
+                pub enum Area {
+                    Computed(f32),
+                    Invalid(String),
+                }
+            }
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::client]
+        #[fdp::definition]
+        pub mod definition {
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Command;
+            }
+
+            pub mod emitted_requests {
+                pub use crate::apps::app_1::incoming_requests::Shape;
+            }
+        }
+    }
+}
+
+use apps::{app_1, app_2};
+use fdp_mqtt_client::BroadcastTransport;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+struct Geometry;
+
+impl app_1::Handlers for Geometry {
+    async fn handle_shape(
+        &self,
+        request: app_1::incoming_requests::Shape,
+    ) -> app_1::outgoing_responses::Area {
+        use app_1::{incoming_requests::Shape, outgoing_responses::Area};
+        match request {
+            Shape::Square { side } => Area::Computed(side * side),
+            Shape::Rectangle { width, height } if width < 0.0 || height < 0.0 => {
+                Area::Invalid("negative size".to_string())
+            }
+            Shape::Rectangle { width, height } => Area::Computed(width * height),
+        }
+    }
+}
+
+struct Robot {
+    speeds: mpsc::UnboundedSender<f32>,
+}
+
+impl app_2::Handlers for Robot {
+    async fn on_command(&self, event: app_1::broadcasted_events::Command) {
+        if let app_1::broadcasted_events::Command::Move { speed } = event {
+            let _ = self.speeds.send(speed);
+        }
+    }
+}
+
+fn main() {
+    // The schemas of the enum messages are unions of their variants
+    let definition = app_1::get_definition();
+    for info in definition
+        .broadcasted_events
+        .iter()
+        .chain(&definition.incoming_requests)
+        .chain(&definition.outgoing_responses)
+    {
+        let one_of = info
+            .schema
+            .schema
+            .subschemas
+            .as_ref()
+            .unwrap()
+            .one_of
+            .as_ref();
+        assert_eq!(one_of.unwrap().len(), 2, "{}", info.identifier);
+    }
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let bus = BroadcastTransport::new(16);
+        let mut app_1 = app_1::Client::with_transport(bus.connect());
+        let mut app_2 = app_2::Client::with_transport(bus.connect());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _app_1_handlers = app_1::serve(&mut app_1, Geometry).await;
+        let _app_2_handlers = app_2::serve(&mut app_2, Robot { speeds: tx }).await;
+
+        let (responses_tx, mut responses) = mpsc::unbounded_channel();
+        let _responses = app_2
+            .on_response::<app_2::emitted_requests::Shape, _>(move |response| {
+                let _ = responses_tx.send(response);
+                Box::pin(async {})
+            })
+            .await;
+
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        app_1
+            .broadcast(app_1::broadcasted_events::Command::Move { speed: 1.5 })
+            .await;
+        app_2
+            .request(app_2::emitted_requests::Shape::Square { side: 3.0 })
+            .await;
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            tokio::time::timeout(timeout, rx.recv()).await.unwrap(),
+            Some(1.5)
+        );
+        let response = tokio::time::timeout(timeout, responses.recv())
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            Some(app_1::outgoing_responses::Area::Computed(area)) if area == 9.0
+        ));
+    });
+}
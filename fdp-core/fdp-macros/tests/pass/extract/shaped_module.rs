@@ -0,0 +1,51 @@
+use fdp_common::extract::{ExtractedItem, ItemKind};
+
+pub struct Shared;
+
+#[fdp::extract(events(struct, impl), references(use))]
+pub mod shaped_module {
+    pub mod events {
+        pub struct Started;
+
+        struct Stopped;
+
+        impl Started {
+            pub fn new() -> Self {
+                Started
+            }
+        }
+    }
+
+    pub mod references {
+        pub use crate::Shared;
+    }
+}
+
+fn main() {
+    let information = get_extracted_information();
+    assert_eq!(information.module, "shaped_module");
+
+    let events = information.submodule("events").unwrap();
+    let kinds: Vec<ItemKind> = events.items.iter().map(|item| item.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![ItemKind::Struct, ItemKind::Struct, ItemKind::Impl]
+    );
+    assert!(events.items[0].public);
+    assert!(!events.items[1].public);
+
+    let references = information.submodule("references").unwrap();
+    assert_eq!(
+        references.items,
+        vec![ExtractedItem {
+            kind: ItemKind::Use,
+            ident: "Shared".to_string(),
+            public: true,
+            path: Some("crate::Shared".to_string()),
+        }]
+    );
+
+    // The module is re-exported at its parent level
+    let _ = events::Started::new();
+    let _ = shaped_module::references::Shared;
+}
@@ -6,6 +6,9 @@ mod simple_module {}
 fn main() {
     assert_eq!(
         simple_module::get_extracted_information(),
-        ExtractedInformation {}
+        ExtractedInformation {
+            module: "simple_module".to_string(),
+            submodules: vec![],
+        }
     );
 }
@@ -0,0 +1,90 @@
+mod apps {
+    pub mod app_1 {
+        #[fdp::definition]
+        pub mod definition {
+            pub mod broadcasted_events {
+                //! The status of the heating
+
+                /// The number of rooms that are heated
+                pub const ROOMS: usize = 3;
+
+                pub type Celsius = f32;
+
+                /// The mode of the heating
+                #[derive(
+                    Debug,
+                    Clone,
+                    Copy,
+                    PartialEq,
+                    serde::Serialize,
+                    serde::Deserialize,
+                    schemars::JsonSchema,
+                )]
+                pub enum Mode {
+                    Off,
+                    Eco,
+                    Comfort,
+                    #[cfg(any())]
+                    Boost,
+                }
+
+                #[fdp::shared]
+                pub struct Room {
+                    pub name: String,
+                    pub target: Celsius,
+                }
+
+                /// The heating status, broadcasted on every change
+                #[fdp::topic("app_1/heating")]
+                #[fdp::event]
+                pub struct Heating {
+                    pub mode: Mode,
+                    pub rooms: Vec<Room>,
+                }
+
+                impl Heating {
+                    pub fn is_on(&self) -> bool {
+                        self.mode != Mode::Off
+                    }
+                }
+            }
+        }
+    }
+
+    pub mod app_2 {
+        #[fdp::definition]
+        pub mod definition {
+            pub mod listened_events {
+                pub use crate::apps::app_1::broadcasted_events::Heating;
+            }
+        }
+    }
+}
+
+use app_1::broadcasted_events::{Heating, Mode, Room, ROOMS};
+use apps::app_1;
+
+fn main() {
+    let definition = app_1::get_definition();
+    let messages: Vec<&str> = definition
+        .broadcasted_events
+        .iter()
+        .map(|info| info.identifier.as_str())
+        .collect();
+    assert_eq!(messages, vec!["Heating"]);
+
+    // The schemas of the supporting types are definitions of the message schema
+    let schema = &definition.broadcasted_events[0].schema;
+    assert!(schema.definitions.contains_key("Mode"));
+    assert!(schema.definitions.contains_key("Room"));
+
+    let heating = Heating {
+        mode: Mode::Eco,
+        rooms: vec![Room {
+            name: "kitchen".to_string(),
+            target: 20.5,
+        }],
+    };
+    assert!(heating.is_on());
+    assert_eq!(ROOMS, 3);
+}
@@ -0,0 +1,33 @@
+use fdp_common::mqtt::Message;
+
+#[fdp::topic("test/temperature")]
+struct Temperature {
+    celsius: f32,
+}
+
+#[fdp::topic("test/temperature", version = 2)]
+struct TemperatureV2 {
+    celsius: f32,
+    sensor: Option<String>,
+}
+
+impl From<Temperature> for TemperatureV2 {
+    fn from(temperature: Temperature) -> Self {
+        TemperatureV2 {
+            celsius: temperature.celsius,
+            sensor: None,
+        }
+    }
+}
+
+fn main() {
+    assert_eq!(Temperature::topic(), "test/temperature");
+    assert_eq!(Temperature::version(), 1);
+
+    assert_eq!(TemperatureV2::topic(), "test/temperature/v2");
+    assert_eq!(TemperatureV2::version(), 2);
+
+    let temperature = TemperatureV2::from(Temperature { celsius: 21.5 });
+    assert_eq!(temperature.celsius, 21.5);
+    assert!(temperature.sensor.is_none());
+}
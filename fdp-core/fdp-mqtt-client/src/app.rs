@@ -0,0 +1,153 @@
+//! The typed client of an app, restricted to the messages of its definition
+
+use crate::{
+    BroadcastTransport, Envelope, MqttClient, MqttClientBuilder, Received, SchemaValidation,
+    Transport,
+};
+use fdp_common::mqtt::{
+    App, BroadcastedEvent, EmittedRequest, IncomingRequest, ListenedEvent, Request,
+};
+use mqtt_client::{AsyncCallback, EventLoop, MqttClient as RawMqttClient};
+use std::marker::PhantomData;
+
+/// A client that only accepts the messages the app `A` declares or references in its definition:
+/// it broadcasts its `broadcasted_events`, listens to its `listened_events`, emits its `emitted_requests`
+/// and responds to its `incoming_requests`. Other messages are rejected at compile time.
+/// The `fdp::definition` macro generates an alias for every app, e.g. `app_1::Client`.
+/// The app is the source of the messages published in an envelope.
+pub struct AppClient<A, T: Transport = RawMqttClient> {
+    client: MqttClient<T>,
+    app: PhantomData<fn() -> A>,
+}
+
+impl<A, T: Transport> Clone for AppClient<A, T> {
+    fn clone(&self) -> Self {
+        AppClient {
+            client: self.client.clone(),
+            app: PhantomData,
+        }
+    }
+}
+
+impl<A: App, T: Transport> From<MqttClient<T>> for AppClient<A, T> {
+    fn from(client: MqttClient<T>) -> Self {
+        AppClient {
+            client: client.with_source(A::name()),
+            app: PhantomData,
+        }
+    }
+}
+
+impl<A: App> AppClient<A> {
+    /// Creates a new instance of the client, connecting to an MQTT broker.
+    pub fn new(client_id: &str, host: &str, port: u16) -> (Self, EventLoop) {
+        let (client, event_loop) = MqttClient::new(client_id, host, port);
+        (client.into(), event_loop)
+    }
+
+    /// Creates a new instance of the client from a configured builder.
+    pub fn from_builder(builder: MqttClientBuilder) -> (Self, EventLoop) {
+        let (client, event_loop) = MqttClient::from_builder(builder);
+        (client.into(), event_loop)
+    }
+
+    /// Starts the MQTT client and begins processing incoming messages, until the client is shut down.
+    pub async fn start(self, event_loop: EventLoop) {
+        self.client.start(event_loop).await;
+    }
+}
+
+impl<A: App> AppClient<A, BroadcastTransport> {
+    /// Starts processing the messages published on the bus, until the client is shut down.
+    pub async fn start(self) {
+        self.client.start().await;
+    }
+}
+
+impl<A: App, T: Transport> AppClient<A, T> {
+    /// Creates a client publishing and subscribing through the given transport
+    pub fn with_transport(transport: T) -> Self {
+        MqttClient::with_transport(transport).into()
+    }
+
+    /// Validates the payloads against the JSON schemas of their messages, see `MqttClient::with_validation`
+    pub fn with_validation(self, validation: SchemaValidation) -> Self {
+        self.client.with_validation(validation).into()
+    }
+
+    /// Publishes the messages in an envelope carrying their metadata, see `MqttClient::with_envelope`
+    pub fn with_envelope(self, envelope: Envelope) -> Self {
+        self.client.with_envelope(envelope).into()
+    }
+
+    /// The transport of the client
+    pub fn transport(&self) -> &T {
+        &self.client.client
+    }
+
+    /// Broadcasts one of the app's events
+    pub async fn broadcast<E: BroadcastedEvent<A>>(&self, event: E) {
+        self.client.broadcast(event).await;
+    }
+
+    /// Emits one of the app's requests
+    pub async fn request<R: EmittedRequest<A>>(&self, request: R) {
+        self.client.request(request).await;
+    }
+
+    /// Registers a listener for one of the events the app listens to.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn listen<C, E>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(E) -> AsyncCallback<()> + Send + Sync + 'static,
+        E: ListenedEvent<A>,
+    {
+        self.client.listen(callback).await
+    }
+
+    /// Registers a listener for two versions of an event the app listens to, converting the previous version
+    /// with `From`, see `MqttClient::listen_versions`. Both versions must be listened to in the app's definition.
+    pub async fn listen_versions<P, E, C>(&mut self, callback: C) -> [T::Subscription; 2]
+    where
+        C: Fn(E) -> AsyncCallback<()> + Send + Sync + 'static,
+        P: ListenedEvent<A>,
+        E: ListenedEvent<A> + From<P>,
+    {
+        self.client.listen_versions::<P, E, C>(callback).await
+    }
+
+    /// Registers a listener for one of the events the app listens to, receiving their metadata with them.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn listen_with_metadata<C, E>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(Received<E>) -> AsyncCallback<()> + Send + Sync + 'static,
+        E: ListenedEvent<A>,
+    {
+        self.client.listen_with_metadata(callback).await
+    }
+
+    /// Registers a listener for the responses to one of the app's requests.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn on_response<R, C>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(R::Response) -> AsyncCallback<()> + Send + Sync + 'static,
+        R: EmittedRequest<A>,
+    {
+        self.client.on_response::<R, C>(callback).await
+    }
+
+    /// Registers the handler of one of the requests the app responds to.
+    /// The handler stays registered until the returned subscription is cancelled or dropped.
+    pub async fn respond<C, R>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(R) -> AsyncCallback<<R as Request>::Response> + Send + Sync + 'static,
+        R: IncomingRequest<A>,
+    {
+        self.client.respond(callback).await
+    }
+
+    /// Waits for the running listeners and request handlers to complete, and disconnects from the transport.
+    pub async fn shutdown(&self) {
+        self.client.shutdown().await;
+    }
+}
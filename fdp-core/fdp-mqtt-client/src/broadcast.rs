@@ -0,0 +1,177 @@
+//! An in-process transport, for single-binary deployments and tests
+
+use crate::transport::Transport;
+use mqtt_client::{
+    AsyncCallback, Codec, DeadLetter, DispatchLimits, EventDispatcher, HandlerId, PublishOptions,
+    QoS, UserProperties,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Notify;
+
+/// A message published on the bus, with its topic and user properties
+type Packet = (String, Vec<u8>, UserProperties);
+
+/// Connects the apps of a single process through a tokio broadcast channel.
+/// Every app connects its own transport to the bus with `connect`, and receives the messages
+/// published by all of them once started, like MQTT clients sharing a broker.
+#[derive(Clone)]
+pub struct BroadcastTransport {
+    bus: broadcast::Sender<Packet>,
+    /// The receiving end of the bus, taken by `start`
+    receiver: Arc<Mutex<Option<broadcast::Receiver<Packet>>>>,
+    dispatcher: Arc<EventDispatcher>,
+    shutting_down: Arc<AtomicBool>,
+    stopped: Arc<Notify>,
+}
+
+impl BroadcastTransport {
+    /// Creates a bus buffering up to `capacity` messages per app, and connects a first transport to it
+    pub fn new(capacity: usize) -> Self {
+        let (bus, _) = broadcast::channel(capacity);
+        Self::on_bus(bus, DispatchLimits::default())
+    }
+
+    /// Connects another transport to the same bus, with its own handlers
+    pub fn connect(&self) -> Self {
+        Self::on_bus(self.bus.clone(), DispatchLimits::default())
+    }
+
+    /// Connects another transport to the same bus, limiting the concurrency of its handlers
+    pub fn connect_with_limits(&self, limits: DispatchLimits) -> Self {
+        Self::on_bus(self.bus.clone(), limits)
+    }
+
+    fn on_bus(bus: broadcast::Sender<Packet>, limits: DispatchLimits) -> Self {
+        BroadcastTransport {
+            receiver: Arc::new(Mutex::new(Some(bus.subscribe()))),
+            bus,
+            dispatcher: Arc::new(EventDispatcher::with_limits(limits)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Sets the hook called with the payloads rejected before reaching their handler
+    pub fn on_dead_letter<H>(&self, hook: H)
+    where
+        H: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        self.dispatcher.on_dead_letter(hook);
+    }
+
+    /// Dispatches the messages published on the bus to the handlers, until the transport is shut down.
+    /// The messages published since the transport was connected are not lost.
+    pub async fn start(self) {
+        let mut receiver = self
+            .receiver
+            .lock()
+            .unwrap()
+            .take()
+            .expect("The transport is already started");
+        loop {
+            let packet = tokio::select! {
+                _ = self.stopped.notified() => break,
+                packet = receiver.recv() => packet,
+            };
+            match packet {
+                Ok((topic, payload, properties)) => {
+                    if !self.shutting_down.load(Ordering::SeqCst) {
+                        self.dispatcher
+                            .dispatch_with_properties(&topic, payload, properties)
+                            .await;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("The transport fell behind and missed {} messages", missed)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Keeps a handler registered on a BroadcastTransport until it is cancelled or dropped
+#[must_use = "the handler is unregistered as soon as the subscription is dropped"]
+pub struct BroadcastSubscription {
+    dispatcher: Arc<EventDispatcher>,
+    filter: String,
+    id: Option<HandlerId>,
+}
+
+impl BroadcastSubscription {
+    /// Unregisters the handler
+    pub fn cancel(self) {}
+
+    /// Releases the subscription, leaving the handler registered
+    pub fn detach(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for BroadcastSubscription {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.dispatcher.remove_handler(&self.filter, id);
+        }
+    }
+}
+
+impl Transport for BroadcastTransport {
+    type Subscription = BroadcastSubscription;
+
+    fn default_qos(&self) -> QoS {
+        QoS::AtMostOnce
+    }
+
+    fn subscription_qos(&self) -> QoS {
+        QoS::AtMostOnce
+    }
+
+    async fn publish<P, C>(&self, topic: &str, payload: P, _options: PublishOptions, codec: &C)
+    where
+        P: Serialize + Send,
+        C: Codec,
+    {
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_mut))]
+        let (payload, mut properties) = codec.encode_with_properties(&payload).unwrap();
+        #[cfg(feature = "telemetry")]
+        let _span = mqtt_client::telemetry::on_publish(topic, &mut properties).entered();
+        // Publishing without any connected transport is not an error, like publishing without subscribers
+        let _ = self.bus.send((topic.to_owned(), payload, properties));
+    }
+
+    async fn subscribe<P, C, F>(
+        &mut self,
+        filter: &str,
+        _qos: QoS,
+        codec: C,
+        handler: F,
+    ) -> BroadcastSubscription
+    where
+        P: DeserializeOwned + Send + 'static,
+        C: Codec,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+    {
+        let id = self
+            .dispatcher
+            .add_topic_handler_with_codec(filter, codec, handler);
+        BroadcastSubscription {
+            dispatcher: self.dispatcher.clone(),
+            filter: filter.to_owned(),
+            id: Some(id),
+        }
+    }
+
+    fn report_dead_letter(&self, letter: DeadLetter) {
+        self.dispatcher.report_dead_letter(letter);
+    }
+
+    async fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.dispatcher.in_flight().wait_idle().await;
+        self.stopped.notify_one();
+    }
+}
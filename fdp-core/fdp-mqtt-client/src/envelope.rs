@@ -0,0 +1,215 @@
+//! The envelope carrying the metadata of the messages next to their payloads
+
+use mqtt_client::{CodecError, UserProperties};
+use serde::{Deserialize, Serialize};
+use std::num::ParseIntError;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// The user properties carrying the metadata
+const ID: &str = "fdp-id";
+const TIMESTAMP: &str = "fdp-timestamp";
+const SOURCE: &str = "fdp-source";
+const SCHEMA_VERSION: &str = "fdp-schema-version";
+const TRACEPARENT: &str = "traceparent";
+
+/// How the metadata of the published messages is carried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Envelope {
+    /// Messages are published as bare payloads, without metadata
+    #[default]
+    Disabled,
+    /// The metadata is carried in MQTT v5 user properties, leaving the payloads unchanged
+    UserProperties,
+    /// The payloads are wrapped in a `{ "metadata": ..., "payload": ... }` object, encoded with the message codec.
+    /// The listeners of the messages must use the same envelope to decode them.
+    Wrapped,
+}
+
+/// The metadata of a message, set when it is published
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The unique identifier of the message
+    pub id: String,
+    /// When the message was published, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// The app that published the message, when it was published by a typed app client
+    pub source: Option<String>,
+    /// The version of the message schema
+    pub schema_version: u32,
+    /// The W3C trace context of the message, missing when it wasn't published within a recorded trace
+    pub traceparent: Option<String>,
+}
+
+impl Metadata {
+    /// Creates the metadata of a message published now
+    pub fn new(source: Option<&str>, schema_version: u32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Metadata {
+            id: Uuid::new_v4().to_string(),
+            timestamp,
+            source: source.map(String::from),
+            schema_version,
+            traceparent: traceparent(),
+        }
+    }
+
+    /// The user properties carrying the metadata
+    pub fn to_properties(&self) -> UserProperties {
+        let mut properties = vec![
+            (ID.to_owned(), self.id.clone()),
+            (TIMESTAMP.to_owned(), self.timestamp.to_string()),
+            (SCHEMA_VERSION.to_owned(), self.schema_version.to_string()),
+        ];
+        if let Some(source) = &self.source {
+            properties.push((SOURCE.to_owned(), source.clone()));
+        }
+        if let Some(traceparent) = &self.traceparent {
+            properties.push((TRACEPARENT.to_owned(), traceparent.clone()));
+        }
+        properties
+    }
+
+    /// Reads the metadata from user properties.
+    /// It returns `None` if the message was published without metadata.
+    pub fn from_properties(properties: &UserProperties) -> Result<Option<Self>, CodecError> {
+        let property = |name: &str| {
+            properties
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        let Some(id) = property(ID) else {
+            return Ok(None);
+        };
+        Ok(Some(Metadata {
+            id,
+            timestamp: number(TIMESTAMP, property(TIMESTAMP))?,
+            source: property(SOURCE),
+            schema_version: number(SCHEMA_VERSION, property(SCHEMA_VERSION))?,
+            traceparent: property(TRACEPARENT),
+        }))
+    }
+}
+
+/// Parses a numeric property, rejecting the values out of the range of its type
+fn number<T: FromStr<Err = ParseIntError>>(
+    name: &str,
+    value: Option<String>,
+) -> Result<T, CodecError> {
+    let value = value.ok_or_else(|| format!("The '{}' property is missing", name))?;
+    value.parse().map_err(|error| {
+        format!(
+            "The '{}' property is not a valid number: {} ({})",
+            name, value, error
+        )
+        .into()
+    })
+}
+
+/// The trace context of a published message, the one of the current span.
+/// It is missing when the spans aren't recorded by a `tracing_opentelemetry` layer.
+#[cfg(feature = "telemetry")]
+fn traceparent() -> Option<String> {
+    let mut properties = UserProperties::new();
+    mqtt_client::telemetry::inject(&tracing::Span::current(), &mut properties);
+    properties
+        .into_iter()
+        .find(|(key, _)| key == TRACEPARENT)
+        .map(|(_, traceparent)| traceparent)
+}
+
+/// The trace context of a published message, which is missing without telemetry, no trace being recorded
+#[cfg(not(feature = "telemetry"))]
+fn traceparent() -> Option<String> {
+    None
+}
+
+/// A received message, with the metadata of its envelope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Received<E> {
+    /// The metadata of the message, missing when it was published without envelope
+    pub metadata: Option<Metadata>,
+    /// The message itself
+    pub message: E,
+}
+
+impl<E> Received<E> {
+    /// Drops the metadata, returning the message
+    pub fn into_message(self) -> E {
+        self.message
+    }
+}
+
+impl<E> Deref for Received<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.message
+    }
+}
+
+/// A payload wrapped with its metadata, with the `Envelope::Wrapped` envelope
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Wrapped<P> {
+    pub metadata: Metadata,
+    pub payload: P,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_metadata_in_user_properties() {
+        let metadata = Metadata::new(Some("app_1"), 2);
+        // No span is recorded here, so there is no trace context to carry
+        assert_eq!(metadata.traceparent, None);
+        let properties = metadata.to_properties();
+        assert_eq!(
+            Metadata::from_properties(&properties).unwrap(),
+            Some(metadata.clone())
+        );
+
+        let metadata = Metadata {
+            traceparent: Some(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+            ),
+            ..metadata
+        };
+        let properties = metadata.to_properties();
+        assert_eq!(
+            Metadata::from_properties(&properties).unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[test]
+    fn reads_messages_without_metadata() {
+        let properties = vec![("unit".to_owned(), "celsius".to_owned())];
+        assert_eq!(Metadata::from_properties(&properties).unwrap(), None);
+
+        let properties = vec![
+            (ID.to_owned(), "1".to_owned()),
+            (TIMESTAMP.to_owned(), "yesterday".to_owned()),
+        ];
+        assert!(Metadata::from_properties(&properties).is_err());
+    }
+
+    #[test]
+    fn rejects_schema_versions_out_of_range() {
+        let mut properties = Metadata::new(None, 1).to_properties();
+        for (key, value) in properties.iter_mut() {
+            if key == SCHEMA_VERSION {
+                *value = (u32::MAX as u64 + 1).to_string();
+            }
+        }
+        let error = Metadata::from_properties(&properties).unwrap_err();
+        assert!(error.to_string().contains(SCHEMA_VERSION), "{}", error);
+    }
+}
@@ -1,56 +1,669 @@
 //! A mqtt-client wrapper designed to be used with the FDP system
 
+pub use app::AppClient;
+pub use broadcast::{BroadcastSubscription, BroadcastTransport};
+pub use envelope::{Envelope, Metadata, Received};
 use fdp_common::mqtt::{Event, Message, Request};
+use message_codec::MessageCodec;
+/// The MQTT client, the default transport of the typed clients
+pub use mqtt_client::MqttClient as MqttTransport;
 use mqtt_client::{AsyncCallback, EventLoop, MqttClient as RawMqttClient};
+pub use mqtt_client::{
+    Codec, ConfigError, DeadLetter, DispatchLimits, LastWillConfig, MqttClientBuilder,
+    MqttClientConfig, PayloadCodec, PublishOptions, QoS, QueuePolicy, SubscriptionGuard, TlsConfig,
+};
+use std::sync::Arc;
+use telemetry::instrument;
+pub use transport::{Reply, Transport};
+use validation::{type_name, Validators};
+pub use validation::{SchemaValidation, SchemaViolation};
 
+mod app;
+mod broadcast;
+mod envelope;
+mod message_codec;
+mod telemetry;
+mod transport;
+mod validation;
+
+/// The typed FDP client, publishing and subscribing to the declared messages through a transport.
+/// The transport is MQTT unless the client is created with `with_transport`.
 #[derive(Clone)]
-pub struct MqttClient {
-    pub client: RawMqttClient,
+pub struct MqttClient<T: Transport = RawMqttClient> {
+    pub client: T,
+    validation: SchemaValidation,
+    validators: Arc<Validators>,
+    envelope: Envelope,
+    /// The app publishing the messages, carried in their envelope
+    source: Option<Arc<str>>,
 }
 
 impl MqttClient {
     /// Creates a new instance of the MqttClient.
     pub fn new(client_id: &str, host: &str, port: u16) -> (Self, EventLoop) {
         let (client, event_loop) = RawMqttClient::new(client_id, host, port);
-        (MqttClient { client }, event_loop)
+        (Self::with_transport(client), event_loop)
+    }
+
+    /// Creates a builder to configure the underlying MQTT client before connecting.
+    /// The configured builder is turned into a client with `MqttClient::from_builder`.
+    pub fn builder(client_id: &str, host: &str, port: u16) -> MqttClientBuilder {
+        RawMqttClient::builder(client_id, host, port)
+    }
+
+    /// Creates a new instance of the MqttClient from a configured builder.
+    pub fn from_builder(builder: MqttClientBuilder) -> (Self, EventLoop) {
+        let (client, event_loop) = builder.build();
+        (Self::with_transport(client), event_loop)
+    }
+
+    /// Starts the MQTT client and begins processing incoming messages, until the client is shut down.
+    pub async fn start(self, event_loop: EventLoop) {
+        self.client.start(event_loop).await;
+    }
+}
+
+impl MqttClient<BroadcastTransport> {
+    /// Starts processing the messages published on the bus, until the client is shut down.
+    pub async fn start(self) {
+        self.client.start().await;
+    }
+}
+
+impl<T: Transport> MqttClient<T> {
+    /// Creates a client publishing and subscribing through the given transport
+    pub fn with_transport(client: T) -> Self {
+        MqttClient {
+            client,
+            validation: SchemaValidation::Disabled,
+            validators: Arc::default(),
+            envelope: Envelope::Disabled,
+            source: None,
+        }
+    }
+
+    /// Validates the payloads against the JSON schemas of their messages.
+    /// Rejected payloads are reported to the dead letter hook of the client with a `SchemaViolation` error,
+    /// and are neither passed to the listeners nor published.
+    /// Listeners and request handlers only use the validation mode set before they are registered.
+    pub fn with_validation(mut self, validation: SchemaValidation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Publishes the messages in an envelope carrying their metadata.
+    /// Listeners and request handlers only use the envelope set before they are registered.
+    pub fn with_envelope(mut self, envelope: Envelope) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Names the app publishing the messages in their envelope
+    pub fn with_source(mut self, app: &str) -> Self {
+        self.source = Some(app.into());
+        self
     }
 
     /// Broadcasts an Event
     pub async fn broadcast<E: Event>(&self, event: E) {
-        self.client.publish(E::topic(), event).await;
+        self.publish(event).await;
     }
 
     /// Emit a Request
     pub async fn request<R: Request>(&self, request: R) {
-        self.client.publish(R::topic(), request).await;
+        self.publish(request).await;
+    }
+
+    /// Publishes a message on its topic with its codec
+    async fn publish<M: Message>(&self, message: M) {
+        if let Some(message) = self.checked(message) {
+            let options = self.publish_options::<M>();
+            let codec = self.message_codec::<M>();
+            let publish = self.client.publish(M::topic(), message, options, &codec);
+            instrument(publish, "publish", type_name::<M>(), self.source.as_deref()).await;
+        }
     }
 
-    /// Registers an Event listener
-    pub async fn listen<C, E>(&mut self, callback: C)
+    /// Validates an outgoing message if outgoing validation is enabled.
+    /// A rejected message is reported to the dead letter hook.
+    fn checked<M: Message>(&self, message: M) -> Option<M> {
+        if self.validation.outgoing() {
+            if let Err(letter) = self.validators.check(&message, &codec::<M>()) {
+                self.client.report_dead_letter(letter);
+                return None;
+            }
+        }
+        Some(message)
+    }
+
+    /// Registers an Event listener.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn listen<C, E>(&mut self, callback: C) -> T::Subscription
     where
         C: Fn(E) -> AsyncCallback<()> + Send + Sync + 'static,
         E: Event,
     {
-        self.client.register_callback(E::topic(), callback).await;
+        let qos = self.subscription_qos::<E>();
+        let decoder = self.message_codec::<E>();
+        let source = self.source.clone();
+        self.client
+            .subscribe(E::topic(), qos, decoder, move |_, event| {
+                handling::<E, _>(&source, callback(event))
+            })
+            .await
+    }
+
+    /// Registers a listener for two versions of an Event declared side by side, e.g. `TemperatureV2`
+    /// and the previous `Temperature` still published by some apps. The events of the previous version
+    /// are converted with `From` before reaching the listener.
+    /// The listener stays registered until both returned subscriptions are cancelled or dropped.
+    pub async fn listen_versions<P, E, C>(&mut self, callback: C) -> [T::Subscription; 2]
+    where
+        C: Fn(E) -> AsyncCallback<()> + Send + Sync + 'static,
+        P: Event,
+        E: Event + From<P>,
+    {
+        let callback = Arc::new(callback);
+        let previous = {
+            let callback = callback.clone();
+            self.listen(move |event: P| callback(E::from(event))).await
+        };
+        let current = self.listen(move |event: E| callback(event)).await;
+        [previous, current]
+    }
+
+    /// Registers an Event listener receiving the metadata of the events with them.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn listen_with_metadata<C, E>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(Received<E>) -> AsyncCallback<()> + Send + Sync + 'static,
+        E: Event,
+    {
+        let qos = self.subscription_qos::<E>();
+        let decoder = self.message_codec::<E>().received();
+        let source = self.source.clone();
+        self.client
+            .subscribe(E::topic(), qos, decoder, move |_, event| {
+                handling::<E, _>(&source, callback(event))
+            })
+            .await
     }
 
-    /// Register a Request handler
+    /// Registers a listener for the responses to a Request.
+    /// The listener stays registered until the returned subscription is cancelled or dropped.
+    pub async fn on_response<R, C>(&mut self, callback: C) -> T::Subscription
+    where
+        C: Fn(R::Response) -> AsyncCallback<()> + Send + Sync + 'static,
+        R: Request,
+    {
+        let qos = self.subscription_qos::<R::Response>();
+        let decoder = self.message_codec::<R::Response>();
+        let source = self.source.clone();
+        self.client
+            .subscribe(R::Response::topic(), qos, decoder, move |_, response| {
+                handling::<R::Response, _>(&source, callback(response))
+            })
+            .await
+    }
+
+    /// Register a Request handler.
+    /// The handler stays registered until the returned subscription is cancelled or dropped.
     /// The response type can be obtained using the following syntax:
     /// ```compile_fail
     /// type Response = <MyIncomingRequest as Request>::Response;
     /// ```
-    pub async fn respond<C, R>(&mut self, callback: C)
+    pub async fn respond<C, R>(&mut self, callback: C) -> T::Subscription
     where
-        C: Fn(R) -> AsyncCallback<R::Response> + Send + Sync + Clone + 'static,
+        C: Fn(R) -> AsyncCallback<R::Response> + Send + Sync + 'static,
         R: Request,
     {
+        let qos = self.subscription_qos::<R>();
+        let decoder = self.message_codec::<R>();
+        let reply = Reply {
+            topic: R::Response::topic().to_owned(),
+            options: self.publish_options::<R::Response>(),
+            codec: self.message_codec::<R::Response>(),
+        };
+        let responder = self.clone();
         self.client
-            .register_response::<_, _, R::Response>(R::topic(), R::Response::topic(), callback)
+            .respond(R::topic(), qos, decoder, reply, move |request: R| {
+                let (responder, response) = (responder.clone(), callback(request));
+                let response = handling::<R, _>(&responder.source, response);
+                Box::pin(async move { responder.checked(response.await) })
+            })
+            .await
+    }
+
+    /// The codec the payloads of a message are encoded and decoded with, in the envelope of the client,
+    /// validating them if incoming validation is enabled
+    fn message_codec<M: Message>(&self) -> MessageCodec<M> {
+        let validators = self.validation.incoming().then(|| self.validators.clone());
+        MessageCodec::new(codec::<M>(), validators)
+            .with_envelope(self.envelope, self.source.clone())
+    }
+
+    /// The options a message is published with, as declared in the manifest
+    /// or falling back to the client defaults.
+    fn publish_options<M: Message>(&self) -> PublishOptions {
+        PublishOptions {
+            qos: M::qos().map(to_qos).unwrap_or(self.client.default_qos()),
+            retain: M::retain(),
+        }
+    }
+
+    /// The QoS a message is subscribed with, as declared in the manifest
+    /// or falling back to the client defaults.
+    fn subscription_qos<M: Message>(&self) -> QoS {
+        M::qos()
+            .map(to_qos)
+            .unwrap_or(self.client.subscription_qos())
+    }
+
+    /// Waits for the running listeners and request handlers to complete, and disconnects from the transport.
+    pub async fn shutdown(&self) {
+        self.client.shutdown().await;
+    }
+}
+
+/// The codec of a message, as declared in the manifest.
+/// Publishing or listening to a message whose codec's cargo feature is disabled fails to compile.
+fn codec<M: Message>() -> PayloadCodec {
+    let () = EnabledCodec::<M>::ASSERT;
+    PayloadCodec::from_name(M::CODEC)
+        .unwrap_or_else(|| unreachable!("{} is not an enabled codec", M::CODEC))
+}
+
+/// Fails the build of the apps using a message whose codec isn't enabled
+struct EnabledCodec<M>(std::marker::PhantomData<M>);
+
+impl<M: Message> EnabledCodec<M> {
+    const ASSERT: () = assert!(
+        codec_enabled(M::CODEC),
+        "A message is encoded with a codec that is unknown or whose fdp-mqtt-client feature is disabled, \
+         enable the 'msgpack', 'cbor' or 'postcard' feature named by its `codec`"
+    );
+}
+
+/// Whether a codec is known and its cargo feature is enabled, evaluated at compile time
+const fn codec_enabled(name: &str) -> bool {
+    const fn eq(a: &str, b: &str) -> bool {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+    eq(name, "json")
+        || (cfg!(feature = "msgpack") && eq(name, "msgpack"))
+        || (cfg!(feature = "cbor") && eq(name, "cbor"))
+        || (cfg!(feature = "postcard") && eq(name, "postcard"))
+}
+
+/// Runs the handler of a message, within a span carrying the message type and the app with the `telemetry` feature
+fn handling<M: Message, R: 'static>(
+    source: &Option<Arc<str>>,
+    handler: AsyncCallback<R>,
+) -> AsyncCallback<R> {
+    Box::pin(instrument(
+        handler,
+        "handle",
+        type_name::<M>(),
+        source.as_deref(),
+    ))
+}
+
+/// Converts a QoS level declared with the fdp::topic macro
+fn to_qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mqtt_client::TestBroker;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Heartbeat {
+        uptime: u64,
+    }
+
+    impl Message for Heartbeat {
+        fn topic() -> &'static str {
+            "app_1/heartbeat"
+        }
+    }
+
+    impl Event for Heartbeat {}
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct HeartbeatV2 {
+        uptime: u64,
+        load: Option<f32>,
+    }
+
+    impl Message for HeartbeatV2 {
+        fn topic() -> &'static str {
+            "app_1/heartbeat/v2"
+        }
+
+        fn version() -> u32 {
+            2
+        }
+    }
+
+    impl Event for HeartbeatV2 {}
+
+    impl From<Heartbeat> for HeartbeatV2 {
+        fn from(heartbeat: Heartbeat) -> Self {
+            HeartbeatV2 {
+                uptime: heartbeat.uptime,
+                load: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct Square {
+        value: i32,
+    }
+
+    impl Message for Square {
+        fn topic() -> &'static str {
+            "app_1/square"
+        }
+    }
+
+    impl Request for Square {
+        type Response = Squared;
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Squared {
+        value: i32,
+    }
+
+    impl Message for Squared {
+        fn topic() -> &'static str {
+            "app_1/square/response"
+        }
+    }
+
+    impl Event for Squared {}
+
+    /// Exchanges an event and a request/response between two apps, whatever their transport
+    async fn exchange<T, W>(mut app_1: MqttClient<T>, mut app_2: MqttClient<T>, subscribed: W)
+    where
+        T: Transport,
+        W: std::future::Future<Output = ()>,
+    {
+        let (events, mut received_events) = mpsc::unbounded_channel();
+        let (responses, mut received_responses) = mpsc::unbounded_channel();
+
+        let _heartbeats = app_2
+            .listen(move |heartbeat: Heartbeat| {
+                let _ = events.send(heartbeat);
+                Box::pin(async {})
+            })
+            .await;
+        let _squares = app_1
+            .respond(|request: Square| {
+                Box::pin(async move {
+                    Squared {
+                        value: request.value * request.value,
+                    }
+                })
+            })
+            .await;
+        let _responses = app_2
+            .listen(move |response: Squared| {
+                let _ = responses.send(response);
+                Box::pin(async {})
+            })
             .await;
+        subscribed.await;
+
+        app_1.broadcast(Heartbeat { uptime: 42 }).await;
+        app_2.request(Square { value: 7 }).await;
+
+        let wait = Duration::from_secs(5);
+        let heartbeat = tokio::time::timeout(wait, received_events.recv()).await;
+        assert_eq!(heartbeat.unwrap(), Some(Heartbeat { uptime: 42 }));
+        let response = tokio::time::timeout(wait, received_responses.recv()).await;
+        assert_eq!(response.unwrap(), Some(Squared { value: 49 }));
     }
 
-    /// Starts the MQTT client and begins processing incoming messages.
-    pub async fn start(self, event_loop: EventLoop) {
-        self.client.start(event_loop).await;
+    #[tokio::test]
+    async fn exchanges_messages_over_mqtt() {
+        let broker = TestBroker::start().await;
+        let (raw_1, event_loop_1) = broker.client("app_1");
+        let (raw_2, event_loop_2) = broker.client("app_2");
+        let (app_1, app_2) = (
+            MqttClient::with_transport(raw_1),
+            MqttClient::with_transport(raw_2),
+        );
+        tokio::spawn(app_1.clone().start(event_loop_1));
+        tokio::spawn(app_2.clone().start(event_loop_2));
+
+        let subscribed = async {
+            broker.wait_for_subscription(Heartbeat::topic()).await;
+            broker.wait_for_subscription(Square::topic()).await;
+            broker.wait_for_subscription(Squared::topic()).await;
+        };
+        exchange(app_1, app_2, subscribed).await;
+    }
+
+    #[tokio::test]
+    async fn exchanges_messages_in_process() {
+        let bus = BroadcastTransport::new(16);
+        let app_1 = MqttClient::with_transport(bus.connect());
+        let app_2 = MqttClient::with_transport(bus.connect());
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        exchange(app_1, app_2, async {}).await;
+    }
+
+    #[tokio::test]
+    async fn reports_invalid_payloads_in_process() {
+        let bus = BroadcastTransport::new(16);
+        let (hook, mut dead_letters) = mqtt_client::dead_letter_channel(1);
+        let transport = bus.connect();
+        transport.on_dead_letter(hook);
+        let mut app =
+            MqttClient::with_transport(transport).with_validation(SchemaValidation::Incoming);
+        let _heartbeats = app
+            .listen(|_: Heartbeat| -> AsyncCallback<()> { panic!("The payload is invalid") })
+            .await;
+        tokio::spawn(app.clone().start());
+
+        let publisher = MqttClient::with_transport(bus.connect());
+        publisher
+            .client
+            .publish(
+                Heartbeat::topic(),
+                serde_json::json!({ "uptime": 1, "unknown": true }),
+                publisher.publish_options::<Heartbeat>(),
+                &PayloadCodec::Json,
+            )
+            .await;
+
+        let letter = tokio::time::timeout(Duration::from_secs(5), dead_letters.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(letter.error.is::<SchemaViolation>(), "{}", letter.error);
+        app.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn listens_to_two_versions_of_an_event() {
+        let bus = BroadcastTransport::new(16);
+        let app_1 = MqttClient::with_transport(bus.connect());
+        let mut app_2 = MqttClient::with_transport(bus.connect());
+        let (events, mut received_events) = mpsc::unbounded_channel();
+        let _heartbeats = app_2
+            .listen_versions::<Heartbeat, HeartbeatV2, _>(move |heartbeat| {
+                let _ = events.send(heartbeat);
+                Box::pin(async {})
+            })
+            .await;
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        app_1.broadcast(Heartbeat { uptime: 1 }).await;
+        let wait = Duration::from_secs(5);
+        let heartbeat = tokio::time::timeout(wait, received_events.recv()).await;
+        let expected = HeartbeatV2 {
+            uptime: 1,
+            load: None,
+        };
+        assert_eq!(heartbeat.unwrap(), Some(expected));
+
+        app_1
+            .broadcast(HeartbeatV2 {
+                uptime: 2,
+                load: Some(0.5),
+            })
+            .await;
+        let heartbeat = tokio::time::timeout(wait, received_events.recv()).await;
+        assert_eq!(heartbeat.unwrap().unwrap().load, Some(0.5));
+    }
+
+    /// Broadcasts an event in an envelope, and returns it as received with its metadata
+    async fn receive_with_metadata<T, W>(
+        publisher: MqttClient<T>,
+        mut listener: MqttClient<T>,
+        subscribed: W,
+    ) -> Received<Heartbeat>
+    where
+        T: Transport,
+        W: std::future::Future<Output = ()>,
+    {
+        let (events, mut received_events) = mpsc::unbounded_channel();
+        let _heartbeats = listener
+            .listen_with_metadata(move |heartbeat: Received<Heartbeat>| {
+                let _ = events.send(heartbeat);
+                Box::pin(async {})
+            })
+            .await;
+        subscribed.await;
+
+        publisher.broadcast(Heartbeat { uptime: 42 }).await;
+        let heartbeat = tokio::time::timeout(Duration::from_secs(5), received_events.recv()).await;
+        heartbeat.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn carries_metadata_in_user_properties_over_mqtt() {
+        let broker = TestBroker::start().await;
+        let (raw_1, event_loop_1) = broker.client("app_1");
+        let (raw_2, event_loop_2) = broker.client("app_2");
+        let app_1 = MqttClient::with_transport(raw_1)
+            .with_envelope(Envelope::UserProperties)
+            .with_source("app_1");
+        let app_2 = MqttClient::with_transport(raw_2).with_envelope(Envelope::UserProperties);
+        tokio::spawn(app_1.clone().start(event_loop_1));
+        tokio::spawn(app_2.clone().start(event_loop_2));
+
+        let subscribed = broker.wait_for_subscription(Heartbeat::topic());
+        let heartbeat = receive_with_metadata(app_1, app_2, subscribed).await;
+        assert_eq!(heartbeat.uptime, 42);
+        let metadata = heartbeat.metadata.expect("The heartbeat has no metadata");
+        assert_eq!(metadata.source.as_deref(), Some("app_1"));
+        assert_eq!(metadata.schema_version, 1);
+        // No publishing span is recorded here, so there is no trace context to carry
+        assert_eq!(metadata.traceparent, None);
+    }
+
+    #[tokio::test]
+    async fn carries_metadata_in_wrapped_payloads_in_process() {
+        let bus = BroadcastTransport::new(16);
+        let app_1 = MqttClient::with_transport(bus.connect())
+            .with_envelope(Envelope::Wrapped)
+            .with_source("app_1");
+        let app_2 = MqttClient::with_transport(bus.connect())
+            .with_envelope(Envelope::Wrapped)
+            .with_validation(SchemaValidation::Incoming);
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        let heartbeat = receive_with_metadata(app_1, app_2, async {}).await;
+        assert_eq!(heartbeat.message, Heartbeat { uptime: 42 });
+        let metadata = heartbeat.metadata.expect("The heartbeat has no metadata");
+        assert_eq!(metadata.source.as_deref(), Some("app_1"));
+    }
+
+    #[tokio::test]
+    async fn receives_events_without_metadata() {
+        let bus = BroadcastTransport::new(16);
+        let app_1 = MqttClient::with_transport(bus.connect());
+        let app_2 =
+            MqttClient::with_transport(bus.connect()).with_envelope(Envelope::UserProperties);
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        let heartbeat = receive_with_metadata(app_1, app_2, async {}).await;
+        assert_eq!(heartbeat.metadata, None);
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[tokio::test]
+    async fn shares_the_trace_of_a_request_with_its_response() {
+        use opentelemetry::trace::{TraceContextExt, TracerProvider};
+        use tracing::Instrument;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let trace_id = |span: &tracing::Span| span.context().span().span_context().trace_id();
+        let tracer = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .build()
+            .tracer("test");
+        // The runtime of the test runs every task on this thread
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer)),
+        );
+
+        let bus = BroadcastTransport::new(16);
+        let mut app_1 = MqttClient::with_transport(bus.connect());
+        let mut app_2 = MqttClient::with_transport(bus.connect());
+        let (traces, mut received_traces) = mpsc::unbounded_channel();
+        let _squares = app_1
+            .respond(|request: Square| {
+                Box::pin(async move {
+                    Squared {
+                        value: request.value * request.value,
+                    }
+                })
+            })
+            .await;
+        let _responses = app_2
+            .listen(move |_: Squared| {
+                let _ = traces.send(trace_id(&tracing::Span::current()));
+                Box::pin(async {})
+            })
+            .await;
+        tokio::spawn(app_1.clone().start());
+        tokio::spawn(app_2.clone().start());
+
+        let span = tracing::info_span!("square");
+        let trace = trace_id(&span);
+        app_2.request(Square { value: 7 }).instrument(span).await;
+        let response = tokio::time::timeout(Duration::from_secs(5), received_traces.recv()).await;
+        assert_eq!(response.unwrap(), Some(trace));
     }
 }
@@ -0,0 +1,232 @@
+//! The codec of the declared messages, applying their envelope and validating them
+
+use crate::envelope::{Envelope, Metadata, Wrapped};
+use crate::validation::Validators;
+use fdp_common::mqtt::Message;
+use mqtt_client::{Codec, CodecError, PayloadCodec, UserProperties};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The codec of a message, publishing its payloads in an envelope with their metadata, and
+/// validating the decoded payloads against the JSON schema of the message when incoming validation is enabled.
+/// Rejected payloads are reported as dead letters by the transport.
+pub(crate) struct MessageCodec<M> {
+    codec: PayloadCodec,
+    validators: Option<Arc<Validators>>,
+    envelope: Envelope,
+    source: Option<Arc<str>>,
+    /// Whether the payloads are decoded to a `Received` with their metadata
+    received: bool,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M: Message> MessageCodec<M> {
+    pub(crate) fn new(codec: PayloadCodec, validators: Option<Arc<Validators>>) -> Self {
+        MessageCodec {
+            codec,
+            validators,
+            envelope: Envelope::Disabled,
+            source: None,
+            received: false,
+            message: PhantomData,
+        }
+    }
+
+    /// Publishes the payloads in an envelope, on behalf of an app
+    pub(crate) fn with_envelope(mut self, envelope: Envelope, source: Option<Arc<str>>) -> Self {
+        self.envelope = envelope;
+        self.source = source;
+        self
+    }
+
+    /// Decodes the payloads to a `Received` message with their metadata
+    pub(crate) fn received(mut self) -> Self {
+        self.received = true;
+        self
+    }
+
+    /// Decodes a payload to the JSON value of the message
+    fn decode_value(&self, payload: &[u8]) -> Result<Value, CodecError> {
+        if self.codec.is_self_describing() {
+            self.codec.decode(payload)
+        } else {
+            // The payload can only be decoded to the message
+            let message: M = self.codec.decode(payload)?;
+            Ok(serde_json::to_value(message)?)
+        }
+    }
+
+    /// Decodes a payload wrapped with its metadata
+    fn decode_wrapped(&self, payload: &[u8]) -> Result<(Metadata, Value), CodecError> {
+        if self.codec.is_self_describing() {
+            let wrapped: Wrapped<Value> = self.codec.decode(payload)?;
+            Ok((wrapped.metadata, wrapped.payload))
+        } else {
+            let wrapped: Wrapped<M> = self.codec.decode(payload)?;
+            Ok((wrapped.metadata, serde_json::to_value(wrapped.payload)?))
+        }
+    }
+}
+
+impl<M> Clone for MessageCodec<M> {
+    fn clone(&self) -> Self {
+        MessageCodec {
+            codec: self.codec,
+            validators: self.validators.clone(),
+            envelope: self.envelope,
+            source: self.source.clone(),
+            received: self.received,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<M: Message> Codec for MessageCodec<M> {
+    fn content_type(&self) -> &'static str {
+        self.codec.content_type()
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(self.encode_with_properties(value)?.0)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        self.decode_with_properties(payload, &UserProperties::new())
+    }
+
+    fn encode_with_properties<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<(Vec<u8>, UserProperties), CodecError> {
+        let metadata = || Metadata::new(self.source.as_deref(), M::version());
+        match self.envelope {
+            Envelope::Disabled => Ok((self.codec.encode(value)?, UserProperties::new())),
+            Envelope::UserProperties => Ok((self.codec.encode(value)?, metadata().to_properties())),
+            Envelope::Wrapped => {
+                let wrapped = Wrapped {
+                    metadata: metadata(),
+                    payload: value,
+                };
+                Ok((self.codec.encode(&wrapped)?, UserProperties::new()))
+            }
+        }
+    }
+
+    fn decode_with_properties<T: DeserializeOwned>(
+        &self,
+        payload: &[u8],
+        properties: &UserProperties,
+    ) -> Result<T, CodecError> {
+        let wrapped = self.envelope == Envelope::Wrapped;
+        if !wrapped && !self.received && self.validators.is_none() {
+            return self.codec.decode(payload);
+        }
+        let (metadata, value) = if wrapped {
+            let (metadata, value) = self.decode_wrapped(payload)?;
+            (Some(metadata), value)
+        } else {
+            (
+                Metadata::from_properties(properties)?,
+                self.decode_value(payload)?,
+            )
+        };
+        if let Some(validators) = &self.validators {
+            validators.validate::<M>(&value)?;
+        }
+        if self.received {
+            Ok(T::deserialize(
+                json!({ "metadata": metadata, "message": value }),
+            )?)
+        } else {
+            Ok(T::deserialize(value)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Received;
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Temperature {
+        celsius: f32,
+    }
+
+    impl Message for Temperature {
+        fn topic() -> &'static str {
+            "sensors/temperature"
+        }
+
+        fn version() -> u32 {
+            3
+        }
+    }
+
+    fn codec(envelope: Envelope) -> MessageCodec<Temperature> {
+        MessageCodec::new(PayloadCodec::Json, None).with_envelope(envelope, Some("sensors".into()))
+    }
+
+    #[test]
+    fn carries_the_metadata_in_user_properties() {
+        let codec = codec(Envelope::UserProperties);
+        let (payload, properties) = codec
+            .encode_with_properties(&Temperature { celsius: 21.5 })
+            .unwrap();
+        assert_eq!(payload, br#"{"celsius":21.5}"#);
+
+        let received: Received<Temperature> = codec
+            .clone()
+            .received()
+            .decode_with_properties(&payload, &properties)
+            .unwrap();
+        let metadata = received.metadata.unwrap();
+        assert_eq!(metadata.source.as_deref(), Some("sensors"));
+        assert_eq!(metadata.schema_version, 3);
+        assert_eq!(received.message, Temperature { celsius: 21.5 });
+
+        // Listeners without metadata get the bare message
+        let temperature: Temperature = codec.decode_with_properties(&payload, &properties).unwrap();
+        assert_eq!(temperature.celsius, 21.5);
+    }
+
+    #[test]
+    fn wraps_the_payloads() {
+        let codec = codec(Envelope::Wrapped);
+        let (payload, properties) = codec
+            .encode_with_properties(&Temperature { celsius: 21.5 })
+            .unwrap();
+        assert!(properties.is_empty());
+        let wrapped: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(wrapped["payload"], json!({ "celsius": 21.5 }));
+        assert_eq!(wrapped["metadata"]["source"], "sensors");
+
+        let received: Received<Temperature> = codec.clone().received().decode(&payload).unwrap();
+        assert!(received.metadata.is_some());
+        let temperature: Temperature = codec.decode(&payload).unwrap();
+        assert_eq!(temperature, *received);
+    }
+
+    #[test]
+    fn receives_messages_without_metadata() {
+        let codec = codec(Envelope::UserProperties).received();
+        let received: Received<Temperature> = codec.decode(br#"{"celsius":21.5}"#).unwrap();
+        assert_eq!(received.metadata, None);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn wraps_payloads_of_codecs_that_are_not_self_describing() {
+        let codec = MessageCodec::<Temperature>::new(PayloadCodec::Postcard, Some(Arc::default()))
+            .with_envelope(Envelope::Wrapped, None)
+            .received();
+        let payload = codec.encode(&Temperature { celsius: 21.5 }).unwrap();
+        let received: Received<Temperature> = codec.decode(&payload).unwrap();
+        assert_eq!(received.metadata.unwrap().schema_version, 3);
+        assert_eq!(received.message, Temperature { celsius: 21.5 });
+    }
+}
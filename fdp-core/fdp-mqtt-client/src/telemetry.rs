@@ -0,0 +1,28 @@
+//! The tracing spans of the typed client, enabled by the `telemetry` feature
+
+use std::future::Future;
+
+/// Runs an operation on a message within a span carrying the message type and the app
+#[cfg(feature = "telemetry")]
+pub(crate) fn instrument<F: Future>(
+    future: F,
+    operation: &'static str,
+    message: &'static str,
+    app: Option<&str>,
+) -> impl Future<Output = F::Output> {
+    use tracing::Instrument;
+
+    let span = tracing::info_span!("fdp", operation, message, app);
+    future.instrument(span)
+}
+
+/// Runs an operation on a message, without telemetry
+#[cfg(not(feature = "telemetry"))]
+pub(crate) fn instrument<F: Future>(
+    future: F,
+    _operation: &'static str,
+    _message: &'static str,
+    _app: Option<&str>,
+) -> impl Future<Output = F::Output> {
+    future
+}
@@ -0,0 +1,139 @@
+//! The message buses the typed client publishes and subscribes through
+
+use mqtt_client::{
+    AsyncCallback, Codec, DeadLetter, MqttClient as RawMqttClient, PublishOptions, QoS,
+    SubscriptionGuard,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+
+/// Where and how the responses of a request handler are published
+#[derive(Debug, Clone)]
+pub struct Reply<C> {
+    pub topic: String,
+    pub options: PublishOptions,
+    pub codec: C,
+}
+
+/// A message bus the typed client publishes and subscribes through.
+/// The MQTT client is the default transport, and `BroadcastTransport` connects the apps of a single process.
+pub trait Transport: Clone + Send + Sync + 'static {
+    /// Keeps a handler subscribed until it is cancelled or dropped
+    type Subscription: Send + 'static;
+
+    /// The QoS messages are published with when their declaration doesn't set one
+    fn default_qos(&self) -> QoS;
+
+    /// The QoS topics are subscribed with when their declaration doesn't set one
+    fn subscription_qos(&self) -> QoS;
+
+    /// Publishes a payload on a topic, encoded with a codec
+    fn publish<P, C>(
+        &self,
+        topic: &str,
+        payload: P,
+        options: PublishOptions,
+        codec: &C,
+    ) -> impl Future<Output = ()> + Send
+    where
+        P: Serialize + Send,
+        C: Codec;
+
+    /// Subscribes a handler to a topic filter, decoding the payloads with a codec.
+    /// The payloads that fail to decode are reported as dead letters.
+    fn subscribe<P, C, F>(
+        &mut self,
+        filter: &str,
+        qos: QoS,
+        codec: C,
+        handler: F,
+    ) -> impl Future<Output = Self::Subscription> + Send
+    where
+        P: DeserializeOwned + Send + 'static,
+        C: Codec,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static;
+
+    /// Subscribes a handler to the requests received on a topic filter, and publishes its responses.
+    /// Nothing is published when the handler returns `None`.
+    fn respond<P, R, C, E, F>(
+        &mut self,
+        filter: &str,
+        qos: QoS,
+        codec: C,
+        reply: Reply<E>,
+        handler: F,
+    ) -> impl Future<Output = Self::Subscription> + Send
+    where
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+        C: Codec,
+        E: Codec,
+        F: Fn(P) -> AsyncCallback<Option<R>> + Send + Sync + 'static,
+    {
+        let transport = self.clone();
+        self.subscribe(filter, qos, codec, move |_, request: P| {
+            let response = handler(request);
+            let (transport, reply) = (transport.clone(), reply.clone());
+            Box::pin(async move {
+                if let Some(response) = response.await {
+                    transport
+                        .publish(&reply.topic, response, reply.options, &reply.codec)
+                        .await;
+                }
+            })
+        })
+    }
+
+    /// Reports a payload rejected before being published or handled
+    fn report_dead_letter(&self, letter: DeadLetter);
+
+    /// Waits for the running handlers to complete, and disconnects from the bus
+    fn shutdown(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl Transport for RawMqttClient {
+    type Subscription = SubscriptionGuard;
+
+    fn default_qos(&self) -> QoS {
+        RawMqttClient::default_qos(self)
+    }
+
+    fn subscription_qos(&self) -> QoS {
+        RawMqttClient::subscription_qos(self)
+    }
+
+    async fn publish<P, C>(&self, topic: &str, payload: P, options: PublishOptions, codec: &C)
+    where
+        P: Serialize + Send,
+        C: Codec,
+    {
+        self.publish_with_codec(topic, payload, options, codec)
+            .await;
+    }
+
+    async fn subscribe<P, C, F>(
+        &mut self,
+        filter: &str,
+        qos: QoS,
+        codec: C,
+        handler: F,
+    ) -> SubscriptionGuard
+    where
+        P: DeserializeOwned + Send + 'static,
+        C: Codec,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+    {
+        let subscription = self
+            .register_topic_callback_with_codec(filter, qos, codec, handler)
+            .await;
+        self.guard(subscription)
+    }
+
+    fn report_dead_letter(&self, letter: DeadLetter) {
+        RawMqttClient::report_dead_letter(self, letter);
+    }
+
+    async fn shutdown(&self) {
+        RawMqttClient::shutdown(self).await;
+    }
+}
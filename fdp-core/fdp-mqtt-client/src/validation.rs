@@ -0,0 +1,331 @@
+//! Runtime validation of the payloads against the JSON schemas of the messages
+
+use fdp_common::mqtt::Message;
+use jsonschema::Validator;
+use mqtt_client::{Codec, DeadLetter};
+use schemars::schema::RootSchema;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Which payloads are validated against the JSON schema of their message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaValidation {
+    /// Payloads are only deserialized
+    #[default]
+    Disabled,
+    /// Incoming payloads are validated before reaching their listener or request handler
+    Incoming,
+    /// Incoming payloads are validated, and outgoing messages are validated before being published
+    IncomingAndOutgoing,
+}
+
+impl SchemaValidation {
+    /// Whether incoming payloads are validated
+    pub fn incoming(self) -> bool {
+        self != SchemaValidation::Disabled
+    }
+
+    /// Whether outgoing messages are validated
+    pub fn outgoing(self) -> bool {
+        self == SchemaValidation::IncomingAndOutgoing
+    }
+}
+
+/// A payload that doesn't match the JSON schema of its message
+#[derive(Debug)]
+pub struct SchemaViolation {
+    /// The message identifier
+    pub message: &'static str,
+    /// The violated constraints, with the path of the invalid values
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The payload doesn't match the schema of {}: {}",
+            self.message,
+            self.errors.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for SchemaViolation {}
+
+/// The compiled validators of the messages, cached by topic
+#[derive(Default)]
+pub(crate) struct Validators {
+    cache: RwLock<HashMap<&'static str, Arc<Validator>>>,
+}
+
+impl Validators {
+    /// Returns the validator of a message, compiling it on first use from `Message::schema()`,
+    /// next to the definitions of the types it references.
+    /// Objects reject the properties that are not declared in their schema, unlike serde which ignores them.
+    fn get<M: Message>(&self) -> Arc<Validator> {
+        if let Some(validator) = self.cache.read().unwrap().get(M::topic()) {
+            return Arc::clone(validator);
+        }
+        let schema = RootSchema {
+            schema: M::schema(),
+            ..schemars::schema_for!(M)
+        };
+        let mut schema = serde_json::to_value(schema).unwrap();
+        deny_additional_properties(&mut schema);
+        let validator = Arc::new(jsonschema::validator_for(&schema).unwrap_or_else(|e| {
+            panic!(
+                "The schema of {} is invalid: {}",
+                std::any::type_name::<M>(),
+                e
+            )
+        }));
+        self.cache
+            .write()
+            .unwrap()
+            .entry(M::topic())
+            .or_insert(validator)
+            .clone()
+    }
+
+    /// Validates a JSON value against the schema of a message
+    pub(crate) fn validate<M: Message>(&self, value: &Value) -> Result<(), SchemaViolation> {
+        let validator = self.get::<M>();
+        let errors: Vec<String> = validator
+            .iter_errors(value)
+            .map(|error| format!("{} at '{}'", error, error.instance_path))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SchemaViolation {
+                message: type_name::<M>(),
+                errors,
+            })
+        }
+    }
+
+    /// Validates a message before it is published, or after it was decoded by a codec that isn't self-describing.
+    /// It returns the rejected message, encoded with the codec, as a dead letter on failure.
+    pub(crate) fn check<M: Message, C: Codec>(
+        &self,
+        message: &M,
+        codec: &C,
+    ) -> Result<(), DeadLetter> {
+        let value = serde_json::to_value(message).unwrap();
+        self.validate::<M>(&value).map_err(|violation| DeadLetter {
+            topic: M::topic().to_owned(),
+            payload: codec.encode(message).unwrap_or_default(),
+            error: Box::new(violation),
+        })
+    }
+}
+
+/// The short name of a message type
+pub(crate) fn type_name<M>() -> &'static str {
+    let name = std::any::type_name::<M>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// The keywords combining schemas that each only declare part of the properties, e.g. for `#[serde(flatten)]`
+const COMBINATORS: [&str; 3] = ["allOf", "oneOf", "anyOf"];
+
+/// Forbids undeclared properties in every object schema that doesn't say otherwise.
+/// Schemas combined through `allOf`, `oneOf` or `anyOf` are left open: draft 7 has no `unevaluatedProperties`,
+/// and `additionalProperties` would reject the properties declared by the other side of the combination.
+fn deny_additional_properties(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            let combined = COMBINATORS.iter().any(|key| object.contains_key(*key));
+            if !combined
+                && object.contains_key("properties")
+                && !object.contains_key("additionalProperties")
+            {
+                object.insert("additionalProperties".to_string(), Value::Bool(false));
+            }
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::Array(members) if COMBINATORS.contains(&key.as_str()) => members
+                        .iter_mut()
+                        .for_each(deny_nested_additional_properties),
+                    _ => deny_additional_properties(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(deny_additional_properties),
+        _ => {}
+    }
+}
+
+/// Forbids undeclared properties below a combined schema, leaving the combined schema itself open
+fn deny_nested_additional_properties(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => object.values_mut().for_each(deny_additional_properties),
+        _ => deny_additional_properties(schema),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_codec::MessageCodec;
+    use mqtt_client::{CodecError, Json, PayloadCodec};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Temperature {
+        celsius: f32,
+        sensor: Sensor,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Sensor {
+        id: u32,
+    }
+
+    impl Message for Temperature {
+        fn topic() -> &'static str {
+            "sensors/temperature"
+        }
+    }
+
+    fn codec() -> MessageCodec<Temperature> {
+        MessageCodec::new(PayloadCodec::Json, Some(Arc::default()))
+    }
+
+    fn decode(value: Value) -> Result<Temperature, CodecError> {
+        codec().decode(&serde_json::to_vec(&value).unwrap())
+    }
+
+    #[test]
+    fn accepts_valid_payloads() {
+        let temperature = decode(json!({ "celsius": 21.5, "sensor": { "id": 3 } })).unwrap();
+        assert_eq!(temperature.sensor, Sensor { id: 3 });
+    }
+
+    #[test]
+    fn rejects_payloads_serde_would_accept() {
+        // serde ignores unknown fields, including in nested messages
+        for value in [
+            json!({ "celsius": 21.5, "sensor": { "id": 3 }, "unit": "C" }),
+            json!({ "celsius": 21.5, "sensor": { "id": 3, "name": "kitchen" } }),
+        ] {
+            let error = decode(value).unwrap_err();
+            assert!(error.is::<SchemaViolation>(), "{}", error);
+        }
+
+        let error = decode(json!({ "celsius": "hot", "sensor": { "id": -1 } })).unwrap_err();
+        let violation = error.downcast_ref::<SchemaViolation>().unwrap();
+        assert_eq!(violation.message, "Temperature");
+        assert_eq!(violation.errors.len(), 2, "{:?}", violation.errors);
+
+        let unvalidated = MessageCodec::<Temperature>::new(PayloadCodec::Json, None);
+        let payload = br#"{ "celsius": 21.5, "sensor": { "id": 3 }, "unit": "C" }"#;
+        assert!(unvalidated.decode::<Temperature>(payload).is_ok());
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Reading {
+        celsius: f32,
+        #[serde(flatten)]
+        place: Place,
+        #[serde(flatten)]
+        source: Source,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Place {
+        room: String,
+        sensor: Sensor,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    #[serde(tag = "kind")]
+    enum Source {
+        Sensor { id: u32 },
+        Manual { by: String },
+    }
+
+    impl Message for Reading {
+        fn topic() -> &'static str {
+            "sensors/reading"
+        }
+    }
+
+    #[test]
+    fn accepts_flattened_structs() {
+        let validators = Validators::default();
+        let reading = Reading {
+            celsius: 21.5,
+            place: Place {
+                room: "kitchen".to_owned(),
+                sensor: Sensor { id: 3 },
+            },
+            source: Source::Manual {
+                by: "alice".to_owned(),
+            },
+        };
+        assert!(validators.check(&reading, &Json).is_ok());
+
+        let value = serde_json::to_value(&reading).unwrap();
+        assert_eq!(value["kind"], "Manual");
+        assert!(validators.validate::<Reading>(&value).is_ok());
+
+        // The messages referenced by a combined schema still reject unknown fields
+        let mut nested = value.clone();
+        nested["sensor"]["name"] = json!("kitchen");
+        assert!(validators.validate::<Reading>(&nested).is_err());
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct Humidity {
+        percent: f32,
+        sensor: Sensor,
+    }
+
+    impl Message for Humidity {
+        fn topic() -> &'static str {
+            "sensors/humidity"
+        }
+
+        fn schema() -> schemars::schema::SchemaObject {
+            let mut schema = schemars::schema_for!(Humidity).schema;
+            let percent = schema.object().properties.get_mut("percent");
+            if let Some(schemars::schema::Schema::Object(percent)) = percent {
+                percent.number().maximum = Some(100.0);
+            }
+            schema
+        }
+    }
+
+    #[test]
+    fn validates_against_the_schema_of_the_message() {
+        let validators = Validators::default();
+        let humidity = json!({ "percent": 40.0, "sensor": { "id": 3 } });
+        assert!(validators.validate::<Humidity>(&humidity).is_ok());
+
+        // The overridden schema restricts the values, and still references the definitions of the other types
+        let humidity = json!({ "percent": 140.0, "sensor": { "id": 3 } });
+        assert!(validators.validate::<Humidity>(&humidity).is_err());
+        let humidity = json!({ "percent": 40.0, "sensor": { "id": "kitchen" } });
+        assert!(validators.validate::<Humidity>(&humidity).is_err());
+    }
+
+    #[test]
+    fn caches_validators_per_topic() {
+        let validators = Validators::default();
+        let first = validators.get::<Temperature>();
+        let second = validators.get::<Temperature>();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let outgoing = Temperature {
+            celsius: 18.0,
+            sensor: Sensor { id: 1 },
+        };
+        assert!(validators.check(&outgoing, &Json).is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+//! Measures the number of messages dispatched per second.
+//! `locked` dispatches through a mutex around the dispatcher, as the client did before the dispatcher
+//! used snapshots, and `snapshot` dispatches directly. The `with_registrations` variants register and
+//! unregister handlers in a background task while dispatching.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use mqtt_client::EventDispatcher;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const MESSAGES: u64 = 1_000;
+const DEVICES: u64 = 1_000;
+
+/// A dispatcher with a handler per device topic, and a wildcard handler
+fn dispatcher() -> EventDispatcher {
+    let dispatcher = EventDispatcher::new();
+    for device in 0..DEVICES {
+        let topic = format!("devices/{}/telemetry", device);
+        dispatcher.add_handler(&topic, |_: f64| Box::pin(async {}));
+    }
+    dispatcher.add_handler("devices/+/telemetry", |_: f64| Box::pin(async {}));
+    dispatcher
+}
+
+fn topic(message: u64) -> String {
+    format!("devices/{}/telemetry", message % DEVICES)
+}
+
+/// Registers and unregisters a handler in a loop
+fn register_and_unregister(dispatcher: &EventDispatcher) {
+    let id = dispatcher.add_handler("devices/new/telemetry", |_: f64| Box::pin(async {}));
+    dispatcher.remove_handler("devices/new/telemetry", id);
+}
+
+/// Registers handlers in the background, holding the lock while doing so
+fn spawn_locked_registrations(locked: Arc<Mutex<EventDispatcher>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            register_and_unregister(&*locked.lock().await);
+            tokio::time::sleep(Duration::from_micros(100)).await;
+        }
+    })
+}
+
+/// Registers handlers in the background, without locking
+fn spawn_registrations(dispatcher: Arc<EventDispatcher>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            register_and_unregister(&dispatcher);
+            tokio::time::sleep(Duration::from_micros(100)).await;
+        }
+    })
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let _runtime = runtime.enter();
+    let payload = serde_json::to_vec(&21.5).unwrap();
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(MESSAGES));
+
+    for registrations in [false, true] {
+        let name = if registrations {
+            "with_registrations"
+        } else {
+            "idle"
+        };
+
+        let locked = Arc::new(Mutex::new(dispatcher()));
+        let background = registrations.then(|| spawn_locked_registrations(locked.clone()));
+        group.bench_with_input(BenchmarkId::new("locked", name), &payload, |b, payload| {
+            b.to_async(&runtime).iter(|| async {
+                let mut handles = Vec::new();
+                for message in 0..MESSAGES {
+                    let dispatch = locked
+                        .lock()
+                        .await
+                        .dispatch(&topic(message), payload.clone());
+                    handles.extend(dispatch.await);
+                }
+                join_all(handles).await;
+            })
+        });
+        if let Some(background) = background {
+            background.abort();
+        }
+
+        let snapshot = Arc::new(dispatcher());
+        let background = registrations.then(|| spawn_registrations(snapshot.clone()));
+        group.bench_with_input(
+            BenchmarkId::new("snapshot", name),
+            &payload,
+            |b, payload| {
+                b.to_async(&runtime).iter(|| async {
+                    let mut handles = Vec::new();
+                    for message in 0..MESSAGES {
+                        handles.extend(snapshot.dispatch(&topic(message), payload.clone()).await);
+                    }
+                    join_all(handles).await;
+                })
+            },
+        );
+        if let Some(background) = background {
+            background.abort();
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
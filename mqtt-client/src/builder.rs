@@ -0,0 +1,412 @@
+use crate::config::{parse_qos, read_file, ConfigError, MqttClientConfig};
+use crate::mqtt_client::MqttClient;
+use crate::scheduler::DispatchLimits;
+use rumqttc::v5::{
+    mqttbytes::{v5::LastWill, QoS},
+    AsyncClient, EventLoop, MqttOptions,
+};
+use rumqttc::{TlsConfiguration, Transport};
+use std::time::Duration;
+
+/// Builds an MqttClient with custom connection options.
+/// The defaults match `MqttClient::new`.
+pub struct MqttClientBuilder {
+    client_id: String,
+    host: String,
+    port: u16,
+    keep_alive: Duration,
+    capacity: usize,
+    credentials: Option<(String, String)>,
+    /// The certificate authority verifying the broker, set with `tls`
+    ca: Option<Vec<u8>>,
+    client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    alpn: Option<Vec<Vec<u8>>>,
+    /// A custom TLS configuration, set with `tls_config`
+    tls_config: Option<TlsConfiguration>,
+    last_will: Option<LastWill>,
+    clean_start: bool,
+    session_expiry: Option<Duration>,
+    inflight: Option<u16>,
+    receive_maximum: Option<u16>,
+    default_qos: QoS,
+    subscription_qos: QoS,
+    dispatch_limits: DispatchLimits,
+    republish_dead_letters: bool,
+}
+
+impl MqttClientBuilder {
+    /// Creates a new builder for a client connecting to the given broker
+    pub fn new(client_id: &str, host: &str, port: u16) -> Self {
+        MqttClientBuilder {
+            client_id: client_id.to_owned(),
+            host: host.to_owned(),
+            port,
+            keep_alive: Duration::from_secs(10),
+            capacity: 10,
+            credentials: None,
+            ca: None,
+            client_auth: None,
+            alpn: None,
+            tls_config: None,
+            last_will: None,
+            clean_start: true,
+            session_expiry: None,
+            inflight: None,
+            receive_maximum: None,
+            default_qos: QoS::AtMostOnce,
+            subscription_qos: QoS::AtLeastOnce,
+            dispatch_limits: DispatchLimits::default(),
+            republish_dead_letters: false,
+        }
+    }
+
+    /// Creates a builder from a loaded configuration, reading the referenced TLS files
+    pub fn from_config(config: MqttClientConfig) -> Result<Self, ConfigError> {
+        let mut builder = Self::new(&config.client_id, &config.host, config.port);
+
+        if let Some(secs) = config.keep_alive_secs {
+            builder = builder.keep_alive(Duration::from_secs(secs));
+        }
+        if let Some(capacity) = config.capacity {
+            builder = builder.capacity(capacity);
+        }
+        match (config.username, config.password) {
+            (Some(username), password) => {
+                builder = builder.credentials(username, password.unwrap_or_default())
+            }
+            (None, Some(_)) => return Err(ConfigError::Missing("username")),
+            (None, None) => {}
+        }
+        if let Some(clean_start) = config.clean_start {
+            builder = builder.clean_start(clean_start);
+        }
+        if let Some(secs) = config.session_expiry_secs {
+            builder = builder.session_expiry(Duration::from_secs(secs.into()));
+        }
+        if let Some(inflight) = config.inflight {
+            builder = builder.inflight(inflight);
+        }
+        if let Some(receive_maximum) = config.receive_maximum {
+            builder = builder.receive_maximum(receive_maximum);
+        }
+        if let Some(level) = config.default_qos {
+            builder = builder.default_qos(parse_qos("default_qos", level)?);
+        }
+        if let Some(level) = config.subscription_qos {
+            builder = builder.subscription_qos(parse_qos("subscription_qos", level)?);
+        }
+        if let Some(limits) = config.dispatch {
+            builder = builder.dispatch_limits(limits);
+        }
+        if let Some(republish) = config.republish_dead_letters {
+            builder = builder.republish_dead_letters(republish);
+        }
+        if let Some(tls) = config.tls {
+            builder = builder.tls(read_file(&tls.ca_cert)?);
+            match (tls.client_cert, tls.client_key) {
+                (Some(cert), Some(key)) => {
+                    builder = builder.client_auth(read_file(&cert)?, read_file(&key)?)
+                }
+                (Some(_), None) => return Err(ConfigError::Missing("tls.client_key")),
+                (None, Some(_)) => return Err(ConfigError::Missing("tls.client_cert")),
+                (None, None) => {}
+            }
+            if let Some(alpn) = tls.alpn {
+                builder = builder.alpn(alpn);
+            }
+        }
+        if let Some(will) = config.last_will {
+            let qos = parse_qos("last_will.qos", will.qos.unwrap_or(0))?;
+            builder =
+                builder.last_will(will.topic, will.payload, qos, will.retain.unwrap_or(false));
+        }
+
+        Ok(builder)
+    }
+
+    /// Creates a builder from the `MQTT_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_config(MqttClientConfig::from_env()?)
+    }
+
+    /// Creates a builder from a TOML configuration file
+    pub fn from_toml_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_config(MqttClientConfig::from_toml_file(path)?)
+    }
+
+    /// Sets the keep-alive interval
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets the capacity of the request channel between the client and its event loop
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Authenticates with a username and password
+    pub fn credentials<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: P,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Connects over TLS (rustls), verifying the broker with a PEM encoded certificate authority.
+    /// It replaces a configuration set with `tls_config`.
+    pub fn tls(mut self, ca: Vec<u8>) -> Self {
+        self.ca = Some(ca);
+        self.tls_config = None;
+        self
+    }
+
+    /// Authenticates with PEM encoded client certificate and key (mutual TLS).
+    /// It applies to the TLS connection enabled with `tls`, whether it is called before or after.
+    pub fn client_auth(mut self, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.client_auth = Some((cert, key));
+        self
+    }
+
+    /// Sets the ALPN protocols to negotiate.
+    /// It applies to the TLS connection enabled with `tls`, whether it is called before or after.
+    pub fn alpn<S: Into<String>>(mut self, protocols: Vec<S>) -> Self {
+        self.alpn = Some(
+            protocols
+                .into_iter()
+                .map(|p| p.into().into_bytes())
+                .collect(),
+        );
+        self
+    }
+
+    /// Connects over TLS with a custom rustls client configuration, which includes its own
+    /// client authentication and ALPN protocols. It replaces a certificate authority set with `tls`.
+    pub fn tls_config(mut self, config: TlsConfiguration) -> Self {
+        self.tls_config = Some(config);
+        self.ca = None;
+        self
+    }
+
+    /// Assembles the TLS configuration from the certificate authority, client authentication and ALPN protocols
+    fn tls_configuration(&self) -> Option<TlsConfiguration> {
+        let options_set = self.client_auth.is_some() || self.alpn.is_some();
+        match (&self.tls_config, &self.ca) {
+            (Some(config), _) => {
+                if options_set {
+                    log::warn!("The client authentication and ALPN protocols are ignored with a custom TLS configuration");
+                }
+                Some(config.clone())
+            }
+            (None, Some(ca)) => Some(TlsConfiguration::Simple {
+                ca: ca.clone(),
+                alpn: self.alpn.clone(),
+                client_auth: self.client_auth.clone(),
+            }),
+            (None, None) => {
+                if options_set {
+                    log::warn!("The client authentication and ALPN protocols are ignored without TLS, enabled with `tls`");
+                }
+                None
+            }
+        }
+    }
+
+    /// Sets the message published by the broker if the client disconnects ungracefully
+    pub fn last_will<T, P>(mut self, topic: T, payload: P, qos: QoS, retain: bool) -> Self
+    where
+        T: Into<String>,
+        P: Into<Vec<u8>>,
+    {
+        self.last_will = Some(LastWill::new(topic, payload, qos, retain, None));
+        self
+    }
+
+    /// Sets whether the broker should discard any previous session on connection
+    pub fn clean_start(mut self, clean_start: bool) -> Self {
+        self.clean_start = clean_start;
+        self
+    }
+
+    /// Sets how long the broker keeps the session after the client disconnects
+    pub fn session_expiry(mut self, expiry: Duration) -> Self {
+        self.session_expiry = Some(expiry);
+        self
+    }
+
+    /// Limits the number of outgoing QoS 1 and 2 messages awaiting acknowledgement
+    pub fn inflight(mut self, inflight: u16) -> Self {
+        self.inflight = Some(inflight);
+        self
+    }
+
+    /// Limits the number of incoming QoS 1 and 2 messages the broker sends before acknowledgement
+    pub fn receive_maximum(mut self, receive_maximum: u16) -> Self {
+        self.receive_maximum = Some(receive_maximum);
+        self
+    }
+
+    /// Sets the QoS used when publishing
+    pub fn default_qos(mut self, qos: QoS) -> Self {
+        self.default_qos = qos;
+        self
+    }
+
+    /// Sets the QoS used when subscribing
+    pub fn subscription_qos(mut self, qos: QoS) -> Self {
+        self.subscription_qos = qos;
+        self
+    }
+
+    /// Limits the concurrency of the callbacks handling incoming messages
+    pub fn dispatch_limits(mut self, limits: DispatchLimits) -> Self {
+        self.dispatch_limits = limits;
+        self
+    }
+
+    /// Republishes the payloads that callbacks failed to deserialize on `<topic>/$dlq`
+    pub fn republish_dead_letters(mut self, republish: bool) -> Self {
+        self.republish_dead_letters = republish;
+        self
+    }
+
+    /// Returns the MQTT options that the client will connect with
+    pub fn mqtt_options(&self) -> MqttOptions {
+        let mut mqttoptions = MqttOptions::new(&self.client_id, &self.host, self.port);
+        mqttoptions.set_keep_alive(self.keep_alive);
+        mqttoptions.set_clean_start(self.clean_start);
+        if let Some((username, password)) = &self.credentials {
+            mqttoptions.set_credentials(username, password);
+        }
+        if let Some(tls) = self.tls_configuration() {
+            mqttoptions.set_transport(Transport::tls_with_config(tls));
+        }
+        if let Some(will) = &self.last_will {
+            mqttoptions.set_last_will(will.clone());
+        }
+        if let Some(expiry) = self.session_expiry {
+            let secs = u32::try_from(expiry.as_secs()).unwrap_or(u32::MAX);
+            let mut properties = mqttoptions.connect_properties().unwrap_or_default();
+            properties.session_expiry_interval = Some(secs);
+            mqttoptions.set_connect_properties(properties);
+        }
+        if let Some(inflight) = self.inflight {
+            mqttoptions.set_outgoing_inflight_upper_limit(inflight);
+        }
+        if let Some(receive_maximum) = self.receive_maximum {
+            mqttoptions.set_receive_maximum(Some(receive_maximum));
+        }
+        mqttoptions
+    }
+
+    /// Builds the client and the event loop that drives it
+    pub fn build(self) -> (MqttClient, EventLoop) {
+        let (client, event_loop) = AsyncClient::new(self.mqtt_options(), self.capacity);
+        (
+            MqttClient::from_parts(
+                client,
+                self.default_qos,
+                self.subscription_qos,
+                self.dispatch_limits,
+                self.republish_dead_letters,
+            ),
+            event_loop,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_new() {
+        let options = MqttClientBuilder::new("client", "localhost", 1883).mqtt_options();
+        assert_eq!(options.keep_alive(), Duration::from_secs(10));
+        assert!(options.clean_start());
+        assert_eq!(options.credentials(), None);
+        assert_eq!(options.last_will(), None);
+    }
+
+    #[test]
+    fn applies_options() {
+        let options = MqttClientBuilder::new("client", "localhost", 8883)
+            .keep_alive(Duration::from_secs(30))
+            .credentials("user", "secret")
+            .clean_start(false)
+            .session_expiry(Duration::from_secs(3600))
+            .inflight(5)
+            .receive_maximum(50)
+            .last_will("client/status", "offline", QoS::AtLeastOnce, true)
+            .mqtt_options();
+
+        assert_eq!(options.keep_alive(), Duration::from_secs(30));
+        assert_eq!(
+            options.credentials(),
+            Some(("user".to_string(), "secret".to_string()))
+        );
+        assert!(!options.clean_start());
+        assert_eq!(
+            options
+                .connect_properties()
+                .unwrap()
+                .session_expiry_interval,
+            Some(3600)
+        );
+        assert_eq!(options.get_outgoing_inflight_upper_limit(), Some(5));
+        assert_eq!(options.receive_maximum(), Some(50));
+        assert!(options.last_will().unwrap().retain);
+    }
+
+    #[test]
+    fn assembles_tls_in_any_order() {
+        let configurations = [
+            MqttClientBuilder::new("client", "localhost", 8883)
+                .client_auth(b"cert".to_vec(), b"key".to_vec())
+                .alpn(vec!["mqtt"])
+                .tls(b"ca".to_vec()),
+            MqttClientBuilder::new("client", "localhost", 8883)
+                .tls(b"old ca".to_vec())
+                .alpn(vec!["mqtt"])
+                .client_auth(b"cert".to_vec(), b"key".to_vec())
+                .tls(b"ca".to_vec()),
+        ];
+        for builder in configurations {
+            match builder.mqtt_options().transport() {
+                Transport::Tls(TlsConfiguration::Simple {
+                    ca,
+                    alpn,
+                    client_auth,
+                }) => {
+                    assert_eq!(ca, b"ca".to_vec());
+                    assert_eq!(alpn, Some(vec![b"mqtt".to_vec()]));
+                    assert_eq!(client_auth, Some((b"cert".to_vec(), b"key".to_vec())));
+                }
+                _ => panic!("Expected a TLS transport"),
+            }
+        }
+
+        let options = MqttClientBuilder::new("client", "localhost", 1883)
+            .alpn(vec!["mqtt"])
+            .mqtt_options();
+        assert!(matches!(options.transport(), Transport::Tcp));
+    }
+
+    #[test]
+    fn rejects_invalid_config_qos() {
+        let config = MqttClientConfig {
+            client_id: "client".into(),
+            host: "localhost".into(),
+            port: 1883,
+            default_qos: Some(3),
+            ..Default::default()
+        };
+        assert!(matches!(
+            MqttClientBuilder::from_config(config),
+            Err(ConfigError::Invalid("default_qos", _))
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The error returned when a payload can't be encoded or decoded
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The MQTT v5 user properties sent along with a payload
+pub type UserProperties = Vec<(String, String)>;
+
+/// A Codec serializes the payloads published on a topic, and deserializes the payloads received on it.
+/// JSON is always available, MessagePack, CBOR and postcard are enabled with the
+/// `msgpack`, `cbor` and `postcard` features.
+pub trait Codec: Clone + Send + Sync + 'static {
+    /// The MQTT v5 content type advertised with the published payloads
+    fn content_type(&self) -> &'static str;
+
+    /// Serializes a value to a payload
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Deserializes a payload to a value
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError>;
+
+    /// Serializes a value to a payload, and the user properties published with it.
+    /// Codecs keep everything in the payload by default.
+    fn encode_with_properties<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<(Vec<u8>, UserProperties), CodecError> {
+        Ok((self.encode(value)?, UserProperties::new()))
+    }
+
+    /// Deserializes a payload received with some user properties to a value.
+    /// Codecs ignore the user properties by default.
+    fn decode_with_properties<T: DeserializeOwned>(
+        &self,
+        payload: &[u8],
+        _properties: &UserProperties,
+    ) -> Result<T, CodecError> {
+        self.decode(payload)
+    }
+}
+
+/// JSON payloads, using serde_json
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Json;
+
+impl Codec for Json {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+/// MessagePack payloads, using rmp-serde. Structs are encoded as maps.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        Ok(rmp_serde::from_slice(payload)?)
+    }
+}
+
+/// CBOR payloads, using ciborium
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        let mut payload = Vec::new();
+        ciborium::into_writer(value, &mut payload)?;
+        Ok(payload)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        Ok(ciborium::from_reader(payload)?)
+    }
+}
+
+/// postcard payloads, the most compact encoding.
+/// The payloads don't describe their structure, so both sides must use the same message definition.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn content_type(&self) -> &'static str {
+        "application/x-postcard"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(postcard::to_allocvec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        Ok(postcard::from_bytes(payload)?)
+    }
+}
+
+/// One of the built-in codecs, selected at runtime by name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayloadCodec {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "postcard")]
+    Postcard,
+}
+
+impl PayloadCodec {
+    /// Returns the codec with the given name (`json`, `msgpack`, `cbor` or `postcard`),
+    /// or `None` if it is unknown or its feature is disabled
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(PayloadCodec::Json),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Some(PayloadCodec::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Some(PayloadCodec::Cbor),
+            #[cfg(feature = "postcard")]
+            "postcard" => Some(PayloadCodec::Postcard),
+            _ => None,
+        }
+    }
+
+    /// The name of the codec
+    pub fn name(&self) -> &'static str {
+        match self {
+            PayloadCodec::Json => "json",
+            #[cfg(feature = "msgpack")]
+            PayloadCodec::MessagePack => "msgpack",
+            #[cfg(feature = "cbor")]
+            PayloadCodec::Cbor => "cbor",
+            #[cfg(feature = "postcard")]
+            PayloadCodec::Postcard => "postcard",
+        }
+    }
+
+    /// Whether the payloads can be decoded without knowing their structure, e.g. to a `serde_json::Value`
+    pub fn is_self_describing(&self) -> bool {
+        #[cfg(feature = "postcard")]
+        if *self == PayloadCodec::Postcard {
+            return false;
+        }
+        true
+    }
+}
+
+impl Codec for PayloadCodec {
+    fn content_type(&self) -> &'static str {
+        match self {
+            PayloadCodec::Json => Json.content_type(),
+            #[cfg(feature = "msgpack")]
+            PayloadCodec::MessagePack => MessagePack.content_type(),
+            #[cfg(feature = "cbor")]
+            PayloadCodec::Cbor => Cbor.content_type(),
+            #[cfg(feature = "postcard")]
+            PayloadCodec::Postcard => Postcard.content_type(),
+        }
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            PayloadCodec::Json => Json.encode(value),
+            #[cfg(feature = "msgpack")]
+            PayloadCodec::MessagePack => MessagePack.encode(value),
+            #[cfg(feature = "cbor")]
+            PayloadCodec::Cbor => Cbor.encode(value),
+            #[cfg(feature = "postcard")]
+            PayloadCodec::Postcard => Postcard.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+        match self {
+            PayloadCodec::Json => Json.decode(payload),
+            #[cfg(feature = "msgpack")]
+            PayloadCodec::MessagePack => MessagePack.decode(payload),
+            #[cfg(feature = "cbor")]
+            PayloadCodec::Cbor => Cbor.decode(payload),
+            #[cfg(feature = "postcard")]
+            PayloadCodec::Postcard => Postcard.decode(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Reading {
+        sensor: String,
+        values: Vec<f32>,
+        online: bool,
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            sensor: "kitchen".into(),
+            values: vec![21.5, 22.0],
+            online: true,
+        }
+    }
+
+    /// Every codec enabled by the features
+    fn codecs() -> Vec<PayloadCodec> {
+        ["json", "msgpack", "cbor", "postcard"]
+            .into_iter()
+            .filter_map(PayloadCodec::from_name)
+            .collect()
+    }
+
+    #[test]
+    fn roundtrips_payloads() {
+        for codec in codecs() {
+            let payload = codec.encode(&reading()).unwrap();
+            let decoded: Reading = codec.decode(&payload).unwrap();
+            assert_eq!(decoded, reading(), "{}", codec.name());
+            assert_eq!(PayloadCodec::from_name(codec.name()), Some(codec));
+        }
+    }
+
+    #[test]
+    fn binary_codecs_are_more_compact() {
+        let json = Json.encode(&reading()).unwrap().len();
+        for codec in codecs().into_iter().filter(|c| *c != PayloadCodec::Json) {
+            let size = codec.encode(&reading()).unwrap().len();
+            assert!(size < json, "{} payload is {} bytes", codec.name(), size);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_payloads() {
+        for codec in codecs() {
+            let result: Result<Reading, _> = codec.decode(&[0xc1, 0x00]);
+            assert!(result.is_err(), "{}", codec.name());
+        }
+        assert_eq!(PayloadCodec::from_name("xml"), None);
+        assert_eq!(Json.content_type(), "application/json");
+    }
+}
@@ -0,0 +1,331 @@
+use crate::scheduler::DispatchLimits;
+use rumqttc::v5::mqttbytes::{qos, QoS};
+use serde::Deserialize;
+use std::{fmt, fs, path::Path, path::PathBuf};
+
+/// Errors that can occur while loading a client configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A file referenced by the configuration could not be read
+    Io(PathBuf, std::io::Error),
+    /// The TOML configuration could not be parsed
+    Toml(toml::de::Error),
+    /// A required value is missing
+    Missing(&'static str),
+    /// A value could not be parsed
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Failed to read '{}': {}", path.display(), e),
+            ConfigError::Toml(e) => write!(f, "Failed to parse TOML configuration: {}", e),
+            ConfigError::Missing(key) => write!(f, "Missing configuration value '{}'", key),
+            ConfigError::Invalid(key, value) => {
+                write!(
+                    f,
+                    "Invalid value '{}' for configuration key '{}'",
+                    value, key
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The configuration of an MQTT client, which can be loaded from a TOML file or from environment variables.
+/// Any value left unset falls back to the `MqttClientBuilder` default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttClientConfig {
+    pub client_id: String,
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub keep_alive_secs: Option<u64>,
+    pub capacity: Option<usize>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub clean_start: Option<bool>,
+    pub session_expiry_secs: Option<u32>,
+    pub inflight: Option<u16>,
+    pub receive_maximum: Option<u16>,
+    pub default_qos: Option<u8>,
+    pub subscription_qos: Option<u8>,
+    pub tls: Option<TlsConfig>,
+    pub last_will: Option<LastWillConfig>,
+    pub republish_dead_letters: Option<bool>,
+    pub dispatch: Option<DispatchLimits>,
+}
+
+/// TLS settings, using PEM encoded certificates read from files
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The certificate authority used to verify the broker
+    pub ca_cert: PathBuf,
+    /// The client certificate, for mutual TLS
+    pub client_cert: Option<PathBuf>,
+    /// The client private key, for mutual TLS
+    pub client_key: Option<PathBuf>,
+    /// The ALPN protocols to negotiate
+    pub alpn: Option<Vec<String>>,
+}
+
+/// The message published by the broker when the client disconnects ungracefully
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LastWillConfig {
+    pub topic: String,
+    pub payload: String,
+    pub qos: Option<u8>,
+    pub retain: Option<bool>,
+}
+
+impl MqttClientConfig {
+    /// Parses a configuration from a TOML string
+    pub fn from_toml_str(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(ConfigError::Toml)
+    }
+
+    /// Parses a configuration from a TOML file
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let contents = read_file(path.as_ref())?;
+        let contents = String::from_utf8(contents).map_err(|e| {
+            ConfigError::Invalid("file", format!("{}: {}", path.as_ref().display(), e))
+        })?;
+        Self::from_toml_str(&contents)
+    }
+
+    /// Reads a configuration from the `MQTT_*` environment variables.
+    /// `MQTT_CLIENT_ID` and `MQTT_HOST` are required, every other variable is optional:
+    /// `MQTT_PORT`, `MQTT_KEEP_ALIVE_SECS`, `MQTT_CAPACITY`, `MQTT_USERNAME`, `MQTT_PASSWORD`,
+    /// `MQTT_CLEAN_START`, `MQTT_SESSION_EXPIRY_SECS`, `MQTT_INFLIGHT`, `MQTT_RECEIVE_MAXIMUM`,
+    /// `MQTT_DEFAULT_QOS`, `MQTT_SUBSCRIPTION_QOS`, `MQTT_CA_CERT`, `MQTT_CLIENT_CERT`, `MQTT_CLIENT_KEY`,
+    /// `MQTT_ALPN` (comma separated), `MQTT_LAST_WILL_TOPIC`, `MQTT_LAST_WILL_PAYLOAD`,
+    /// `MQTT_LAST_WILL_QOS`, `MQTT_LAST_WILL_RETAIN` and `MQTT_REPUBLISH_DEAD_LETTERS`.
+    /// The client certificate, key and ALPN protocols require `MQTT_CA_CERT`, which enables TLS.
+    /// The dispatch limits can only be set from a TOML file.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Reads a configuration using a lookup function for the `MQTT_*` variables
+    fn from_lookup<L>(lookup: L) -> Result<Self, ConfigError>
+    where
+        L: Fn(&str) -> Option<String>,
+    {
+        let required = |key: &'static str| lookup(key).ok_or(ConfigError::Missing(key));
+
+        let client_cert = lookup("MQTT_CLIENT_CERT").map(PathBuf::from);
+        let client_key = lookup("MQTT_CLIENT_KEY").map(PathBuf::from);
+        let alpn =
+            lookup("MQTT_ALPN").map(|alpn| alpn.split(',').map(|p| p.trim().to_string()).collect());
+        let tls = match lookup("MQTT_CA_CERT") {
+            Some(ca_cert) => Some(TlsConfig {
+                ca_cert: ca_cert.into(),
+                client_cert,
+                client_key,
+                alpn,
+            }),
+            // The TLS options would be ignored without TLS
+            None if client_cert.is_some() || client_key.is_some() || alpn.is_some() => {
+                return Err(ConfigError::Missing("MQTT_CA_CERT"))
+            }
+            None => None,
+        };
+
+        let last_will = match lookup("MQTT_LAST_WILL_TOPIC") {
+            Some(topic) => Some(LastWillConfig {
+                topic,
+                payload: lookup("MQTT_LAST_WILL_PAYLOAD").unwrap_or_default(),
+                qos: parse_var(&lookup, "MQTT_LAST_WILL_QOS")?,
+                retain: parse_var(&lookup, "MQTT_LAST_WILL_RETAIN")?,
+            }),
+            None => None,
+        };
+
+        Ok(MqttClientConfig {
+            client_id: required("MQTT_CLIENT_ID")?,
+            host: required("MQTT_HOST")?,
+            port: parse_var(&lookup, "MQTT_PORT")?.unwrap_or_else(default_port),
+            keep_alive_secs: parse_var(&lookup, "MQTT_KEEP_ALIVE_SECS")?,
+            capacity: parse_var(&lookup, "MQTT_CAPACITY")?,
+            username: lookup("MQTT_USERNAME"),
+            password: lookup("MQTT_PASSWORD"),
+            clean_start: parse_var(&lookup, "MQTT_CLEAN_START")?,
+            session_expiry_secs: parse_var(&lookup, "MQTT_SESSION_EXPIRY_SECS")?,
+            inflight: parse_var(&lookup, "MQTT_INFLIGHT")?,
+            receive_maximum: parse_var(&lookup, "MQTT_RECEIVE_MAXIMUM")?,
+            default_qos: parse_var(&lookup, "MQTT_DEFAULT_QOS")?,
+            subscription_qos: parse_var(&lookup, "MQTT_SUBSCRIPTION_QOS")?,
+            tls,
+            last_will,
+            republish_dead_letters: parse_var(&lookup, "MQTT_REPUBLISH_DEAD_LETTERS")?,
+            dispatch: None,
+        })
+    }
+}
+
+/// The port of the broker when it isn't configured, the standard MQTT port
+fn default_port() -> u16 {
+    1883
+}
+
+/// Parses an optional variable returned by the lookup function
+fn parse_var<L, T>(lookup: &L, key: &'static str) -> Result<Option<T>, ConfigError>
+where
+    L: Fn(&str) -> Option<String>,
+    T: std::str::FromStr,
+{
+    match lookup(key) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError::Invalid(key, value)),
+        None => Ok(None),
+    }
+}
+
+/// Reads a file referenced by the configuration
+pub(crate) fn read_file(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    fs::read(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))
+}
+
+/// Converts a QoS level number to a QoS
+pub(crate) fn parse_qos(key: &'static str, level: u8) -> Result<QoS, ConfigError> {
+    qos(level).ok_or(ConfigError::Invalid(key, level.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::QueuePolicy;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parses_toml_config() {
+        let config = MqttClientConfig::from_toml_str(
+            r#"
+            client_id = "app_1"
+            host = "broker.local"
+            port = 8883
+            keep_alive_secs = 30
+            username = "user"
+            password = "secret"
+            session_expiry_secs = 3600
+            default_qos = 1
+
+            [tls]
+            ca_cert = "certs/ca.pem"
+            client_cert = "certs/client.pem"
+            client_key = "certs/client.key"
+
+            [last_will]
+            topic = "app_1/status"
+            payload = "offline"
+            retain = true
+
+            [dispatch]
+            max_concurrency = 64
+            queue_policy = "drop_oldest"
+            ordered = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.client_id, "app_1");
+        assert_eq!(config.port, 8883);
+        assert_eq!(config.keep_alive_secs, Some(30));
+        assert_eq!(config.default_qos, Some(1));
+        assert_eq!(config.tls.unwrap().ca_cert, PathBuf::from("certs/ca.pem"));
+        assert_eq!(config.last_will.unwrap().retain, Some(true));
+
+        let dispatch = config.dispatch.unwrap();
+        assert_eq!(dispatch.max_concurrency, Some(64));
+        assert_eq!(dispatch.queue_policy, QueuePolicy::DropOldest);
+        assert_eq!(
+            dispatch.queue_capacity,
+            DispatchLimits::default().queue_capacity
+        );
+        assert!(dispatch.ordered);
+    }
+
+    #[test]
+    fn defaults_the_port() {
+        let config = MqttClientConfig::from_toml_str(
+            r#"
+            client_id = "app_1"
+            host = "localhost"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.port, 1883);
+    }
+
+    #[test]
+    fn rejects_unknown_toml_keys() {
+        let result = MqttClientConfig::from_toml_str(
+            r#"
+            client_id = "app_1"
+            host = "localhost"
+            port = 1883
+            keepalive = 10
+            "#,
+        );
+        assert!(matches!(result, Err(ConfigError::Toml(_))));
+    }
+
+    #[test]
+    fn reads_env_config() {
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("MQTT_CLIENT_ID", "app_2"),
+            ("MQTT_HOST", "localhost"),
+            ("MQTT_INFLIGHT", "20"),
+            ("MQTT_CA_CERT", "ca.pem"),
+            ("MQTT_ALPN", "mqtt, x-amzn-mqtt-ca"),
+        ]);
+        let config =
+            MqttClientConfig::from_lookup(|key| vars.get(key).map(|v| v.to_string())).unwrap();
+
+        assert_eq!(config.port, 1883);
+        assert_eq!(config.inflight, Some(20));
+        assert_eq!(
+            config.tls.unwrap().alpn,
+            Some(vec!["mqtt".to_string(), "x-amzn-mqtt-ca".to_string()])
+        );
+        assert_eq!(config.last_will, None);
+    }
+
+    #[test]
+    fn reports_invalid_env_values() {
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("MQTT_CLIENT_ID", "app_2"),
+            ("MQTT_HOST", "localhost"),
+            ("MQTT_PORT", "not_a_port"),
+        ]);
+        let result = MqttClientConfig::from_lookup(|key| vars.get(key).map(|v| v.to_string()));
+        assert!(matches!(result, Err(ConfigError::Invalid("MQTT_PORT", _))));
+
+        let result = MqttClientConfig::from_lookup(|_| None);
+        assert!(matches!(
+            result,
+            Err(ConfigError::Missing("MQTT_CLIENT_ID"))
+        ));
+    }
+
+    #[test]
+    fn requires_a_ca_cert_for_client_auth() {
+        let vars: HashMap<&str, &str> = HashMap::from([
+            ("MQTT_CLIENT_ID", "app_2"),
+            ("MQTT_HOST", "localhost"),
+            ("MQTT_CLIENT_CERT", "client.pem"),
+            ("MQTT_CLIENT_KEY", "client.key"),
+        ]);
+        let result = MqttClientConfig::from_lookup(|key| vars.get(key).map(|v| v.to_string()));
+        assert!(matches!(result, Err(ConfigError::Missing("MQTT_CA_CERT"))));
+    }
+}
@@ -0,0 +1,68 @@
+use arc_swap::ArcSwapOption;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// The topic level dead letters are republished under.
+/// Wildcard filters never match it, and dead letters received under it are never republished.
+pub const DLQ_LEVEL: &str = "$dlq";
+
+/// A payload received on a topic that was rejected before reaching its handler,
+/// because it failed to deserialize or to validate
+#[derive(Debug)]
+pub struct DeadLetter {
+    /// The concrete topic the payload was received on
+    pub topic: String,
+    /// The raw payload
+    pub payload: Vec<u8>,
+    /// Why the payload was rejected, e.g. a `serde_json::Error`
+    pub error: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl DeadLetter {
+    /// The topic bad messages are republished on, `<topic>/$dlq`
+    pub fn dlq_topic(&self) -> String {
+        format!("{}/{}", self.topic, DLQ_LEVEL)
+    }
+
+    /// Whether the payload was received on a dead letter topic, i.e. under a `$dlq` level
+    pub fn is_from_dlq(&self) -> bool {
+        self.topic.split('/').any(|level| level == DLQ_LEVEL)
+    }
+}
+
+/// A function called with every dead letter
+pub type DeadLetterHook = Box<dyn Fn(DeadLetter) + Send + Sync + 'static>;
+
+/// Collects the dead letters of an EventDispatcher, and passes them to the configured hook.
+/// They are only logged when no hook is set.
+#[derive(Default)]
+pub(crate) struct DeadLetters {
+    hook: ArcSwapOption<DeadLetterHook>,
+}
+
+impl DeadLetters {
+    /// Replaces the hook called with every dead letter
+    pub(crate) fn set_hook(&self, hook: DeadLetterHook) {
+        self.hook.store(Some(Arc::new(hook)));
+    }
+
+    /// Logs a dead letter, and passes it to the hook
+    pub(crate) fn send(&self, letter: DeadLetter) {
+        log::error!("Rejected payload on '{}': {}", letter.topic, letter.error);
+        if let Some(hook) = self.hook.load().as_ref() {
+            hook(letter);
+        }
+    }
+}
+
+/// Creates a hook sending the dead letters to a bounded channel.
+/// Dead letters are dropped while the channel is full.
+pub fn dead_letter_channel(capacity: usize) -> (DeadLetterHook, mpsc::Receiver<DeadLetter>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let hook: DeadLetterHook = Box::new(move |letter: DeadLetter| {
+        if let Err(e) = tx.try_send(letter) {
+            log::warn!("Failed to forward a dead letter: {}", e);
+        }
+    });
+    (hook, rx)
+}
@@ -1,80 +1,309 @@
+use crate::codec::{Codec, Json, UserProperties};
+use crate::dead_letter::{DeadLetter, DeadLetters};
+use crate::scheduler::{DispatchHandle, DispatchLimits, Job, Scheduler};
+use crate::topic_trie::TopicTrie;
+use arc_swap::ArcSwap;
 use serde::de::DeserializeOwned;
-use std::collections::HashMap;
-use std::fmt::Debug;
 use std::future::Future;
-use std::hash::Hash;
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
 
 /// Type alias for an asynchronous callback that returns a result of type `R`.
 pub type AsyncCallback<R> = Pin<Box<dyn Future<Output = R> + Send>>;
 
-/// An EventKey identifies an event in a HashMap.
-pub trait EventKey: Hash + Eq + Debug + Clone {}
-impl<T> EventKey for T where T: Hash + Eq + Debug + Clone {}
+/// The callback of an EventHandler, called with the concrete topic, the payload and its user properties
+pub type HandlerCallback =
+    dyn Fn(String, Vec<u8>, &UserProperties) -> AsyncCallback<()> + Send + Sync + 'static;
 
-/// An EventHandler asynchronously handles a serialized payload (of bytes).
+/// An EventHandler asynchronously handles a serialized payload (of bytes)
+/// received on a concrete topic, with its user properties.
 pub struct EventHandler {
-    pub callback: Box<dyn Fn(Vec<u8>) -> AsyncCallback<()> + Send + Sync + 'static>,
+    pub callback: Box<HandlerCallback>,
 }
 
 impl EventHandler {
-    fn handle(&self, payload: Vec<u8>) -> AsyncCallback<()> {
-        (self.callback)(payload)
+    fn handle(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: &UserProperties,
+    ) -> AsyncCallback<()> {
+        (self.callback)(topic, payload, properties)
     }
 }
 
-/// An EventDispatcher stores handlers for a given event key.
-pub struct EventDispatcher<K: EventKey> {
-    pub handlers: HashMap<K, Arc<EventHandler>>,
+/// Counts the handlers currently running, so they can be awaited before shutting down.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<(AtomicUsize, Notify)>,
 }
 
-impl<K: EventKey> EventDispatcher<K> {
-    /// Creates a new event dispatcher.
+/// Marks a handler as running until it is dropped.
+pub struct InFlightGuard(InFlight);
+
+impl InFlight {
+    /// Marks a handler as running
+    pub fn enter(&self) -> InFlightGuard {
+        self.inner.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    /// The number of handlers currently running
+    pub fn count(&self) -> usize {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    /// Waits until no handler is running
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.inner.1.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.count() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.inner.0.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.inner.1.notify_waiters();
+        }
+    }
+}
+
+/// Identifies a handler registered in an EventDispatcher, used to remove it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+/// The handlers of every topic filter
+type Routes = TopicTrie<(HandlerId, Arc<EventHandler>)>;
+
+/// An EventDispatcher stores handlers for MQTT topic filters, which can contain the `+` and `#` wildcards.
+/// Several handlers can be registered for the same filter, and every handler whose filter
+/// matches a topic is called on dispatch, within the dispatch limits.
+///
+/// Dispatching reads an immutable snapshot of the handlers without locking.
+/// Adding or removing a handler copies the snapshot and swaps it in, so it never stalls dispatch.
+pub struct EventDispatcher {
+    routes: ArcSwap<Routes>,
+    /// Serializes the updates of the snapshot, so that concurrent updates are not lost
+    update: Mutex<()>,
+    next_id: AtomicU64,
+    in_flight: InFlight,
+    scheduler: Arc<Scheduler>,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl EventDispatcher {
+    /// Creates a new event dispatcher, running every handler as soon as an event is dispatched.
     pub fn new() -> Self {
+        Self::with_limits(DispatchLimits::default())
+    }
+
+    /// Creates a new event dispatcher, running the handlers within the given limits.
+    pub fn with_limits(limits: DispatchLimits) -> Self {
         Self {
-            handlers: HashMap::new(),
+            routes: ArcSwap::from_pointee(TopicTrie::new()),
+            update: Mutex::new(()),
+            next_id: AtomicU64::new(0),
+            in_flight: InFlight::default(),
+            scheduler: Arc::new(Scheduler::new(limits)),
+            dead_letters: Arc::default(),
         }
     }
 
-    /// Adds an asynchronous handler for a given topic.
+    /// Returns the counter of the handlers currently running
+    pub fn in_flight(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+
+    /// Sets the hook called with the payloads that were rejected before reaching their handler, replacing any previous hook.
+    /// The failures are only logged until a hook is set.
+    pub fn on_dead_letter<H>(&self, hook: H)
+    where
+        H: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        self.dead_letters.set_hook(Box::new(hook));
+    }
+
+    /// Passes a payload rejected outside of the dispatcher to the dead letter hook
+    pub fn report_dead_letter(&self, letter: DeadLetter) {
+        self.dead_letters.send(letter);
+    }
+
+    /// Applies an update to a copy of the handlers, and publishes it as the new snapshot
+    fn update<R>(&self, update: impl FnOnce(&mut Routes) -> R) -> R {
+        let _update = self.update.lock().unwrap();
+        let mut routes = Routes::clone(&self.routes.load());
+        let result = update(&mut routes);
+        self.routes.store(Arc::new(routes));
+        result
+    }
+
+    /// Adds an asynchronous handler for a given topic filter, next to any existing handlers.
+    /// It returns the HandlerId used to remove it.
+    pub fn add_event_handler(&self, filter: &str, handler: EventHandler) -> HandlerId {
+        let id = HandlerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let handler = Arc::new(handler);
+        self.update(|routes| routes.insert(filter, (id, handler)));
+        id
+    }
+
+    /// Removes a handler for a given topic filter.
+    /// It returns true if it was the last handler registered for the filter.
+    pub fn remove_handler(&self, filter: &str, id: HandlerId) -> bool {
+        if !self
+            .routes
+            .load()
+            .get(filter)
+            .iter()
+            .any(|(handler_id, _)| *handler_id == id)
+        {
+            return false;
+        }
+        self.update(|routes| routes.remove(filter, |(handler_id, _)| *handler_id == id))
+    }
+
+    /// Returns true if at least one handler is registered for exactly this topic filter.
+    pub fn has_handlers(&self, filter: &str) -> bool {
+        self.routes.load().contains(filter)
+    }
+
+    /// Adds an asynchronous handler for a given topic filter.
     /// The handler is called with the deserialized payload.
-    pub fn add_handler<T, C, P>(&mut self, topic: T, callback: C)
+    pub fn add_handler<C, P>(&self, filter: &str, callback: C) -> HandlerId
     where
-        T: Into<K>,
         C: Fn(P) -> AsyncCallback<()> + Send + Sync + 'static,
         P: DeserializeOwned + Send + 'static,
     {
+        self.add_topic_handler(filter, move |_topic: String, payload: P| callback(payload))
+    }
+
+    /// Adds an asynchronous handler for a given topic filter.
+    /// The handler is called with the concrete topic the payload was received on,
+    /// and the deserialized JSON payload. Payloads that fail to deserialize are sent to the dead letter hook.
+    pub fn add_topic_handler<C, P>(&self, filter: &str, callback: C) -> HandlerId
+    where
+        C: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+        P: DeserializeOwned + Send + 'static,
+    {
+        self.add_topic_handler_with_codec(filter, Json, callback)
+    }
+
+    /// Adds an asynchronous handler for a given topic filter, decoding the payloads with the given codec.
+    /// The handler is called with the concrete topic the payload was received on,
+    /// and the decoded payload. Payloads that fail to decode are sent to the dead letter hook.
+    pub fn add_topic_handler_with_codec<D, C, P>(
+        &self,
+        filter: &str,
+        codec: D,
+        callback: C,
+    ) -> HandlerId
+    where
+        D: Codec,
+        C: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+        P: DeserializeOwned + Send + 'static,
+    {
+        let dead_letters = Arc::clone(&self.dead_letters);
         let handler = EventHandler {
-            callback: Box::new(move |payload: Vec<u8>| {
-                match serde_json::from_slice::<P>(&payload) {
-                    Ok(deserialized_payload) => callback(deserialized_payload),
-                    Err(e) => {
-                        log::error!("Failed to deserialize payload: {:?}", e);
+            callback: Box::new(move |topic, payload, properties| {
+                match codec.decode_with_properties::<P>(&payload, properties) {
+                    Ok(decoded_payload) => callback(topic, decoded_payload),
+                    Err(error) => {
+                        #[cfg(feature = "telemetry")]
+                        crate::telemetry::on_decode_failure(&topic);
+                        dead_letters.send(DeadLetter {
+                            topic,
+                            payload,
+                            error,
+                        });
                         Box::pin(async {})
                     }
                 }
             }),
         };
 
-        self.handlers.insert(topic.into(), Arc::new(handler));
+        self.add_event_handler(filter, handler)
     }
 
-    /// Dispatches an event to its handler for a given topic.
-    /// The handler runs asynchronously and doesn't block the caller.
-    /// It returns a JoinHandle which can be used to wait for the handler to complete.
-    pub fn dispatch<T, P>(&self, topic: T, payload: P) -> Option<JoinHandle<()>>
+    /// Dispatches an event to every handler whose filter matches a given topic.
+    /// The handlers run asynchronously and don't block the caller, which only waits when
+    /// a topic queue is full with the `QueuePolicy::Block` policy.
+    /// It returns a DispatchHandle per handler which can be used to wait for them to complete.
+    /// The returned future doesn't borrow the dispatcher, so it can be awaited without holding a lock on it.
+    pub fn dispatch<P>(
+        &self,
+        topic: &str,
+        payload: P,
+    ) -> impl Future<Output = Vec<DispatchHandle>> + Send + 'static
     where
-        T: Into<K> + Clone,
         P: Into<Vec<u8>> + Send + 'static,
     {
-        self.handlers.get(&topic.into()).map(|handler| {
-            let handler_clone = Arc::clone(handler);
-            tokio::spawn(async move {
-                handler_clone.handle(payload.into()).await;
+        self.dispatch_with_properties(topic, payload, UserProperties::new())
+    }
+
+    /// Dispatches an event received with some user properties, which are passed to the codecs of the handlers.
+    /// See `dispatch`.
+    pub fn dispatch_with_properties<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        properties: UserProperties,
+    ) -> impl Future<Output = Vec<DispatchHandle>> + Send + 'static
+    where
+        P: Into<Vec<u8>> + Send + 'static,
+    {
+        let routes = self.routes.load();
+        let handlers = routes.matches(topic);
+        #[cfg(feature = "telemetry")]
+        let _span = crate::telemetry::on_dispatch(topic, handlers.len()).entered();
+        let payload: Vec<u8> = if handlers.is_empty() {
+            Vec::new()
+        } else {
+            payload.into()
+        };
+        let properties = Arc::new(properties);
+        let jobs: Vec<(Job, DispatchHandle)> = handlers
+            .into_iter()
+            .map(|(_, handler)| {
+                let handler_clone = Arc::clone(handler);
+                #[cfg(feature = "telemetry")]
+                let trace = crate::telemetry::HandlerTrace::new(topic, &properties);
+                let topic = topic.to_owned();
+                let payload = payload.clone();
+                let properties = Arc::clone(&properties);
+                let callback: AsyncCallback<()> =
+                    Box::pin(
+                        async move { handler_clone.handle(topic, payload, &properties).await },
+                    );
+                #[cfg(feature = "telemetry")]
+                let callback = trace.instrument(callback);
+                Job::new(callback, self.in_flight.enter())
             })
-        })
+            .collect();
+
+        let scheduler = Arc::clone(&self.scheduler);
+        let topic = topic.to_owned();
+        async move {
+            let mut handles = Vec::with_capacity(jobs.len());
+            for (job, handle) in jobs {
+                scheduler.submit(&topic, job).await;
+                handles.push(handle);
+            }
+            handles
+        }
+    }
+}
+
+impl Default for EventDispatcher {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -99,7 +328,7 @@ mod tests {
             value: 42,
         };
 
-        let mut event_dispatcher: EventDispatcher<String> = EventDispatcher::new();
+        let event_dispatcher: EventDispatcher = EventDispatcher::new();
 
         let example_payload_clone = example_payload.clone();
 
@@ -114,7 +343,7 @@ mod tests {
 
         // We call the topic handler with the serialized example payload
         let serialized_payload = serde_json::to_vec(&example_payload).unwrap();
-        event_dispatcher.dispatch("topic", serialized_payload);
+        event_dispatcher.dispatch("topic", serialized_payload).await;
     }
 
     #[tokio::test]
@@ -124,35 +353,160 @@ mod tests {
             message: String,
         }
 
-        let mut manager: EventDispatcher<String> = EventDispatcher::new();
+        let manager: EventDispatcher = EventDispatcher::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
 
-        // Add the first handler to the manager.
-        manager.add_handler("clashing_topic", |payload: SimplePayload| {
+        // Add two handlers to the manager, with the same key.
+        for handler_number in [1, 2] {
+            let calls = calls.clone();
+            manager.add_handler("clashing_topic", move |payload: SimplePayload| {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    println!("Handler {} processed: {:?}", handler_number, payload);
+                    calls.lock().await.push(handler_number);
+                })
+            });
+        }
+
+        // Payload to dispatch.
+        let payload = SimplePayload {
+            message: "Hello from clashing test".into(),
+        };
+
+        // Dispatch the event to the clashing topic, both handlers are called.
+        let serialized_payload = serde_json::to_vec(&payload).unwrap();
+        let handles = manager.dispatch("clashing_topic", serialized_payload).await;
+        assert_eq!(handles.len(), 2, "Both handlers should be dispatched");
+        for handle in join_all(handles).await {
+            handle.expect("Dispatched handler failed");
+        }
+
+        let mut calls = calls.lock().await.clone();
+        calls.sort();
+        assert_eq!(calls, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_handler() {
+        let manager: EventDispatcher = EventDispatcher::new();
+
+        let first = manager.add_handler("topic", |_: u32| Box::pin(async {}));
+        let second = manager.add_handler("topic", |_: u32| {
+            Box::pin(async {
+                panic!("This handler should not be called.");
+            })
+        });
+
+        // Removing one of the handlers keeps the topic registered.
+        assert!(!manager.remove_handler("topic", second));
+        assert!(manager.has_handlers("topic"));
+
+        let handles = manager
+            .dispatch("topic", serde_json::to_vec(&1).unwrap())
+            .await;
+        assert_eq!(handles.len(), 1);
+        for handle in join_all(handles).await {
+            handle.expect("Dispatched handler failed");
+        }
+
+        // Removing the last handler removes the topic.
+        assert!(manager.remove_handler("topic", first));
+        assert!(!manager.has_handlers("topic"));
+        assert!(!manager.remove_handler("topic", first));
+        assert!(manager.dispatch("topic", vec![]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_handlers_receive_concrete_topic() {
+        let manager = EventDispatcher::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        for filter in ["app_1/sensor/+", "app_1/#", "app_2/#"] {
+            let received = received.clone();
+            manager.add_topic_handler(filter, move |topic: String, value: i32| {
+                let received = received.clone();
+                Box::pin(async move {
+                    received.lock().await.push((filter, topic, value));
+                })
+            });
+        }
+
+        let handles = manager
+            .dispatch("app_1/sensor/temperature", serde_json::to_vec(&21).unwrap())
+            .await;
+        assert_eq!(handles.len(), 2);
+        join_all(handles).await;
+
+        let mut received = received.lock().await.clone();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                ("app_1/#", "app_1/sensor/temperature".to_string(), 21),
+                ("app_1/sensor/+", "app_1/sensor/temperature".to_string(), 21),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_handlers() {
+        let manager = EventDispatcher::new();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = Arc::new(Mutex::new(Some(rx)));
+
+        manager.add_handler("topic", move |_: u32| {
+            let rx = rx.clone();
             Box::pin(async move {
-                println!("Handler 1 processed: {:?}", payload);
-                assert!(false, "This handler should not be called.");
+                let rx = rx.lock().await.take().unwrap();
+                let _ = rx.await;
             })
         });
 
-        // Add the second handler to the manager, with the same key.
-        manager.add_handler("clashing_topic", |payload: SimplePayload| {
+        let in_flight = manager.in_flight();
+        manager
+            .dispatch("topic", serde_json::to_vec(&1).unwrap())
+            .await;
+        assert_eq!(in_flight.count(), 1);
+
+        // The wait only completes once the running handler is done.
+        let waiter = tokio::spawn(async move { in_flight.wait_idle().await });
+        time::sleep(time::Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        tx.send(()).unwrap();
+        time::timeout(time::Duration::from_secs(1), waiter)
+            .await
+            .expect("The in-flight handlers were not drained")
+            .unwrap();
+        assert_eq!(manager.in_flight().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_register_while_dispatching() {
+        let manager = Arc::new(EventDispatcher::new());
+        let registered = Arc::new(Mutex::new(Vec::new()));
+
+        // The handler registers another handler while the event is being dispatched.
+        let manager_clone = Arc::clone(&manager);
+        let registered_clone = Arc::clone(&registered);
+        manager.add_handler("register", move |topic: String| {
+            let id = manager_clone.add_handler(&topic, |_: u32| Box::pin(async {}));
+            let registered = registered_clone.clone();
             Box::pin(async move {
-                println!("Handler 2 processed: {:?}", payload);
+                registered.lock().await.push(id);
             })
         });
 
-        // Payload to dispatch.
-        let payload = SimplePayload {
-            message: "Hello from clashing test".into(),
-        };
+        let handles = manager
+            .dispatch("register", serde_json::to_vec("app_1/status").unwrap())
+            .await;
+        time::timeout(time::Duration::from_secs(1), join_all(handles))
+            .await
+            .expect("Registering from a handler should not block");
 
-        // Dispatch the event to the clashing topic.
-        let serialized_payload = serde_json::to_vec(&payload).unwrap();
-        if let Some(handle) = manager.dispatch("clashing_topic", serialized_payload) {
-            handle.await.expect("Dispatched handler failed");
-        } else {
-            assert!(false, "Handler dispatch failed");
-        }
+        assert_eq!(registered.lock().await.len(), 1);
+        assert!(manager.has_handlers("app_1/status"));
+        assert!(manager.has_handlers("register"));
     }
 
     /// Tests that async handlers don't block each others
@@ -166,14 +520,14 @@ mod tests {
             value: u64,
         }
 
-        let mut manager: EventDispatcher<&str> = EventDispatcher::new();
+        let manager: EventDispatcher = EventDispatcher::new();
 
         // Shared vector to record the order of handler completions.
         let completion_order = Arc::new(Mutex::new(Vec::new()));
 
         // Modified to accept a clone of the shared Arc<Mutex<Vec<u64>>> for recording
         let add_handler_with_recording =
-            |manager: &mut EventDispatcher<&str>,
+            |manager: &EventDispatcher,
              topic: &'static str,
              completion_order: Arc<Mutex<Vec<u64>>>| {
                 manager.add_handler(topic, move |payload: SimplePayload| {
@@ -187,9 +541,9 @@ mod tests {
                 });
             };
 
-        add_handler_with_recording(&mut manager, "topic/slow", completion_order.clone());
-        add_handler_with_recording(&mut manager, "topic/fast", completion_order.clone());
-        add_handler_with_recording(&mut manager, "topic/medium", completion_order.clone());
+        add_handler_with_recording(&manager, "topic/slow", completion_order.clone());
+        add_handler_with_recording(&manager, "topic/fast", completion_order.clone());
+        add_handler_with_recording(&manager, "topic/medium", completion_order.clone());
 
         let payloads = vec![
             ("topic/medium", SimplePayload { value: 2 }),
@@ -200,9 +554,7 @@ mod tests {
         let mut handles = Vec::new();
         for (topic, payload) in payloads {
             let serialized_payload = serde_json::to_vec(&payload).unwrap();
-            if let Some(handle) = manager.dispatch(topic, serialized_payload) {
-                handles.push(handle);
-            }
+            handles.extend(manager.dispatch(topic, serialized_payload).await);
         }
 
         time::advance(Duration::from_secs(4)).await;
@@ -220,57 +572,57 @@ mod tests {
 
     #[tokio::test]
     async fn test_nonexistent_event_key() {
-        let manager: EventDispatcher<String> = EventDispatcher::new();
+        let manager: EventDispatcher = EventDispatcher::new();
 
         // Attempt to dispatch an event with a nonexistent key
         assert!(
-            manager.dispatch("nonexistent_topic", vec![]).is_none(),
-            "Expected None for unregistered event key"
+            manager
+                .dispatch("nonexistent_topic", vec![])
+                .await
+                .is_empty(),
+            "Expected no handlers for unregistered event key"
         );
     }
 
     #[tokio::test]
     async fn test_payload_deserialization_error() {
+        use crate::dead_letter::dead_letter_channel;
         use serde::{Deserialize, Serialize};
-        use std::time::Duration;
 
         #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
         struct ExpectedPayload {
             expected_field: String,
         }
 
-        let mut manager: EventDispatcher<String> = EventDispatcher::new();
+        let manager: EventDispatcher = EventDispatcher::new();
+        let (hook, mut dead_letters) = dead_letter_channel(10);
+        manager.on_dead_letter(hook);
 
         // Handler expecting `ExpectedPayload`, but we will dispatch something else
-        manager.add_handler(
-            "test_deserialization_error",
-            move |_payload: ExpectedPayload| {
-                Box::pin(async move {
-                    // If the handler gets called, the test should fail.
-                    assert!(
-                        false,
-                        "Handler should not have been called due to deserialization error."
-                    );
-                })
-            },
-        );
+        manager.add_handler("sensor/+", move |_payload: ExpectedPayload| {
+            Box::pin(async move {
+                // If the handler gets called, the test should fail.
+                panic!("Handler should not have been called due to deserialization error.");
+            })
+        });
 
         // Dispatch a payload that cannot be deserialized into `ExpectedPayload`.
         // For example, missing the `expected_field`.
         let incorrect_payload = serde_json::to_vec(&json!({"unexpected_field": "value"})).unwrap();
-
-        // Since the dispatcher's `dispatch` function currently doesn't return any result indicating success or failure of deserialization,
-        // and instead just logs errors, this test won't be able to directly assert the failure.
-        // However, we can ensure that the system does not panic and that the incorrect handler is not called.
-        if let Some(handle) = manager.dispatch("test_deserialization_error", incorrect_payload) {
-            // We give some time for the handler to potentially be called if it incorrectly proceeds past deserialization.
-            let result = tokio::time::timeout(Duration::from_secs(1), handle).await;
-            assert!(
-                result.is_ok(),
-                "The dispatch should not panic or block indefinitely."
-            );
-        } else {
-            assert!(false, "Dispatcher did not handle the event.");
+        let handles = manager
+            .dispatch("sensor/temperature", incorrect_payload.clone())
+            .await;
+        assert_eq!(handles.len(), 1, "Dispatcher did not handle the event.");
+        for result in join_all(handles).await {
+            result.expect("The handler should not panic");
         }
+
+        // The payload is sent to the dead letter hook, with the concrete topic and the serde error.
+        let letter = dead_letters.try_recv().expect("No dead letter was sent");
+        assert_eq!(letter.topic, "sensor/temperature");
+        assert_eq!(letter.payload, incorrect_payload);
+        assert!(letter.error.to_string().contains("expected_field"));
+        assert_eq!(letter.dlq_topic(), "sensor/temperature/$dlq");
+        assert!(dead_letters.try_recv().is_err());
     }
 }
@@ -1,4 +1,30 @@
+mod builder;
+mod codec;
+mod config;
+mod dead_letter;
 mod event_dispatcher;
 mod mqtt_client;
+mod scheduler;
+mod subscription;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+#[cfg(any(test, feature = "test-broker"))]
+mod test_broker;
+mod topic_trie;
 
-pub use mqtt_client::*;
\ No newline at end of file
+pub use builder::MqttClientBuilder;
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+#[cfg(feature = "postcard")]
+pub use codec::Postcard;
+pub use codec::{Codec, CodecError, Json, PayloadCodec, UserProperties};
+pub use config::{ConfigError, LastWillConfig, MqttClientConfig, TlsConfig};
+pub use dead_letter::{dead_letter_channel, DeadLetter, DeadLetterHook, DLQ_LEVEL};
+pub use event_dispatcher::EventDispatcher;
+pub use mqtt_client::*;
+pub use scheduler::{DispatchError, DispatchHandle, DispatchLimits, QueuePolicy};
+pub use subscription::{Subscription, SubscriptionGuard};
+#[cfg(any(test, feature = "test-broker"))]
+pub use test_broker::TestBroker;
@@ -1,38 +1,94 @@
+use crate::builder::MqttClientBuilder;
+use crate::codec::{Codec, Json};
+use crate::dead_letter::{dead_letter_channel, DeadLetter};
 pub use crate::event_dispatcher::AsyncCallback;
-use crate::event_dispatcher::{EventDispatcher, EventHandler};
+use crate::event_dispatcher::EventDispatcher;
+pub use crate::event_dispatcher::HandlerId;
+use crate::scheduler::DispatchLimits;
+use crate::subscription::{Subscription, SubscriptionGuard};
+pub use rumqttc::v5::mqttbytes::QoS;
 pub use rumqttc::v5::EventLoop;
 use rumqttc::v5::{
-    mqttbytes::{
-        v5::{Packet, Publish},
-        QoS,
-    },
-    AsyncClient, Event, MqttOptions,
+    mqttbytes::v5::{Packet, Publish, PublishProperties},
+    AsyncClient, Event,
 };
+use rumqttc::Outgoing;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+/// The delivery semantics used when publishing a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishOptions {
+    pub qos: QoS,
+    pub retain: bool,
+}
 
 #[derive(Clone)]
 pub struct MqttClient {
     pub client: AsyncClient,
-    event_dispatcher: Arc<Mutex<EventDispatcher<String>>>,
+    event_dispatcher: Arc<EventDispatcher>,
+    /// The QoS of the broker subscription of each topic filter with handlers.
+    /// Its lock serializes registrations, so that the broker subscription of a topic filter follows its handlers.
+    subscriptions: Arc<Mutex<HashMap<String, QoS>>>,
+    shutting_down: Arc<AtomicBool>,
+    default_qos: QoS,
+    subscription_qos: QoS,
+    republish_dead_letters: bool,
 }
 
 impl MqttClient {
     /// Creates a new MQTT client
     pub fn new(client_id: &str, host: &str, port: u16) -> (Self, EventLoop) {
-        let mut mqttoptions = MqttOptions::new(client_id, host, port);
-        mqttoptions.set_keep_alive(Duration::from_secs(10));
-        let (client, event_loop) = AsyncClient::new(mqttoptions, 10);
-        let event_dispatcher: EventDispatcher<String> = EventDispatcher::new();
+        Self::builder(client_id, host, port).build()
+    }
+
+    /// Creates a builder to configure the MQTT client before connecting
+    pub fn builder(client_id: &str, host: &str, port: u16) -> MqttClientBuilder {
+        MqttClientBuilder::new(client_id, host, port)
+    }
+
+    /// Wraps a rumqttc client, used by the MqttClientBuilder
+    pub(crate) fn from_parts(
+        client: AsyncClient,
+        default_qos: QoS,
+        subscription_qos: QoS,
+        dispatch_limits: DispatchLimits,
+        republish_dead_letters: bool,
+    ) -> Self {
+        let client = MqttClient {
+            client,
+            event_dispatcher: Arc::new(EventDispatcher::with_limits(dispatch_limits)),
+            subscriptions: Arc::default(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            default_qos,
+            subscription_qos,
+            republish_dead_letters,
+        };
+        if republish_dead_letters {
+            client.on_dead_letter(|_| {});
+        }
+        client
+    }
+
+    /// The QoS used when publishing without explicit options
+    pub fn default_qos(&self) -> QoS {
+        self.default_qos
+    }
+
+    /// The QoS used when subscribing without an explicit QoS
+    pub fn subscription_qos(&self) -> QoS {
+        self.subscription_qos
+    }
 
-        (
-            MqttClient {
-                client,
-                event_dispatcher: Arc::new(Mutex::new(event_dispatcher)),
-            },
-            event_loop,
-        )
+    /// The options used when publishing without explicit options
+    pub fn publish_options(&self) -> PublishOptions {
+        PublishOptions {
+            qos: self.default_qos,
+            retain: false,
+        }
     }
 
     /// Publishes a payload to a topic
@@ -41,178 +97,533 @@ impl MqttClient {
         T: Into<String>,
         P: Serialize,
     {
-        let serialized_payload = serde_json::to_vec(&payload).unwrap();
-        self.client
-            .publish(topic, QoS::AtMostOnce, false, serialized_payload)
-            .await
-            .unwrap();
+        self.publish_with(topic, payload, self.publish_options())
+            .await;
+    }
+
+    /// Publishes a payload to a topic with the given QoS and retain flag
+    pub async fn publish_with<T, P>(&self, topic: T, payload: P, options: PublishOptions)
+    where
+        T: Into<String>,
+        P: Serialize,
+    {
+        self.publish_with_codec(topic, payload, options, &Json)
+            .await;
+    }
+
+    /// Publishes a payload encoded with the given codec, advertising its content type
+    pub async fn publish_with_codec<T, P, C>(
+        &self,
+        topic: T,
+        payload: P,
+        options: PublishOptions,
+        codec: &C,
+    ) where
+        T: Into<String>,
+        P: Serialize,
+        C: Codec,
+    {
+        let topic: String = topic.into();
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_mut))]
+        let (encoded_payload, mut user_properties) =
+            codec.encode_with_properties(&payload).unwrap();
+        #[cfg(feature = "telemetry")]
+        let span = crate::telemetry::on_publish(&topic, &mut user_properties);
+        let properties = PublishProperties {
+            content_type: Some(codec.content_type().to_owned()),
+            user_properties,
+            ..Default::default()
+        };
+        let publish = self.client.publish_with_properties(
+            topic,
+            options.qos,
+            options.retain,
+            encoded_payload,
+            properties,
+        );
+        #[cfg(feature = "telemetry")]
+        let publish = tracing::Instrument::instrument(publish, span);
+        publish.await.unwrap();
     }
 
-    ///
-    pub async fn register_callback<F, P>(&mut self, topic: &str, callback: F)
+    /// Subscribes to a topic and registers a callback called with the deserialized payload
+    pub async fn register_callback<F, P>(&mut self, topic: &str, callback: F) -> Subscription
     where
         P: DeserializeOwned + Send + 'static,
         F: Fn(P) -> AsyncCallback<()> + Send + Sync + 'static,
     {
-        self.client
-            .subscribe(topic, QoS::AtLeastOnce)
+        let qos = self.subscription_qos;
+        self.register_callback_with_qos(topic, qos, callback).await
+    }
+
+    /// Subscribes to a topic with the given QoS and registers a callback called with the deserialized payload
+    pub async fn register_callback_with_qos<F, P>(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        callback: F,
+    ) -> Subscription
+    where
+        P: DeserializeOwned + Send + 'static,
+        F: Fn(P) -> AsyncCallback<()> + Send + Sync + 'static,
+    {
+        self.register(topic, qos, |event_dispatcher| {
+            event_dispatcher.add_handler(topic, callback)
+        })
+        .await
+    }
+
+    /// Subscribes to a topic filter, which can contain the `+` and `#` wildcards, and registers a callback
+    /// called with the concrete topic and the deserialized payload of every matching message
+    pub async fn register_topic_callback<F, P>(&mut self, filter: &str, callback: F) -> Subscription
+    where
+        P: DeserializeOwned + Send + 'static,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+    {
+        let qos = self.subscription_qos;
+        self.register_topic_callback_with_qos(filter, qos, callback)
             .await
-            .unwrap();
+    }
 
-        self.event_dispatcher
-            .lock()
+    /// Subscribes to a topic filter with the given QoS and registers a callback
+    /// called with the concrete topic and the deserialized payload
+    pub async fn register_topic_callback_with_qos<F, P>(
+        &mut self,
+        filter: &str,
+        qos: QoS,
+        callback: F,
+    ) -> Subscription
+    where
+        P: DeserializeOwned + Send + 'static,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+    {
+        self.register_topic_callback_with_codec(filter, qos, Json, callback)
             .await
-            .add_handler(topic, callback);
     }
 
-    pub async fn register_response<C, P, R>(&mut self, topic: &str, reply_topic: &str, callback: C)
+    /// Subscribes to a topic filter with the given QoS and registers a callback called with the concrete topic
+    /// and the payload decoded with the given codec
+    pub async fn register_topic_callback_with_codec<F, P, C>(
+        &mut self,
+        filter: &str,
+        qos: QoS,
+        codec: C,
+        callback: F,
+    ) -> Subscription
+    where
+        P: DeserializeOwned + Send + 'static,
+        F: Fn(String, P) -> AsyncCallback<()> + Send + Sync + 'static,
+        C: Codec,
+    {
+        self.register(filter, qos, |event_dispatcher| {
+            event_dispatcher.add_topic_handler_with_codec(filter, codec, callback)
+        })
+        .await
+    }
+
+    /// Subscribes to a topic and registers a callback whose result is published on the reply topic
+    pub async fn register_response<C, P, R>(
+        &mut self,
+        topic: &str,
+        reply_topic: &str,
+        callback: C,
+    ) -> Subscription
     where
         C: Fn(P) -> AsyncCallback<R> + Send + Sync + Clone + 'static,
         P: DeserializeOwned + Send + 'static,
         R: Serialize + Send + 'static,
     {
-        self.client
-            .subscribe(topic, QoS::AtLeastOnce)
+        let (qos, reply_options) = (self.subscription_qos, self.publish_options());
+        self.register_response_with(topic, qos, reply_topic, reply_options, callback)
             .await
-            .unwrap();
+    }
 
-        let client_clone = self.clone();
+    /// Subscribes to a topic with the given QoS and registers a callback whose result
+    /// is published on the reply topic with the given options
+    pub async fn register_response_with<C, P, R>(
+        &mut self,
+        topic: &str,
+        qos: QoS,
+        reply_topic: &str,
+        reply_options: PublishOptions,
+        callback: C,
+    ) -> Subscription
+    where
+        C: Fn(P) -> AsyncCallback<R> + Send + Sync + Clone + 'static,
+        P: DeserializeOwned + Send + 'static,
+        R: Serialize + Send + 'static,
+    {
+        let responder = self.clone();
         let reply_topic = reply_topic.to_owned();
-        let handler = EventHandler {
-            callback: Box::new(move |payload: Vec<u8>| {
-                match serde_json::from_slice::<P>(&payload) {
-                    Ok(deserialized_payload) => {
-                        let responder = client_clone.clone();
-                        let callback_clone = callback.clone();
-                        let reply_topic_clone = reply_topic.clone();
-                        Box::pin(async move {
-                            let response = callback_clone(deserialized_payload).await;
-                            responder.publish(reply_topic_clone, response).await;
-                        })
-                    }
-                    Err(e) => {
-                        log::error!("Failed to deserialize payload: {:?}", e);
-                        Box::pin(async {})
-                    }
-                }
-            }),
+        self.register(topic, qos, |event_dispatcher| {
+            event_dispatcher.add_handler(topic, move |payload: P| {
+                let responder = responder.clone();
+                let callback = callback.clone();
+                let reply_topic = reply_topic.clone();
+                Box::pin(async move {
+                    let response = callback(payload).await;
+                    responder
+                        .publish_with(reply_topic, response, reply_options)
+                        .await;
+                })
+            })
+        })
+        .await
+    }
+
+    /// Sets the hook called with the payloads that were rejected before reaching their callback, replacing any previous hook.
+    /// When dead letters are republished, they are published on `<topic>/$dlq` before calling the hook.
+    pub fn on_dead_letter<H>(&self, hook: H)
+    where
+        H: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        let republisher = self
+            .republish_dead_letters
+            .then(|| (self.client.clone(), self.default_qos));
+        self.event_dispatcher.on_dead_letter(move |letter| {
+            if let Some((client, qos)) = &republisher {
+                republish_dead_letter(client, *qos, &letter);
+            }
+            hook(letter);
+        });
+    }
+
+    /// Passes a payload rejected by the application, e.g. by a validation layer, to the dead letter hook
+    pub fn report_dead_letter(&self, letter: DeadLetter) {
+        self.event_dispatcher.report_dead_letter(letter);
+    }
+
+    /// Returns a channel receiving the payloads that callbacks failed to deserialize, replacing any previous hook.
+    /// Dead letters are dropped while the channel is full.
+    pub fn dead_letters(&self, capacity: usize) -> mpsc::Receiver<DeadLetter> {
+        let (hook, dead_letters) = dead_letter_channel(capacity);
+        self.on_dead_letter(hook);
+        dead_letters
+    }
+
+    /// Unregisters a callback.
+    /// The topic is unsubscribed from the broker once its last callback is unregistered.
+    pub async fn unregister(&self, subscription: Subscription) {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let last = self
+            .event_dispatcher
+            .remove_handler(subscription.topic.as_str(), subscription.id);
+        if last {
+            subscriptions.remove(&subscription.topic);
+            if let Err(e) = self.client.unsubscribe(subscription.topic.as_str()).await {
+                log::warn!(
+                    "Failed to unsubscribe from '{}': {:?}",
+                    subscription.topic,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Unregisters a callback without waiting, used when no async runtime is available.
+    /// The callback stays registered if another callback is being registered or unregistered.
+    pub(crate) fn try_unregister(&self, subscription: Subscription) {
+        let Ok(mut subscriptions) = self.subscriptions.try_lock() else {
+            log::warn!(
+                "Failed to unregister a callback from '{}'",
+                subscription.topic
+            );
+            return;
         };
+        if self
+            .event_dispatcher
+            .remove_handler(subscription.topic.as_str(), subscription.id)
+        {
+            subscriptions.remove(&subscription.topic);
+            if let Err(e) = self.client.try_unsubscribe(subscription.topic.as_str()) {
+                log::warn!(
+                    "Failed to unsubscribe from '{}': {:?}",
+                    subscription.topic,
+                    e
+                );
+            }
+        }
+    }
 
-        self.event_dispatcher
-            .lock()
-            .await
-            .handlers
-            .insert(topic.into(), Arc::new(handler));
+    /// Wraps a subscription in a guard that unregisters it when dropped
+    pub fn guard(&self, subscription: Subscription) -> SubscriptionGuard {
+        SubscriptionGuard::new(self.clone(), subscription)
+    }
+
+    /// Stops dispatching incoming messages, waits for the running callbacks to complete,
+    /// and disconnects from the broker. The `start` loop then returns.
+    pub async fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.event_dispatcher.in_flight().wait_idle().await;
+        if let Err(e) = self.client.disconnect().await {
+            log::warn!("Failed to disconnect: {:?}", e);
+        }
+    }
+
+    /// Returns true once `shutdown` was called
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
     }
 
+    /// Adds a handler to the event dispatcher, and subscribes to the topic filter at the broker
+    /// if it is the first handler registered on it.
+    /// A handler needing a higher QoS than the current subscription subscribes again with it,
+    /// which upgrades the subscription shared by all the handlers of the filter until the last one is unregistered.
+    async fn register<A>(&self, filter: &str, qos: QoS, add_handler: A) -> Subscription
+    where
+        A: FnOnce(&EventDispatcher) -> HandlerId,
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let id = add_handler(&self.event_dispatcher);
+        if !matches!(subscriptions.get(filter), Some(subscribed) if *subscribed >= qos) {
+            self.client.subscribe(filter, qos).await.unwrap();
+            subscriptions.insert(filter.to_owned(), qos);
+        }
+
+        Subscription {
+            topic: filter.to_owned(),
+            id,
+        }
+    }
+
+    /// Polls the event loop and dispatches incoming messages, until the client is shut down
     pub async fn start(self, mut event_loop: EventLoop) {
-        // tokio::spawn(async move {
         loop {
-            let event = event_loop.poll().await.unwrap();
-            if let Event::Incoming(Packet::Publish(Publish { topic, payload, .. })) = event {
-                let topic_str = String::from_utf8(topic.to_vec()).unwrap();
-                self.event_dispatcher
-                    .lock()
-                    .await
-                    .dispatch(topic_str, payload);
+            let event = match event_loop.poll().await {
+                Ok(event) => event,
+                Err(_) if self.is_shutting_down() => break,
+                Err(e) => panic!("MQTT connection error: {:?}", e),
+            };
+            match event {
+                Event::Incoming(Packet::Publish(Publish {
+                    topic,
+                    payload,
+                    properties,
+                    ..
+                })) => {
+                    if self.is_shutting_down() {
+                        continue;
+                    }
+                    let topic_str = String::from_utf8(topic.to_vec()).unwrap();
+                    let user_properties = properties
+                        .map(|properties| properties.user_properties)
+                        .unwrap_or_default();
+                    self.event_dispatcher
+                        .dispatch_with_properties(&topic_str, payload, user_properties)
+                        .await;
+                }
+                Event::Outgoing(Outgoing::Disconnect) => break,
+                _ => {}
             }
         }
-        // });
+    }
+}
+
+/// Republishes a dead letter on `<topic>/$dlq`, without waiting.
+/// Dead letters of `$dlq` topics are not republished, which would loop when the client receives its own publishes.
+fn republish_dead_letter(client: &AsyncClient, qos: QoS, letter: &DeadLetter) {
+    if letter.is_from_dlq() {
+        log::debug!("Not republishing the dead letter of '{}'", letter.topic);
+        return;
+    }
+    if let Err(e) = client.try_publish(letter.dlq_topic(), qos, false, letter.payload.clone()) {
+        log::warn!(
+            "Failed to republish a dead letter from '{}': {:?}",
+            letter.topic,
+            e
+        );
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{fmt::Debug, time::Duration};
-    use tokio::{process::Command, sync::mpsc, task::JoinHandle, time::sleep};
+    use crate::codec::{CodecError, UserProperties};
+    use crate::test_broker::TestBroker;
+    use std::time::Duration;
+    use tokio::{sync::mpsc, time::sleep};
 
-    /// Helper function to publish a message to a given topic using mosquitto_pub, with serialization.
-    async fn mock_publish<T, P>(topic: T, payload: P)
-    where
-        T: Into<String>,
-        P: Serialize + Debug,
-    {
-        let topic = topic.into();
-        let serialized_message =
-            serde_json::to_string(&payload).expect("Failed to serialize message");
-        println!(
-            "🥸  Topic: {} | Mock publishing message: {}",
-            topic, serialized_message
+    #[tokio::test]
+    async fn guards_unregister_callbacks() {
+        let (mut client, _event_loop) = MqttClient::new("guards", "localhost", 1883);
+        let topic = "test/guard";
+
+        let first = client
+            .register_callback(topic, |_: i32| Box::pin(async {}))
+            .await;
+        let second = client
+            .register_callback(topic, |_: i32| Box::pin(async {}))
+            .await;
+        let (first, second) = (client.guard(first), client.guard(second));
+
+        first.cancel().await;
+        assert!(client.event_dispatcher.has_handlers(topic));
+
+        drop(second);
+        sleep(Duration::from_millis(50)).await;
+        assert!(!client.event_dispatcher.has_handlers(topic));
+
+        let detached = client
+            .register_callback(topic, |_: i32| Box::pin(async {}))
+            .await;
+        let _ = client.guard(detached).detach();
+        assert!(client.event_dispatcher.has_handlers(topic));
+    }
+
+    #[tokio::test]
+    async fn upgrades_the_subscription_for_a_higher_qos() {
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker.client("upgrade");
+        let topic = "test/upgrade";
+
+        client
+            .register_callback_with_qos(topic, QoS::AtMostOnce, |_: i32| Box::pin(async {}))
+            .await;
+        tokio::spawn(client.clone().start(event_loop));
+        broker.wait_for_subscription(topic).await;
+
+        // A handler needing a higher QoS subscribes again, and a lower one keeps the subscription
+        client
+            .register_callback_with_qos(topic, QoS::ExactlyOnce, |_: i32| Box::pin(async {}))
+            .await;
+        client
+            .register_callback_with_qos(topic, QoS::AtLeastOnce, |_: i32| Box::pin(async {}))
+            .await;
+        broker
+            .wait_for_subscription_with_qos(topic, QoS::ExactlyOnce)
+            .await;
+        assert_eq!(client.subscriptions.lock().await[topic], QoS::ExactlyOnce);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_callbacks() {
+        let (mut client, _event_loop) = MqttClient::new("shutdown", "localhost", 1883);
+        let topic = "test/shutdown";
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = Arc::new(Mutex::new(Some(rx)));
+
+        client
+            .register_callback(topic, move |_: i32| {
+                let rx = rx.clone();
+                Box::pin(async move {
+                    let rx = rx.lock().await.take().unwrap();
+                    let _ = rx.await;
+                })
+            })
+            .await;
+
+        client
+            .event_dispatcher
+            .dispatch(topic, serde_json::to_vec(&1).unwrap())
+            .await;
+
+        let shutdown_client = client.clone();
+        let shutdown = tokio::spawn(async move { shutdown_client.shutdown().await });
+        sleep(Duration::from_millis(50)).await;
+        assert!(client.is_shutting_down());
+        assert!(
+            !shutdown.is_finished(),
+            "Shutdown should wait for the callback"
         );
-        Command::new("mosquitto_pub")
-            .arg("-t")
-            .arg(topic)
-            .arg("-m")
-            .arg(serialized_message)
-            .spawn()
-            .expect("mosquitto_pub command failed to start")
-            .wait()
+
+        tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), shutdown)
             .await
-            .expect("Failed to wait on mosquitto_pub");
+            .expect("Shutdown did not complete")
+            .unwrap();
     }
 
-    /// Helper function to assert that a message is received on a given topic, with deserialization.
-    async fn assert_received<T, P>(topic: T, expected_payload: P) -> JoinHandle<()>
-    where
-        T: Into<String> + Send + Copy + 'static,
-        P: Serialize + Send + Debug + 'static,
-    {
-        let handler = tokio::spawn(async move {
-            println!(
-                "🔎 Topic: {} | Waiting for message {:?}",
-                topic.into(),
-                expected_payload
-            );
+    #[tokio::test]
+    async fn forwards_dead_letters() {
+        let (mut client, _event_loop) = MqttClient::builder("dead_letters", "localhost", 1883)
+            .republish_dead_letters(true)
+            .build();
+        let mut dead_letters = client.dead_letters(10);
+        let topic = "test/dead_letters";
 
-            let child = Command::new("mosquitto_sub")
-                .arg("-C")
-                .arg("1")
-                .arg("-t")
-                .arg(topic.into())
-                .stdout(std::process::Stdio::piped())
-                .spawn()
-                .expect("Failed to spawn mosquitto_sub");
-
-            let output = child
-                .wait_with_output()
-                .await
-                .expect("Failed to wait on mosquitto_sub");
-
-            let expected_payload_serialized = serde_json::to_string(&expected_payload).unwrap();
-            let received_payload_serialized = String::from_utf8(output.stdout).unwrap();
-            let received_payload_serialized = received_payload_serialized.trim();
-
-            println!(
-                "📩 Topic: {} | Received message: {:?}",
-                topic.into(),
-                received_payload_serialized
-            );
+        client
+            .register_response(topic, "test/dead_letters/reply", |value: i32| {
+                Box::pin(async move { value })
+            })
+            .await;
 
-            assert_eq!(
-                received_payload_serialized, expected_payload_serialized,
-                "The expected message was not received for the given topic."
-            );
-        });
+        let handles = client
+            .event_dispatcher
+            .dispatch(topic, b"not a number".to_vec())
+            .await;
+        futures::future::join_all(handles).await;
 
-        sleep(Duration::from_millis(100)).await;
+        let letter = dead_letters.try_recv().expect("No dead letter was sent");
+        assert_eq!(letter.topic, topic);
+        assert_eq!(letter.payload, b"not a number");
+    }
+
+    #[tokio::test]
+    async fn does_not_republish_dead_letters_of_dead_letters() {
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker
+            .builder("dead_letter_loop")
+            .republish_dead_letters(true)
+            .build();
+        let mut dead_letters = client.dead_letters(10);
+        let mut republished = broker.subscribe("app_1/x/$dlq");
+        let mut nested = broker.subscribe("app_1/x/$dlq/$dlq");
+
+        client
+            .register_callback("app_1/#", |_: i32| Box::pin(async {}))
+            .await;
+        // Receives the republished letters, like a monitoring client would
+        client
+            .register_callback("app_1/+/$dlq", |_: i32| Box::pin(async {}))
+            .await;
+        tokio::spawn(client.clone().start(event_loop));
+        broker.wait_for_subscription("app_1/#").await;
+        broker.wait_for_subscription("app_1/+/$dlq").await;
+
+        broker.publish("app_1/x", b"not a number".to_vec());
+
+        let first = dead_letters.recv().await.expect("No dead letter was sent");
+        assert_eq!(first.topic, "app_1/x");
+        let message = republished
+            .recv()
+            .await
+            .expect("The letter was not republished");
+        assert_eq!(message.payload, b"not a number".as_slice());
+
+        let second = dead_letters
+            .recv()
+            .await
+            .expect("The republished letter was not rejected");
+        assert_eq!(second.topic, "app_1/x/$dlq");
+        assert!(second.is_from_dlq());
 
-        handler
+        sleep(Duration::from_millis(100)).await;
+        assert!(
+            dead_letters.try_recv().is_err(),
+            "The '#' filter matched a dead letter topic"
+        );
+        assert!(
+            nested.try_recv().is_err(),
+            "A dead letter was republished twice"
+        );
     }
 
     #[tokio::test]
     async fn test_mqtt_testing_flow() {
+        let broker = TestBroker::start().await;
         let topic = "test/flow";
-        let payload = "Hello flow";
+        let payload = serde_json::to_vec("Hello flow").unwrap();
 
-        let receiver = assert_received(topic, payload).await;
-        mock_publish(topic, payload).await;
-        receiver.await.expect("The spawned task failed");
+        let mut received = broker.subscribe(topic);
+        broker.publish(topic, payload.clone());
+        let message = received.recv().await.expect("The message was not received");
+        assert_eq!(message.payload, payload);
     }
 
     #[tokio::test]
     async fn callback() {
-        let (mut client, event_loop) = MqttClient::new("callback", "localhost", 1883);
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker.client("callback");
         let topic = "test/topic";
         let payload = String::from("Hello World");
         let serialized_payload = serde_json::to_string(&payload).unwrap();
@@ -221,7 +632,6 @@ mod tests {
 
         client
             .register_callback(topic, move |p: String| {
-                println!("Callback with {}", p);
                 let tx_clone = tx.clone();
                 Box::pin(async move {
                     let _ = tx_clone.send(p).await;
@@ -233,14 +643,16 @@ mod tests {
             client.start(event_loop).await;
         });
 
-        mock_publish(topic, &serialized_payload).await;
+        broker.wait_for_subscription(topic).await;
+        broker.publish(topic, serde_json::to_vec(&serialized_payload).unwrap());
         let received_message = rx.recv().await.expect("Failed to receive message");
         assert_eq!(received_message, serialized_payload);
     }
 
     #[tokio::test]
     async fn response() {
-        let (mut client, event_loop) = MqttClient::new("response", "localhost", 1883);
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker.client("response");
         let topic = "test/topic";
         let reply_topic = "test/reply";
         let payload = 42;
@@ -262,10 +674,132 @@ mod tests {
             client.start(event_loop).await;
         });
 
-        let receiver = assert_received(reply_topic, reply_payload.to_string()).await;
-        mock_publish(topic, &payload).await;
+        let mut replies = broker.subscribe(reply_topic);
+        broker.wait_for_subscription(topic).await;
+        broker.publish(topic, serde_json::to_vec(&payload).unwrap());
         let received_message = rx.recv().await.expect("Failed to receive message");
         assert_eq!(received_message, payload);
-        receiver.await.expect("The spawned task failed");
+
+        let reply = replies.recv().await.expect("The reply was not published");
+        assert_eq!(reply.payload, serde_json::to_vec(reply_payload).unwrap());
+    }
+
+    #[tokio::test]
+    async fn advertises_the_codec_content_type() {
+        let broker = TestBroker::start().await;
+        let (client, event_loop) = broker.client("content_type");
+        tokio::spawn(client.clone().start(event_loop));
+
+        let mut messages = broker.subscribe("test/content_type");
+        client
+            .publish_with_codec("test/content_type", 7, client.publish_options(), &Json)
+            .await;
+
+        let message = messages.recv().await.unwrap();
+        let properties = message.properties.expect("The message has no properties");
+        assert_eq!(properties.content_type.as_deref(), Some("application/json"));
+    }
+
+    /// Carries the unit of a number in a user property
+    #[derive(Clone)]
+    struct Unit;
+
+    impl Codec for Unit {
+        fn content_type(&self) -> &'static str {
+            "application/json"
+        }
+
+        fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+            Json.encode(value)
+        }
+
+        fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, CodecError> {
+            Json.decode(payload)
+        }
+
+        fn encode_with_properties<T: Serialize + ?Sized>(
+            &self,
+            value: &T,
+        ) -> Result<(Vec<u8>, UserProperties), CodecError> {
+            Ok((self.encode(value)?, vec![("unit".into(), "celsius".into())]))
+        }
+
+        fn decode_with_properties<T: DeserializeOwned>(
+            &self,
+            payload: &[u8],
+            properties: &UserProperties,
+        ) -> Result<T, CodecError> {
+            let value: serde_json::Value = self.decode(payload)?;
+            let unit = properties.iter().find(|(key, _)| key == "unit");
+            Ok(serde_json::from_value(serde_json::json!([
+                value,
+                unit.map(|(_, unit)| unit)
+            ]))?)
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_user_properties_to_the_codecs() {
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker.client("user_properties");
+        tokio::spawn(client.clone().start(event_loop));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _subscription = client
+            .register_topic_callback_with_codec(
+                "test/user_properties",
+                QoS::AtLeastOnce,
+                Unit,
+                move |_, reading: (f32, Option<String>)| {
+                    let _ = tx.send(reading);
+                    Box::pin(async {})
+                },
+            )
+            .await;
+        broker.wait_for_subscription("test/user_properties").await;
+
+        let mut messages = broker.subscribe("test/user_properties");
+        client
+            .publish_with_codec(
+                "test/user_properties",
+                21.5,
+                client.publish_options(),
+                &Unit,
+            )
+            .await;
+
+        let properties = messages.recv().await.unwrap().properties.unwrap();
+        let unit = ("unit".to_owned(), "celsius".to_owned());
+        assert!(properties.user_properties.contains(&unit));
+        let reading = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(reading.unwrap(), Some((21.5, Some("celsius".to_owned()))));
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[tokio::test]
+    async fn runs_handlers_in_the_trace_of_their_message() {
+        use crate::telemetry::{test_subscriber, trace_id};
+        use tracing::Instrument;
+
+        // The runtime of the test runs every task on this thread
+        let _subscriber = tracing::subscriber::set_default(test_subscriber());
+        let broker = TestBroker::start().await;
+        let (mut client, event_loop) = broker.client("telemetry");
+        tokio::spawn(client.clone().start(event_loop));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _subscription = client
+            .register_callback("test/telemetry", move |_: u32| {
+                let _ = tx.send(trace_id(&tracing::Span::current()));
+                Box::pin(async {})
+            })
+            .await;
+        broker.wait_for_subscription("test/telemetry").await;
+
+        let span = tracing::info_span!("request");
+        let trace = trace_id(&span);
+        client.publish("test/telemetry", 1).instrument(span).await;
+        let handled = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(handled.unwrap(), Some(trace));
     }
 }
@@ -0,0 +1,576 @@
+use crate::event_dispatcher::{AsyncCallback, InFlightGuard};
+use futures::FutureExt;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::{oneshot, Notify};
+
+/// What to do with an incoming message when the queue of its topic is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePolicy {
+    /// Drops the incoming message
+    #[default]
+    DropNewest,
+    /// Drops the oldest queued message to make room for the incoming one
+    DropOldest,
+    /// Waits for room in the queue, which stops polling the event loop in the meantime.
+    /// The broker connection may time out if the handlers stay blocked longer than the keep-alive interval.
+    /// A handler must not wait on the client whose event loop it blocks, e.g. with `publish`,
+    /// which waits for room in the request channel that only the event loop empties:
+    /// it deadlocks once that channel is full. Such a handler should publish from a spawned task.
+    Block,
+}
+
+/// Limits on how the handlers of incoming messages are run.
+/// By default every handler runs in its own task as soon as a message is received.
+/// Once a limit is reached, the messages are queued per topic until a handler completes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatchLimits {
+    /// The maximum number of handlers running at once, across all topics
+    pub max_concurrency: Option<usize>,
+    /// The maximum number of handlers running at once for a single topic
+    pub max_concurrency_per_topic: Option<usize>,
+    /// The maximum number of messages waiting to be handled for a single topic
+    pub queue_capacity: usize,
+    /// The maximum number of messages waiting to be handled across all topics
+    pub total_queue_capacity: usize,
+    /// What to do with an incoming message when the queue of its topic is full
+    pub queue_policy: QueuePolicy,
+    /// Handles the messages of each topic one at a time, in the order they were received.
+    /// Different topics are still handled in parallel.
+    pub ordered: bool,
+}
+
+impl Default for DispatchLimits {
+    fn default() -> Self {
+        DispatchLimits {
+            max_concurrency: None,
+            max_concurrency_per_topic: None,
+            queue_capacity: 1024,
+            total_queue_capacity: 16 * 1024,
+            queue_policy: QueuePolicy::default(),
+            ordered: false,
+        }
+    }
+}
+
+/// The reason a dispatched handler did not complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The message was dropped by the queue policy
+    Dropped,
+    /// The handler panicked
+    Panicked,
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::Dropped => {
+                write!(f, "The message was dropped because the queue is full")
+            }
+            DispatchError::Panicked => write!(f, "The handler panicked"),
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
+
+/// Resolves once a dispatched handler has completed, or was dropped by the queue policy.
+pub struct DispatchHandle(oneshot::Receiver<Result<(), DispatchError>>);
+
+impl Future for DispatchHandle {
+    type Output = Result<(), DispatchError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(DispatchError::Dropped)))
+    }
+}
+
+/// A handler call waiting to be run.
+/// Dropping it resolves its DispatchHandle with `DispatchError::Dropped`.
+pub(crate) struct Job {
+    callback: AsyncCallback<()>,
+    done: oneshot::Sender<Result<(), DispatchError>>,
+    _in_flight: InFlightGuard,
+}
+
+impl Job {
+    /// Creates a job and the handle resolving once it completes
+    pub(crate) fn new(
+        callback: AsyncCallback<()>,
+        in_flight: InFlightGuard,
+    ) -> (Self, DispatchHandle) {
+        let (done, handle) = oneshot::channel();
+        let job = Job {
+            callback,
+            done,
+            _in_flight: in_flight,
+        };
+        (job, DispatchHandle(handle))
+    }
+
+    async fn run(self) {
+        let result = AssertUnwindSafe(self.callback)
+            .catch_unwind()
+            .await
+            .map_err(|_| DispatchError::Panicked);
+        let _ = self.done.send(result);
+    }
+}
+
+/// The queued jobs of a topic, and the number of its jobs running
+#[derive(Default)]
+struct Lane {
+    running: usize,
+    queue: VecDeque<Job>,
+    /// Whether the lane is in the ready list, waiting for a worker
+    ready: bool,
+}
+
+/// The jobs waiting to run, and the workers running them
+#[derive(Default)]
+struct State {
+    lanes: HashMap<String, Lane>,
+    /// The lanes with queued jobs that may run more of them, served in turn by the workers
+    ready: VecDeque<String>,
+    workers: usize,
+    queued: usize,
+}
+
+/// Runs jobs within the dispatch limits.
+/// At most `max_concurrency` worker tasks run at once, each running a job at a time, then the next job
+/// of the lanes that are ready in turn, and exiting once none is. A topic runs at most `lane_limit` jobs at once.
+/// A worker is spawned with the job that started it, so only the jobs waiting for a worker fill the queues.
+pub(crate) struct Scheduler {
+    limits: DispatchLimits,
+    state: Mutex<State>,
+    space: Notify,
+}
+
+impl Scheduler {
+    pub(crate) fn new(limits: DispatchLimits) -> Self {
+        Scheduler {
+            limits,
+            state: Mutex::new(State::default()),
+            space: Notify::new(),
+        }
+    }
+
+    /// The maximum number of workers, across all topics
+    fn worker_limit(&self) -> usize {
+        self.limits.max_concurrency.unwrap_or(usize::MAX).max(1)
+    }
+
+    /// The maximum number of running jobs of a single topic
+    fn lane_limit(&self) -> usize {
+        if self.limits.ordered {
+            return 1;
+        }
+        [
+            self.limits.max_concurrency,
+            self.limits.max_concurrency_per_topic,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(usize::MAX)
+        .max(1)
+    }
+
+    /// Runs a job as soon as the limits allow it, queueing it in the meantime.
+    /// It only waits when a queue is full and the policy is `QueuePolicy::Block`.
+    pub(crate) async fn submit(self: &Arc<Self>, topic: &str, job: Job) {
+        loop {
+            let notified = self.space.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                let State {
+                    lanes,
+                    ready,
+                    workers,
+                    queued,
+                } = &mut *state;
+                let lane = lanes.entry(topic.to_owned()).or_default();
+                if lane.running < self.lane_limit() && *workers < self.worker_limit() {
+                    lane.running += 1;
+                    *workers += 1;
+                    tokio::spawn(Arc::clone(self).work(topic.to_owned(), job));
+                    return;
+                }
+                if lane.queue.len() < self.limits.queue_capacity
+                    && *queued < self.limits.total_queue_capacity
+                {
+                    lane.queue.push_back(job);
+                    *queued += 1;
+                    self.mark_ready(topic, lane, ready);
+                    return;
+                }
+                // Without queued messages of its own, the topic only has the incoming one to drop
+                if self.limits.queue_policy == QueuePolicy::DropOldest && !lane.queue.is_empty() {
+                    log::warn!(
+                        "The queue of '{}' is full, dropping the oldest message",
+                        topic
+                    );
+                    lane.queue.pop_front();
+                    lane.queue.push_back(job);
+                    return;
+                }
+                // A topic without running nor queued jobs has no lane
+                if lane.running == 0 && lane.queue.is_empty() {
+                    lanes.remove(topic);
+                }
+                if self.limits.queue_policy != QueuePolicy::Block {
+                    log::warn!("The queue of '{}' is full, dropping the message", topic);
+                    return;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Adds a lane to the ready list if it has queued jobs and may run more of them
+    fn mark_ready(&self, topic: &str, lane: &mut Lane, ready: &mut VecDeque<String>) {
+        if !lane.ready && !lane.queue.is_empty() && lane.running < self.lane_limit() {
+            lane.ready = true;
+            ready.push_back(topic.to_owned());
+        }
+    }
+
+    /// Runs a job, then the queued jobs of the ready lanes in turn until none is left
+    async fn work(self: Arc<Self>, mut topic: String, mut job: Job) {
+        loop {
+            job.run().await;
+
+            let next = {
+                let mut state = self.state.lock().unwrap();
+                let State {
+                    lanes,
+                    ready,
+                    workers,
+                    queued,
+                } = &mut *state;
+                let lane = lanes
+                    .get_mut(&topic)
+                    .expect("A lane is only removed once it has no running job");
+                lane.running -= 1;
+                self.mark_ready(&topic, lane, ready);
+                if lane.running == 0 && lane.queue.is_empty() {
+                    lanes.remove(&topic);
+                }
+
+                let next = ready.pop_front().map(|topic| {
+                    let lane = lanes.get_mut(&topic).expect("A ready lane has queued jobs");
+                    let next = lane
+                        .queue
+                        .pop_front()
+                        .expect("A ready lane has queued jobs");
+                    lane.running += 1;
+                    lane.ready = false;
+                    self.mark_ready(&topic, lane, ready);
+                    (next, topic)
+                });
+                match next {
+                    Some(_) => *queued -= 1,
+                    None => *workers -= 1,
+                }
+                next
+            };
+            self.space.notify_waiters();
+            match next {
+                Some((next, next_topic)) => (job, topic) = (next, next_topic),
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_dispatcher::{EventDispatcher, InFlight};
+    use futures::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Mutex as AsyncMutex;
+    use tokio::time::{self, Duration};
+
+    /// Adds a handler sleeping for the given number of milliseconds, recording the peak concurrency
+    fn add_sleeping_handler(
+        dispatcher: &EventDispatcher,
+        filter: &str,
+        running: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    ) {
+        dispatcher.add_handler(filter, move |millis: u64| {
+            let (running, peak) = (running.clone(), peak.clone());
+            Box::pin(async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                time::sleep(Duration::from_millis(millis)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+    }
+
+    async fn dispatch_all(
+        dispatcher: &EventDispatcher,
+        topics: &[&str],
+        millis: u64,
+    ) -> Vec<DispatchHandle> {
+        let mut handles = Vec::new();
+        for topic in topics {
+            let payload = serde_json::to_vec(&millis).unwrap();
+            handles.extend(dispatcher.dispatch(topic, payload).await);
+        }
+        handles
+    }
+
+    #[tokio::test]
+    async fn limits_global_concurrency() {
+        let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+            max_concurrency: Some(2),
+            ..Default::default()
+        });
+        let (running, peak) = (Arc::default(), Arc::default());
+        add_sleeping_handler(&dispatcher, "+", running, Arc::clone(&peak));
+
+        let handles = dispatch_all(&dispatcher, &["a", "b", "c", "a", "b", "c"], 20).await;
+        for result in join_all(handles).await {
+            result.expect("No message should be dropped");
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn limits_per_topic_concurrency() {
+        let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+            max_concurrency_per_topic: Some(2),
+            ..Default::default()
+        });
+        let (running, peak) = (Arc::default(), Arc::default());
+        add_sleeping_handler(&dispatcher, "a", running, Arc::clone(&peak));
+        let (other_running, other_peak) = (Arc::default(), Arc::default());
+        add_sleeping_handler(&dispatcher, "b", other_running, Arc::clone(&other_peak));
+
+        let handles = dispatch_all(&dispatcher, &["a", "b", "a", "b", "a", "b"], 20).await;
+        for result in join_all(handles).await {
+            result.expect("No message should be dropped");
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(other_peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn bounds_the_workers_and_queued_jobs_across_topics() {
+        let scheduler = Arc::new(Scheduler::new(DispatchLimits {
+            max_concurrency: Some(2),
+            total_queue_capacity: 5,
+            ..Default::default()
+        }));
+        let in_flight = InFlight::default();
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+
+        let mut handles = Vec::new();
+        for topic in 0..100 {
+            let release = Arc::clone(&release);
+            let (job, handle) = Job::new(
+                Box::pin(async move {
+                    release.acquire().await.unwrap().forget();
+                }),
+                in_flight.enter(),
+            );
+            scheduler.submit(&format!("topic/{}", topic), job).await;
+            handles.push(handle);
+        }
+        {
+            // Every topic is distinct, but only two workers run and five jobs wait for them
+            let state = scheduler.state.lock().unwrap();
+            assert_eq!(state.workers, 2);
+            assert_eq!(state.queued, 5);
+            assert_eq!(state.lanes.len(), 7);
+        }
+
+        release.add_permits(100);
+        let results = join_all(handles).await;
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 7);
+        // The workers update the state once their last job has completed
+        let idle = || {
+            let state = scheduler.state.lock().unwrap();
+            (state.workers, state.queued, state.lanes.len()) == (0, 0, 0)
+        };
+        for _ in 0..100 {
+            if idle() {
+                break;
+            }
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(idle());
+    }
+
+    #[tokio::test]
+    async fn drops_messages_when_the_queue_is_full() {
+        for (policy, expected) in [
+            (QueuePolicy::DropNewest, vec![0, 1]),
+            (QueuePolicy::DropOldest, vec![0, 3]),
+        ] {
+            let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+                queue_capacity: 1,
+                queue_policy: policy,
+                ordered: true,
+                ..Default::default()
+            });
+            let handled = Arc::new(AsyncMutex::new(Vec::new()));
+            let handled_clone = Arc::clone(&handled);
+            dispatcher.add_handler("topic", move |value: u32| {
+                let handled = handled_clone.clone();
+                Box::pin(async move {
+                    time::sleep(Duration::from_millis(20)).await;
+                    handled.lock().await.push(value);
+                })
+            });
+
+            // The first message runs, the second one is queued, and the others overflow the queue.
+            let mut handles = Vec::new();
+            for value in 0..4u32 {
+                handles.extend(
+                    dispatcher
+                        .dispatch("topic", serde_json::to_vec(&value).unwrap())
+                        .await,
+                );
+            }
+            let dropped = join_all(handles)
+                .await
+                .into_iter()
+                .filter(|result| *result == Err(DispatchError::Dropped))
+                .count();
+
+            assert_eq!(dropped, 2, "{:?}", policy);
+            assert_eq!(*handled.lock().await, expected, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks_until_the_queue_has_room() {
+        let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+            queue_capacity: 1,
+            queue_policy: QueuePolicy::Block,
+            ordered: true,
+            ..Default::default()
+        });
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let rx = Arc::new(AsyncMutex::new(rx));
+        dispatcher.add_handler("topic", move |_: u32| {
+            let rx = rx.clone();
+            Box::pin(async move {
+                rx.lock().await.recv().await;
+            })
+        });
+        let dispatcher = Arc::new(dispatcher);
+
+        let mut handles = Vec::new();
+        handles.extend(
+            dispatcher
+                .dispatch("topic", serde_json::to_vec(&0).unwrap())
+                .await,
+        );
+        handles.extend(
+            dispatcher
+                .dispatch("topic", serde_json::to_vec(&1).unwrap())
+                .await,
+        );
+
+        // The queue is full, so the third dispatch waits for the first handler to complete.
+        let blocked_dispatcher = Arc::clone(&dispatcher);
+        let blocked = tokio::spawn(async move {
+            blocked_dispatcher
+                .dispatch("topic", serde_json::to_vec(&2).unwrap())
+                .await
+        });
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+
+        for _ in 0..3 {
+            tx.send(()).unwrap();
+        }
+        handles.extend(blocked.await.unwrap());
+        for result in join_all(handles).await {
+            result.expect("No message should be dropped");
+        }
+    }
+
+    #[tokio::test]
+    async fn orders_messages_per_topic() {
+        let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+            ordered: true,
+            ..Default::default()
+        });
+        let handled = Arc::new(AsyncMutex::new(Vec::new()));
+        let handled_clone = Arc::clone(&handled);
+        dispatcher.add_topic_handler("sensor/+", move |topic: String, millis: u64| {
+            let handled = handled_clone.clone();
+            Box::pin(async move {
+                time::sleep(Duration::from_millis(millis)).await;
+                handled.lock().await.push((topic, millis));
+            })
+        });
+
+        let mut handles = Vec::new();
+        for (topic, millis) in [("sensor/a", 60), ("sensor/a", 10), ("sensor/b", 5)] {
+            handles.extend(
+                dispatcher
+                    .dispatch(topic, serde_json::to_vec(&millis).unwrap())
+                    .await,
+            );
+        }
+        join_all(handles).await;
+
+        // 'sensor/b' is not held back by 'sensor/a', whose messages are handled in order.
+        assert_eq!(
+            *handled.lock().await,
+            vec![
+                ("sensor/b".to_string(), 5),
+                ("sensor/a".to_string(), 60),
+                ("sensor/a".to_string(), 10),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_panicking_handlers() {
+        let dispatcher = EventDispatcher::with_limits(DispatchLimits {
+            ordered: true,
+            ..Default::default()
+        });
+        dispatcher.add_handler("topic", |value: u32| {
+            Box::pin(async move {
+                if value == 0 {
+                    panic!("Handler failure");
+                }
+            })
+        });
+
+        let mut handles = dispatcher
+            .dispatch("topic", serde_json::to_vec(&0).unwrap())
+            .await;
+        handles.extend(
+            dispatcher
+                .dispatch("topic", serde_json::to_vec(&1).unwrap())
+                .await,
+        );
+
+        // The worker of the topic keeps running the next messages after a panic.
+        let results = join_all(handles).await;
+        assert_eq!(results, vec![Err(DispatchError::Panicked), Ok(())]);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::event_dispatcher::HandlerId;
+use crate::mqtt_client::MqttClient;
+
+/// A handle to a callback registered on a topic, used to unregister it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub topic: String,
+    pub id: HandlerId,
+}
+
+/// Keeps a callback registered until it is cancelled or dropped.
+/// Use `detach` to keep the callback registered for the lifetime of the client.
+#[must_use = "the callback is unregistered as soon as the guard is dropped"]
+pub struct SubscriptionGuard {
+    client: MqttClient,
+    subscription: Option<Subscription>,
+}
+
+impl SubscriptionGuard {
+    /// Creates a guard unregistering the subscription from the client on drop
+    pub fn new(client: MqttClient, subscription: Subscription) -> Self {
+        SubscriptionGuard {
+            client,
+            subscription: Some(subscription),
+        }
+    }
+
+    /// The guarded subscription
+    pub fn subscription(&self) -> &Subscription {
+        self.subscription
+            .as_ref()
+            .expect("The subscription is only taken when the guard is consumed")
+    }
+
+    /// Unregisters the callback, and waits for the topic to be unsubscribed if it was the last one
+    pub async fn cancel(mut self) {
+        if let Some(subscription) = self.subscription.take() {
+            self.client.unregister(subscription).await;
+        }
+    }
+
+    /// Releases the guard, leaving the callback registered
+    pub fn detach(mut self) -> Subscription {
+        self.subscription
+            .take()
+            .expect("The subscription is only taken when the guard is consumed")
+    }
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        let Some(subscription) = self.subscription.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let client = self.client.clone();
+                runtime.spawn(async move { client.unregister(subscription).await });
+            }
+            Err(_) => self.client.try_unregister(subscription),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Tracing spans, OpenTelemetry metrics and trace context propagation, enabled by the `telemetry` feature
+
+use crate::codec::UserProperties;
+use crate::event_dispatcher::AsyncCallback;
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The user property carrying the W3C trace context of a message
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Writes a trace context to the user properties of a message, replacing the values of the same keys.
+/// Empty values, such as the `tracestate` of most traces, are left out.
+pub struct PropertyInjector<'a>(pub &'a mut UserProperties);
+
+impl Injector for PropertyInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.retain(|(name, _)| name != key);
+        if !value.is_empty() {
+            self.0.push((key.to_owned(), value));
+        }
+    }
+}
+
+/// Reads a trace context from the user properties of a message
+pub struct PropertyExtractor<'a>(pub &'a UserProperties);
+
+impl Extractor for PropertyExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_str()).collect()
+    }
+}
+
+/// Adds the W3C trace context of a span to user properties.
+/// Nothing is added when the span isn't recorded by a `tracing_opentelemetry` layer.
+pub fn inject(span: &Span, properties: &mut UserProperties) {
+    TraceContextPropagator::new()
+        .inject_context(&span.context(), &mut PropertyInjector(properties));
+}
+
+/// Reads the W3C trace context carried by user properties, which has no span when they carry none
+pub fn extract(properties: &UserProperties) -> Context {
+    TraceContextPropagator::new().extract(&PropertyExtractor(properties))
+}
+
+/// Makes the trace context carried by user properties the parent of a span, if they carry one
+fn set_remote_parent(span: &Span, properties: &UserProperties) {
+    let parent = extract(properties);
+    if parent.span().span_context().is_valid() {
+        // Fails only when the span isn't recorded by a `tracing_opentelemetry` layer
+        let _ = span.set_parent(parent);
+    }
+}
+
+/// The metrics of the MQTT runtime, reported to the global OpenTelemetry meter provider.
+/// The provider must be set before the first message is published or received.
+struct Metrics {
+    messages_out: Counter<u64>,
+    messages_in: Counter<u64>,
+    handler_duration: Histogram<f64>,
+    decode_failures: Counter<u64>,
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let meter = opentelemetry::global::meter("mqtt-client");
+        Metrics {
+            messages_out: meter
+                .u64_counter("mqtt.messages.out")
+                .with_description("The messages published")
+                .build(),
+            messages_in: meter
+                .u64_counter("mqtt.messages.in")
+                .with_description("The messages received")
+                .build(),
+            handler_duration: meter
+                .f64_histogram("mqtt.handler.duration")
+                .with_description("The time spent handling a message")
+                .with_unit("s")
+                .build(),
+            decode_failures: meter
+                .u64_counter("mqtt.decode.failures")
+                .with_description("The payloads that failed to decode")
+                .build(),
+        }
+    })
+}
+
+fn topic_attribute(topic: &str) -> [KeyValue; 1] {
+    [KeyValue::new("topic", topic.to_owned())]
+}
+
+/// Records a published message, and returns the span of the publication, a child of the current span.
+/// The trace context of the span is added to the user properties of the message, unless its codec already added one,
+/// in which case the span belongs to that trace.
+pub fn on_publish(topic: &str, properties: &mut UserProperties) -> Span {
+    metrics().messages_out.add(1, &topic_attribute(topic));
+    let span = tracing::info_span!("mqtt.publish", topic);
+    if properties.iter().any(|(key, _)| key == TRACEPARENT) {
+        set_remote_parent(&span, properties);
+    } else {
+        inject(&span, properties);
+    }
+    span
+}
+
+/// Records a received message, and returns the span of its dispatch
+pub(crate) fn on_dispatch(topic: &str, handlers: usize) -> Span {
+    metrics().messages_in.add(1, &topic_attribute(topic));
+    tracing::debug_span!("mqtt.dispatch", topic, handlers)
+}
+
+/// Records a payload that failed to decode
+pub(crate) fn on_decode_failure(topic: &str) {
+    metrics().decode_failures.add(1, &topic_attribute(topic));
+}
+
+/// The trace of a handler, whose span is a child of the span that published the message it handles
+pub(crate) struct HandlerTrace {
+    span: Span,
+    attributes: [KeyValue; 1],
+}
+
+impl HandlerTrace {
+    pub(crate) fn new(topic: &str, properties: &UserProperties) -> Self {
+        let span = tracing::info_span!("mqtt.handle", topic);
+        set_remote_parent(&span, properties);
+        HandlerTrace {
+            span,
+            attributes: topic_attribute(topic),
+        }
+    }
+
+    /// Runs the handler within its span, so the messages it publishes belong to the same trace, and records its duration
+    pub(crate) fn instrument(self, handler: AsyncCallback<()>) -> AsyncCallback<()> {
+        Box::pin(async move {
+            let start = Instant::now();
+            handler.instrument(self.span).await;
+            metrics()
+                .handler_duration
+                .record(start.elapsed().as_secs_f64(), &self.attributes);
+        })
+    }
+}
+
+/// A subscriber recording the spans with an OpenTelemetry tracer, as the apps install one
+#[cfg(test)]
+pub(crate) fn test_subscriber() -> impl tracing::Subscriber + Send + Sync {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let tracer = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .build()
+        .tracer("test");
+    tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// The trace a span belongs to
+#[cfg(test)]
+pub(crate) fn trace_id(span: &Span) -> opentelemetry::trace::TraceId {
+    span.context().span().span_context().trace_id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn propagates_the_trace_context_in_user_properties() {
+        let _subscriber = tracing::subscriber::set_default(test_subscriber());
+        let parent = tracing::info_span!("parent");
+        let mut properties = UserProperties::new();
+        let published = parent.in_scope(|| on_publish("topic", &mut properties));
+
+        assert_eq!(properties.len(), 1, "{:?}", properties);
+        let context = extract(&properties);
+        let span_context = context.span().span_context().clone();
+        assert!(span_context.is_remote());
+        assert_eq!(span_context.trace_id(), trace_id(&parent));
+        assert_eq!(
+            span_context.span_id(),
+            published.context().span().span_context().span_id()
+        );
+
+        let handler = HandlerTrace::new("topic", &properties);
+        assert_eq!(trace_id(&handler.span), trace_id(&parent));
+
+        // The trace context set by a codec is kept
+        let republished = on_publish("topic", &mut properties);
+        assert_eq!(properties.len(), 1);
+        assert_eq!(trace_id(&republished), trace_id(&parent));
+    }
+
+    #[test]
+    fn adds_no_trace_context_without_opentelemetry_layer() {
+        let mut properties = UserProperties::new();
+        let _span = on_publish("topic", &mut properties);
+        assert!(properties.is_empty());
+        assert!(!extract(&properties).has_active_span());
+    }
+}
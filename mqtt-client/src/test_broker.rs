@@ -0,0 +1,404 @@
+//! A minimal in-process MQTT v5 broker, so that tests don't depend on an external broker.
+//! It supports subscriptions with wildcard filters, retained messages and the QoS 1 and 2 handshakes,
+//! but keeps no sessions and ignores authentication.
+
+use crate::builder::MqttClientBuilder;
+use crate::mqtt_client::MqttClient;
+use bytes::{Bytes, BytesMut};
+use rumqttc::v5::mqttbytes::v5::{
+    ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, PubComp, PubRec, PubRel, Publish,
+    PublishProperties, SubAck, SubscribeReasonCode, UnsubAck, UnsubAckReason,
+};
+use rumqttc::v5::mqttbytes::{matches, Error, QoS};
+use rumqttc::v5::EventLoop;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+/// A broker listening on a random local port, until it is dropped
+pub struct TestBroker {
+    addr: SocketAddr,
+    state: Arc<BrokerState>,
+    listener: JoinHandle<()>,
+}
+
+/// A connected client
+struct Connection {
+    client_id: String,
+    filters: Vec<(String, QoS)>,
+    packets: mpsc::UnboundedSender<Packet>,
+    next_pkid: u16,
+}
+
+impl Connection {
+    /// Sends a message to the client if one of its filters matches the topic.
+    /// The message is delivered with the lowest of the published and subscribed QoS.
+    fn deliver(&mut self, topic: &str, publish: &Publish, retain: bool) {
+        let Some(qos) = self
+            .filters
+            .iter()
+            .filter(|(filter, _)| matches(topic, filter))
+            .map(|(_, qos)| *qos)
+            .max_by_key(|qos| *qos as u8)
+        else {
+            return;
+        };
+        let qos = std::cmp::min_by_key(qos, publish.qos, |qos| *qos as u8);
+        let pkid = if qos == QoS::AtMostOnce {
+            0
+        } else {
+            self.next_pkid = self.next_pkid.checked_add(1).unwrap_or(1);
+            self.next_pkid
+        };
+        let _ = self.packets.send(Packet::Publish(Publish {
+            dup: false,
+            qos,
+            retain,
+            topic: publish.topic.clone(),
+            pkid,
+            payload: publish.payload.clone(),
+            properties: publish.properties.clone(),
+        }));
+    }
+}
+
+/// The connections, observers and retained messages of a broker
+#[derive(Default)]
+struct BrokerState {
+    inner: Mutex<Routing>,
+    /// Notified when a client subscribes
+    subscribed: Notify,
+}
+
+#[derive(Default)]
+struct Routing {
+    connections: HashMap<u64, Connection>,
+    observers: Vec<(String, mpsc::UnboundedSender<Publish>)>,
+    retained: HashMap<String, Publish>,
+    next_connection: u64,
+}
+
+impl BrokerState {
+    /// Routes a message to the subscribed clients and observers, and stores it if it is retained
+    fn route(&self, publish: Publish) {
+        let topic = String::from_utf8_lossy(&publish.topic).into_owned();
+        let mut routing = self.inner.lock().unwrap();
+        routing.observers.retain(|(filter, observer)| {
+            !matches(&topic, filter) || observer.send(publish.clone()).is_ok()
+        });
+        for connection in routing.connections.values_mut() {
+            connection.deliver(&topic, &publish, false);
+        }
+        if publish.retain {
+            if publish.payload.is_empty() {
+                routing.retained.remove(&topic);
+            } else {
+                routing.retained.insert(topic, publish);
+            }
+        }
+    }
+
+    /// Whether a client is subscribed with the given filter
+    fn is_subscribed(&self, filter: &str, min_qos: QoS) -> bool {
+        let routing = self.inner.lock().unwrap();
+        routing.connections.values().any(|connection| {
+            connection
+                .filters
+                .iter()
+                .any(|(f, qos)| f == filter && *qos >= min_qos)
+        })
+    }
+}
+
+impl TestBroker {
+    /// Starts a broker on a random local port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind the test broker");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(BrokerState::default());
+
+        let accept_state = state.clone();
+        let listener = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accept_state.clone()));
+            }
+        });
+
+        TestBroker {
+            addr,
+            state,
+            listener,
+        }
+    }
+
+    /// The port the broker listens on
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Creates a client connecting to the broker
+    pub fn client(&self, client_id: &str) -> (MqttClient, EventLoop) {
+        self.builder(client_id).build()
+    }
+
+    /// Creates a builder for a client connecting to the broker
+    pub fn builder(&self, client_id: &str) -> MqttClientBuilder {
+        MqttClientBuilder::new(client_id, "127.0.0.1", self.port())
+    }
+
+    /// Publishes a payload on a topic, as if it was sent by another client
+    pub fn publish<T: Into<String>, P: Into<Bytes>>(&self, topic: T, payload: P) {
+        self.state
+            .route(Publish::new(topic, QoS::AtLeastOnce, payload, None));
+    }
+
+    /// Receives the messages published on the topics matching a filter
+    pub fn subscribe(&self, filter: &str) -> mpsc::UnboundedReceiver<Publish> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut routing = self.state.inner.lock().unwrap();
+        for (topic, publish) in &routing.retained {
+            if matches(topic, filter) {
+                let _ = tx.send(publish.clone());
+            }
+        }
+        routing.observers.push((filter.to_owned(), tx));
+        rx
+    }
+
+    /// Waits until a client subscribes with the given filter.
+    /// Registered callbacks only subscribe once their client's event loop is polled.
+    pub async fn wait_for_subscription(&self, filter: &str) {
+        self.wait_for_subscription_with_qos(filter, QoS::AtMostOnce)
+            .await
+    }
+
+    /// Waits until a client subscribes with the given filter, with at least the given QoS
+    pub async fn wait_for_subscription_with_qos(&self, filter: &str, qos: QoS) {
+        let wait = async {
+            loop {
+                let subscribed = self.state.subscribed.notified();
+                if self.state.is_subscribed(filter, qos) {
+                    return;
+                }
+                subscribed.await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or_else(|_| panic!("No client subscribed to '{}' with {:?}", filter, qos));
+    }
+
+    /// The ids of the connected clients
+    pub fn clients(&self) -> Vec<String> {
+        let routing = self.state.inner.lock().unwrap();
+        routing
+            .connections
+            .values()
+            .map(|connection| connection.client_id.clone())
+            .collect()
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// Handles the packets of a client until it disconnects
+async fn serve(stream: TcpStream, state: Arc<BrokerState>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (packets, mut outgoing) = mpsc::unbounded_channel::<Packet>();
+
+    let writes = tokio::spawn(async move {
+        let mut buffer = BytesMut::new();
+        while let Some(packet) = outgoing.recv().await {
+            buffer.clear();
+            if packet.write(&mut buffer).is_err() || writer.write_all(&buffer).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut connection_id = None;
+    let mut buffer = BytesMut::with_capacity(4096);
+    'connection: loop {
+        match reader.read_buf(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        loop {
+            let packet = match Packet::read(&mut buffer, None) {
+                Ok(packet) => packet,
+                Err(Error::InsufficientBytes(_)) => break,
+                Err(e) => {
+                    log::warn!("Test broker received a malformed packet: {:?}", e);
+                    break 'connection;
+                }
+            };
+            if !handle(packet, &state, &packets, &mut connection_id) {
+                break 'connection;
+            }
+        }
+    }
+
+    if let Some(id) = connection_id {
+        state.inner.lock().unwrap().connections.remove(&id);
+    }
+    drop(packets);
+    let _ = writes.await;
+}
+
+/// Handles a packet from a client, returning false when the client disconnects
+fn handle(
+    packet: Packet,
+    state: &BrokerState,
+    packets: &mpsc::UnboundedSender<Packet>,
+    connection_id: &mut Option<u64>,
+) -> bool {
+    match packet {
+        Packet::Connect(connect, _, _) => {
+            let mut routing = state.inner.lock().unwrap();
+            let id = routing.next_connection;
+            routing.next_connection += 1;
+            routing.connections.insert(
+                id,
+                Connection {
+                    client_id: connect.client_id,
+                    filters: Vec::new(),
+                    packets: packets.clone(),
+                    next_pkid: 0,
+                },
+            );
+            *connection_id = Some(id);
+            let _ = packets.send(Packet::ConnAck(ConnAck {
+                session_present: false,
+                code: ConnectReturnCode::Success,
+                properties: None,
+            }));
+        }
+        Packet::Publish(mut publish) => {
+            match publish.qos {
+                QoS::AtMostOnce => {}
+                QoS::AtLeastOnce => {
+                    let _ = packets.send(Packet::PubAck(PubAck::new(publish.pkid, None)));
+                }
+                QoS::ExactlyOnce => {
+                    let _ = packets.send(Packet::PubRec(PubRec::new(publish.pkid, None)));
+                }
+            }
+            // Topic aliases and subscription identifiers only apply to a single connection
+            publish.properties = publish.properties.map(|properties| PublishProperties {
+                topic_alias: None,
+                subscription_identifiers: Vec::new(),
+                ..properties
+            });
+            state.route(publish);
+        }
+        Packet::PubRel(pubrel) => {
+            let _ = packets.send(Packet::PubComp(PubComp::new(pubrel.pkid, None)));
+        }
+        Packet::PubRec(pubrec) => {
+            let _ = packets.send(Packet::PubRel(PubRel::new(pubrec.pkid, None)));
+        }
+        Packet::Subscribe(subscribe) => {
+            let Some(id) = *connection_id else {
+                return false;
+            };
+            let mut routing = state.inner.lock().unwrap();
+            let Routing {
+                connections,
+                retained,
+                ..
+            } = &mut *routing;
+            let Some(connection) = connections.get_mut(&id) else {
+                return false;
+            };
+            let mut return_codes = Vec::new();
+            for filter in subscribe.filters {
+                connection.filters.retain(|(path, _)| *path != filter.path);
+                connection.filters.push((filter.path, filter.qos));
+                return_codes.push(SubscribeReasonCode::Success(filter.qos));
+            }
+            let _ = packets.send(Packet::SubAck(SubAck {
+                pkid: subscribe.pkid,
+                return_codes,
+                properties: None,
+            }));
+            for (topic, publish) in retained.iter() {
+                connection.deliver(topic, publish, true);
+            }
+            drop(routing);
+            state.subscribed.notify_waiters();
+        }
+        Packet::Unsubscribe(unsubscribe) => {
+            let mut routing = state.inner.lock().unwrap();
+            if let Some(connection) = connection_id.and_then(|id| routing.connections.get_mut(&id))
+            {
+                connection
+                    .filters
+                    .retain(|(path, _)| !unsubscribe.filters.contains(path));
+            }
+            let _ = packets.send(Packet::UnsubAck(UnsubAck {
+                pkid: unsubscribe.pkid,
+                reasons: vec![UnsubAckReason::Success; unsubscribe.filters.len()],
+                properties: None,
+            }));
+        }
+        Packet::PingReq(_) => {
+            let _ = packets.send(Packet::PingResp(PingResp));
+        }
+        Packet::Disconnect(_) => return false,
+        _ => {}
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn routes_messages_between_clients() {
+        let broker = TestBroker::start().await;
+        let (client, event_loop) = broker.client("publisher");
+        tokio::spawn(client.clone().start(event_loop));
+
+        let mut messages = broker.subscribe("sensors/+/temperature");
+        client.publish("sensors/kitchen/temperature", 21.5).await;
+        client.publish("sensors/kitchen/humidity", 40).await;
+
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.topic, "sensors/kitchen/temperature");
+        assert_eq!(message.payload, "21.5");
+        assert!(messages.try_recv().is_err());
+        assert_eq!(broker.clients(), vec!["publisher".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn delivers_retained_messages_to_new_subscribers() {
+        let broker = TestBroker::start().await;
+        let (client, event_loop) = broker.client("retainer");
+        tokio::spawn(client.clone().start(event_loop));
+
+        let mut published = broker.subscribe("status");
+        let options = crate::PublishOptions {
+            qos: QoS::ExactlyOnce,
+            retain: true,
+        };
+        client.publish_with("status", "online", options).await;
+        published.recv().await.unwrap();
+
+        let mut late = broker.subscribe("status");
+        let retained = late.recv().await.unwrap();
+        assert_eq!(retained.payload, "\"online\"");
+        assert!(retained.retain);
+    }
+}
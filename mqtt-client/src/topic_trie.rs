@@ -0,0 +1,250 @@
+use crate::dead_letter::DLQ_LEVEL;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A TopicTrie stores values by MQTT topic filter, and finds the values whose filter matches a topic.
+/// Filters can use the `+` (single level) and `#` (multi level) wildcards.
+/// Like topics starting with `$`, the `$dlq` levels of dead letter topics are only matched by filters naming them.
+/// A lookup only walks the levels of the topic, so it doesn't depend on the number of stored filters.
+/// The nodes are shared between clones, and an update only copies the nodes on the path of its filter.
+#[derive(Clone)]
+pub struct TopicTrie<V> {
+    root: Node<V>,
+}
+
+#[derive(Clone)]
+struct Node<V> {
+    children: HashMap<String, Arc<Node<V>>>,
+    values: Vec<V>,
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Node {
+            children: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<V: Clone> Node<V> {
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.children.is_empty()
+    }
+
+    /// Removes the values matching the predicate at the given filter levels, pruning empty nodes.
+    /// Returns true if values remained before the removal and none remain after it.
+    fn remove<F>(&mut self, levels: &[&str], predicate: &mut F) -> bool
+    where
+        F: FnMut(&V) -> bool,
+    {
+        match levels.split_first() {
+            None => {
+                let had_values = !self.values.is_empty();
+                self.values.retain(|value| !predicate(value));
+                had_values && self.values.is_empty()
+            }
+            Some((level, rest)) => {
+                let Some(child) = self.children.get_mut(*level) else {
+                    return false;
+                };
+                let child = Arc::make_mut(child);
+                let emptied = child.remove(rest, predicate);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                emptied
+            }
+        }
+    }
+
+    /// Collects the values of the filters matching the given topic levels
+    fn collect<'a>(&'a self, levels: &[&str], first_level: bool, matches: &mut Vec<&'a V>) {
+        // Topics starting with '$' are not matched by wildcards at the first level
+        let wildcards = !(first_level && levels.first().is_some_and(|l| l.starts_with('$')));
+
+        // '#' also matches the parent level, e.g. 'a/#' matches 'a', but never a dead letter level
+        if wildcards && !levels.contains(&DLQ_LEVEL) {
+            if let Some(multi) = self.children.get("#") {
+                matches.extend(multi.values.iter());
+            }
+        }
+
+        match levels.split_first() {
+            None => matches.extend(self.values.iter()),
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect(rest, false, matches);
+                }
+                if wildcards && *level != DLQ_LEVEL {
+                    if let Some(single) = self.children.get("+") {
+                        single.collect(rest, false, matches);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<V: Clone> TopicTrie<V> {
+    /// Creates an empty trie
+    pub fn new() -> Self {
+        TopicTrie {
+            root: Node::default(),
+        }
+    }
+
+    /// Adds a value for a topic filter, next to any existing values for the same filter
+    pub fn insert(&mut self, filter: &str, value: V) {
+        let node = filter.split('/').fold(&mut self.root, |node, level| {
+            Arc::make_mut(node.children.entry(level.to_owned()).or_default())
+        });
+        node.values.push(value);
+    }
+
+    /// Removes the values of a topic filter matching the predicate.
+    /// Returns true if the filter has no values left after the removal.
+    pub fn remove<F>(&mut self, filter: &str, mut predicate: F) -> bool
+    where
+        F: FnMut(&V) -> bool,
+    {
+        let levels: Vec<&str> = filter.split('/').collect();
+        self.root.remove(&levels, &mut predicate)
+    }
+
+    /// Returns the values stored for exactly this topic filter
+    pub fn get(&self, filter: &str) -> &[V] {
+        filter
+            .split('/')
+            .try_fold(&self.root, |node, level| {
+                node.children.get(level).map(Arc::as_ref)
+            })
+            .map(|node| node.values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns true if values are stored for exactly this topic filter
+    pub fn contains(&self, filter: &str) -> bool {
+        !self.get(filter).is_empty()
+    }
+
+    /// Returns the values of every filter matching a concrete topic
+    pub fn matches(&self, topic: &str) -> Vec<&V> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut matches = Vec::new();
+        self.root.collect(&levels, true, &mut matches);
+        matches
+    }
+}
+
+impl<V: Clone> Default for TopicTrie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matching(trie: &TopicTrie<&'static str>, topic: &str) -> Vec<&'static str> {
+        let mut matches: Vec<&str> = trie.matches(topic).into_iter().copied().collect();
+        matches.sort();
+        matches
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_filters() {
+        let mut trie = TopicTrie::new();
+        trie.insert("app_1/sensor/temperature", "exact");
+        trie.insert("app_1/sensor/+", "single");
+        trie.insert("app_1/+/temperature", "single_middle");
+        trie.insert("app_1/sensor/#", "multi");
+        trie.insert("#", "all");
+        trie.insert("app_2/#", "other");
+
+        assert_eq!(
+            matching(&trie, "app_1/sensor/temperature"),
+            vec!["all", "exact", "multi", "single", "single_middle"]
+        );
+        assert_eq!(
+            matching(&trie, "app_1/sensor/humidity"),
+            vec!["all", "multi", "single"]
+        );
+        assert_eq!(matching(&trie, "app_1/sensor"), vec!["all", "multi"]);
+        assert_eq!(
+            matching(&trie, "app_1/sensor/temperature/raw"),
+            vec!["all", "multi"]
+        );
+        assert_eq!(matching(&trie, "app_3/status"), vec!["all"]);
+    }
+
+    #[test]
+    fn wildcards_skip_system_topics() {
+        let mut trie = TopicTrie::new();
+        trie.insert("#", "all");
+        trie.insert("+/broker/uptime", "single");
+        trie.insert("$SYS/#", "system");
+
+        assert_eq!(matching(&trie, "$SYS/broker/uptime"), vec!["system"]);
+    }
+
+    #[test]
+    fn wildcards_skip_dead_letter_levels() {
+        let mut trie = TopicTrie::new();
+        trie.insert("#", "all");
+        trie.insert("app_1/#", "multi");
+        trie.insert("app_1/x/+", "single");
+        trie.insert("app_1/x/$dlq", "dlq");
+        trie.insert("app_1/+/$dlq", "any_dlq");
+
+        assert_eq!(matching(&trie, "app_1/x/$dlq"), vec!["any_dlq", "dlq"]);
+        assert_eq!(matching(&trie, "app_1/x/$dlq/raw"), Vec::<&str>::new());
+        assert_eq!(matching(&trie, "app_1/x"), vec!["all", "multi"]);
+    }
+
+    #[test]
+    fn removes_values_and_prunes_filters() {
+        let mut trie = TopicTrie::new();
+        trie.insert("app_1/sensor/+", 1);
+        trie.insert("app_1/sensor/+", 2);
+
+        assert!(!trie.remove("app_1/sensor/+", |v| *v == 1));
+        assert_eq!(trie.get("app_1/sensor/+"), &[2]);
+        assert!(trie.remove("app_1/sensor/+", |v| *v == 2));
+        assert!(!trie.contains("app_1/sensor/+"));
+        assert!(trie.root.is_empty());
+        assert!(!trie.remove("app_1/sensor/+", |_| true));
+    }
+
+    #[test]
+    fn clones_are_not_affected_by_updates() {
+        let mut trie = TopicTrie::new();
+        trie.insert("app_1/sensor/+", "single");
+        let snapshot = trie.clone();
+
+        trie.insert("app_1/sensor/temperature", "exact");
+        trie.remove("app_1/sensor/+", |_| true);
+
+        assert_eq!(
+            matching(&snapshot, "app_1/sensor/temperature"),
+            vec!["single"]
+        );
+        assert_eq!(matching(&trie, "app_1/sensor/temperature"), vec!["exact"]);
+    }
+
+    #[test]
+    fn handles_thousands_of_filters() {
+        let mut trie = TopicTrie::new();
+        for device in 0..5000 {
+            trie.insert(&format!("devices/{}/telemetry", device), device);
+        }
+        trie.insert("devices/+/telemetry", -1);
+
+        let matches = trie.matches("devices/4242/telemetry");
+        let mut matches: Vec<i32> = matches.into_iter().copied().collect();
+        matches.sort();
+        assert_eq!(matches, vec![-1, 4242]);
+        assert!(trie.matches("devices/4242/status").is_empty());
+    }
+}